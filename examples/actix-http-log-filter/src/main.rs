@@ -1,5 +1,4 @@
 use actix_web::{get, web, App, HttpRequest, HttpResponse, HttpServer, Responder};
-use opentelemetry::global;
 use std::io;
 use tembo_telemetry::{TelemetryConfig, TelemetryInit};
 use tracing::*;
@@ -29,20 +28,21 @@ async fn main() -> io::Result<()> {
             env: std::env::var("ENV").unwrap_or_else(|_| "development".to_string()),
             endpoint_url: Some(otlp_endpoint),
             tracer_id: Some(TRACER_NAME.to_string()),
+            ..Default::default()
         };
         println!("{:?}", tc);
-        let _telemetry = TelemetryInit::init(&tc).await;
         tc
     } else {
-        let tc = TelemetryConfig {
-            app_name: std::env::var("ENV").unwrap_or_else(|_| "development".to_string()),
+        TelemetryConfig {
+            app_name: std::env::var("CARGO_BIN_NAME").unwrap_or_else(|_| "basic".to_string()),
             env: std::env::var("ENV").unwrap_or_else(|_| "development".to_string()),
             endpoint_url: None,
             tracer_id: Some(TRACER_NAME.to_string()),
-        };
-        let _telemetry = TelemetryInit::init(&tc).await;
-        tc
+            ..Default::default()
+        }
     };
+    // Flushes and shuts down telemetry when dropped at the end of `main`
+    let _telemetry = TelemetryInit::init(&telemetry_config).await;
 
     let server_bind_address = "0.0.0.0:3001".to_string();
     let server = HttpServer::new({
@@ -69,7 +69,5 @@ async fn main() -> io::Result<()> {
     );
     server.await?;
 
-    global::shutdown_tracer_provider();
-
     Ok(())
 }
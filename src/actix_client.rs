@@ -0,0 +1,117 @@
+//! Helpers tracing outbound requests sent with actix's `awc` client.
+//!
+//! `awc` has no middleware support, so requests are traced by creating a client span before
+//! sending them and recording the response on it afterwards. `awc` uses the same header, method
+//! and status types as actix-web, so the helpers take them directly from the `ClientRequest`:
+//!
+//! ```rust,ignore
+//! use tembo_telemetry::actix_client::{record_response, request_span};
+//! use tracing::Instrument;
+//!
+//! let mut request = awc::Client::new().get("http://billing.svc/invoices");
+//! let span = request_span(request.get_method(), request.get_uri(), request.headers_mut());
+//! let response = request.send().instrument(span.clone()).await;
+//! record_response(&span, response.as_ref().map(|response| response.status()));
+//! drop(span);
+//! ```
+
+use crate::propagation::{self, HeaderInjector};
+use actix_web::http::{header::HeaderMap, Method, StatusCode, Uri};
+use tracing::{field::Empty, Span};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+/// Creates a client span for an outbound request and injects its context into `headers`.
+///
+/// Like the spans of `ClientTracingLayer`, the span is named after the request method, has the
+/// `client` kind and records `http.method` and `http.url`. The span context is injected with
+/// the global propagator, so the called service continues the trace. Instrument the future
+/// sending the request with the span, and pass the outcome to [`record_response`].
+///
+/// # Arguments
+///
+/// * `method` - The method of the request, e.g. from `ClientRequest::get_method`.
+/// * `uri` - The URI of the request, e.g. from `ClientRequest::get_uri`.
+/// * `headers` - The request headers, e.g. from `ClientRequest::headers_mut`.
+pub fn request_span(method: &Method, uri: &Uri, headers: &mut HeaderMap) -> Span {
+    let span = tracing::info_span!(
+        "HTTP request",
+        otel.name = %method,
+        otel.kind = "client",
+        otel.status_code = Empty,
+        http.method = %method,
+        http.url = %uri,
+        http.status_code = Empty,
+    );
+    propagation::inject_context(&span.context(), &mut HeaderInjector(headers));
+    span
+}
+
+/// Records the status of the response, or the failure of the request, on a client span.
+///
+/// Responses with a status of 400 or above, and requests that failed to send, mark the span as
+/// failed.
+///
+/// # Arguments
+///
+/// * `span` - The span returned by [`request_span`].
+/// * `outcome` - The response status, or the error returned when sending the request.
+pub fn record_response<E>(span: &Span, outcome: Result<StatusCode, E>) {
+    record_outcome(span, outcome.map(|status| status.as_u16()));
+}
+
+/// Records the status code of a response, or the failure of a request, on a client span.
+pub(crate) fn record_outcome<E>(span: &Span, outcome: Result<u16, E>) {
+    match outcome {
+        Ok(status) => {
+            span.record("http.status_code", i64::from(status));
+            if status >= 400 {
+                span.record("otel.status_code", "ERROR");
+            }
+        }
+        Err(_) => {
+            span.record("otel.status_code", "ERROR");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processors::tests::{attribute, collecting_subscriber};
+    use opentelemetry::{
+        trace::{SpanKind, Status, TraceContextExt},
+        Value,
+    };
+    use opentelemetry_sdk::propagation::TraceContextPropagator;
+
+    #[test]
+    fn test_request_span_and_propagation() {
+        opentelemetry::global::set_text_map_propagator(TraceContextPropagator::new());
+        let (collector, subscriber) = collecting_subscriber();
+
+        tracing::subscriber::with_default(subscriber, || {
+            let mut headers = HeaderMap::new();
+            let uri = Uri::from_static("http://billing.svc/invoices?page=2");
+            let span = request_span(&Method::POST, &uri, &mut headers);
+            let trace_id = span.context().span().span_context().trace_id();
+            let traceparent = headers.get("traceparent").unwrap().to_str().unwrap();
+            assert!(traceparent.contains(&trace_id.to_string()));
+            record_response::<()>(&span, Ok(StatusCode::NOT_FOUND));
+        });
+
+        let spans = collector.0.lock().unwrap();
+        assert_eq!(spans.len(), 1);
+        let span = &spans[0];
+        assert_eq!(span.name, "POST");
+        assert_eq!(span.span_kind, SpanKind::Client);
+        assert!(matches!(span.status, Status::Error { .. }));
+        assert_eq!(
+            attribute(&span.attributes, "http.url"),
+            Some(Value::from("http://billing.svc/invoices?page=2"))
+        );
+        assert_eq!(
+            attribute(&span.attributes, "http.status_code"),
+            Some(Value::I64(404))
+        );
+    }
+}
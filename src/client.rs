@@ -0,0 +1,157 @@
+//! Tower layer tracing outbound HTTP requests.
+
+use crate::{actix_client::record_outcome, propagation};
+use http::{HeaderMap, HeaderName, HeaderValue, Request, Response};
+use opentelemetry::propagation::Injector;
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tower::{Layer, Service};
+use tracing::{field::Empty, Instrument, Span};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+/// Tower layer creating a client span for each outbound request and propagating its context.
+///
+/// The span follows the OpenTelemetry HTTP client conventions: it is named after the request
+/// method, has the `client` kind and records `http.method`, `http.url` and
+/// `http.status_code`. Responses with a status of 400 or above, and errors returned by the
+/// inner service, mark the span as failed. The span context is injected into the request
+/// headers with the global propagator, so the called service continues the trace.
+///
+/// ```rust,ignore
+/// let client = tower::ServiceBuilder::new()
+///     .layer(ClientTracingLayer)
+///     .service(hyper::Client::new());
+/// ```
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ClientTracingLayer;
+
+impl<S> Layer<S> for ClientTracingLayer {
+    type Service = ClientTracing<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        ClientTracing { inner }
+    }
+}
+
+/// Service created by [`ClientTracingLayer`].
+#[derive(Clone, Debug)]
+pub struct ClientTracing<S> {
+    inner: S,
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for ClientTracing<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<S::Response, S::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: Request<ReqBody>) -> Self::Future {
+        let span = tracing::info_span!(
+            "HTTP request",
+            otel.name = %request.method(),
+            otel.kind = "client",
+            otel.status_code = Empty,
+            http.method = %request.method(),
+            http.url = %request.uri(),
+            http.status_code = Empty,
+        );
+        propagation::inject_context(
+            &span.context(),
+            &mut HttpHeaderInjector(request.headers_mut()),
+        );
+
+        let future = span.in_scope(|| self.inner.call(request));
+        Box::pin(
+            async move {
+                let result = future.await;
+                record_outcome(
+                    &Span::current(),
+                    result.as_ref().map(|response| response.status().as_u16()),
+                );
+                result
+            }
+            .instrument(span),
+        )
+    }
+}
+
+/// Injector writing propagation headers into an `http` `HeaderMap`.
+struct HttpHeaderInjector<'a>(&'a mut HeaderMap);
+
+impl Injector for HttpHeaderInjector<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        if let (Ok(name), Ok(value)) = (
+            HeaderName::from_bytes(key.as_bytes()),
+            HeaderValue::from_str(&value),
+        ) {
+            self.0.insert(name, value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processors::tests::{attribute, collecting_subscriber};
+    use opentelemetry::{
+        trace::{SpanKind, Status, TraceContextExt},
+        Value,
+    };
+    use opentelemetry_sdk::propagation::TraceContextPropagator;
+    use std::convert::Infallible;
+    use tower::{ServiceBuilder, ServiceExt};
+    use tracing::instrument::WithSubscriber;
+
+    #[tokio::test]
+    async fn test_client_span_and_propagation() {
+        opentelemetry::global::set_text_map_propagator(TraceContextPropagator::new());
+        let (collector, subscriber) = collecting_subscriber();
+
+        let client = ServiceBuilder::new().layer(ClientTracingLayer).service_fn(
+            |request: Request<()>| async move {
+                let traceparent = request.headers()["traceparent"].to_str().unwrap();
+                let trace_id = Span::current().context().span().span_context().trace_id();
+                assert!(traceparent.contains(&trace_id.to_string()));
+                Ok::<_, Infallible>(Response::builder().status(503).body(()).unwrap())
+            },
+        );
+        let request = Request::get("http://billing.svc/invoices?page=2")
+            .body(())
+            .unwrap();
+        let response = client
+            .oneshot(request)
+            .with_subscriber(subscriber)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 503);
+
+        let spans = collector.0.lock().unwrap();
+        assert_eq!(spans.len(), 1);
+        let span = &spans[0];
+        assert_eq!(span.name, "GET");
+        assert_eq!(span.span_kind, SpanKind::Client);
+        assert!(matches!(span.status, Status::Error { .. }));
+        assert_eq!(
+            attribute(&span.attributes, "http.method"),
+            Some(Value::from("GET"))
+        );
+        assert_eq!(
+            attribute(&span.attributes, "http.url"),
+            Some(Value::from("http://billing.svc/invoices?page=2"))
+        );
+        assert_eq!(
+            attribute(&span.attributes, "http.status_code"),
+            Some(Value::I64(503))
+        );
+    }
+}
@@ -0,0 +1,113 @@
+//! Serde support for durations written as strings with a unit, e.g. `"250ms"` or `"5s"`.
+
+use serde::{de::Error, Deserialize, Deserializer, Serializer};
+use std::time::Duration;
+
+/// Serializes `duration` in the coarsest of `s`, `ms`, `us` and `ns` that represents it exactly.
+pub(crate) fn serialize<S: Serializer>(
+    duration: &Duration,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    let nanos = duration.subsec_nanos();
+    if nanos == 0 {
+        serializer.collect_str(&format_args!("{}s", duration.as_secs()))
+    } else if nanos % 1_000_000 == 0 {
+        serializer.collect_str(&format_args!("{}ms", duration.as_millis()))
+    } else if nanos % 1_000 == 0 {
+        serializer.collect_str(&format_args!("{}us", duration.as_micros()))
+    } else {
+        serializer.collect_str(&format_args!("{}ns", duration.as_nanos()))
+    }
+}
+
+/// Deserializes a duration from an integer followed by `ns`, `us`, `ms`, `s`, `m` or `h`.
+pub(crate) fn deserialize<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Duration, D::Error> {
+    let value = String::deserialize(deserializer)?;
+    parse(&value).ok_or_else(|| {
+        D::Error::invalid_value(
+            serde::de::Unexpected::Str(&value),
+            &"a duration such as \"500ns\", \"100us\", \"250ms\", \"5s\", \"1m\" or \"1h\"",
+        )
+    })
+}
+
+/// Parses a duration such as `"250ms"` or `"5s"`.
+fn parse(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    let split = value.find(|c: char| !c.is_ascii_digit())?;
+    let (amount, unit) = value.split_at(split);
+    let amount: u64 = amount.parse().ok()?;
+    match unit.trim() {
+        "ns" => Some(Duration::from_nanos(amount)),
+        "us" => Some(Duration::from_micros(amount)),
+        "ms" => Some(Duration::from_millis(amount)),
+        "s" => Some(Duration::from_secs(amount)),
+        "m" => Some(Duration::from_secs(amount.checked_mul(60)?)),
+        "h" => Some(Duration::from_secs(amount.checked_mul(3600)?)),
+        _ => None,
+    }
+}
+
+/// Serde support for optional durations, written as `null` or omitted when `None`.
+pub(crate) mod option {
+    use super::*;
+
+    pub(crate) fn serialize<S: Serializer>(
+        duration: &Option<Duration>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match duration {
+            Some(duration) => super::serialize(duration, serializer),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub(crate) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Duration>, D::Error> {
+        #[derive(Deserialize)]
+        struct Wrapper(#[serde(with = "super")] Duration);
+
+        Ok(Option::<Wrapper>::deserialize(deserializer)?.map(|Wrapper(duration)| duration))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse("250ms"), Some(Duration::from_millis(250)));
+        assert_eq!(parse("5s"), Some(Duration::from_secs(5)));
+        assert_eq!(parse("2m"), Some(Duration::from_secs(120)));
+        assert_eq!(parse("1h"), Some(Duration::from_secs(3600)));
+        assert_eq!(parse("5"), None);
+        assert_eq!(parse("1.5s"), None);
+        assert_eq!(parse("5 days"), None);
+        assert_eq!(parse("1500us"), Some(Duration::from_micros(1500)));
+        assert_eq!(parse("42ns"), Some(Duration::from_nanos(42)));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        #[derive(Debug, PartialEq, serde::Serialize, Deserialize)]
+        struct Config(#[serde(with = "super")] Duration);
+
+        let cases = [
+            (Duration::from_secs(5), "\"5s\""),
+            (Duration::from_millis(1_250), "\"1250ms\""),
+            (Duration::from_micros(2_500), "\"2500us\""),
+            (Duration::new(1, 5), "\"1000000005ns\""),
+        ];
+        for (duration, json) in cases {
+            assert_eq!(serde_json::to_string(&Config(duration)).unwrap(), json);
+            assert_eq!(
+                serde_json::from_str::<Config>(json).unwrap(),
+                Config(duration)
+            );
+        }
+    }
+}
@@ -0,0 +1,122 @@
+//! Errors returned while building the telemetry pipeline.
+
+use opentelemetry::trace::TraceError;
+use std::{fmt, io, path::PathBuf};
+
+/// Error returned when the OTLP pipeline cannot be built.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum TelemetryError {
+    /// No `endpoint_url` is configured, so there is nothing to export to.
+    MissingEndpoint,
+    /// An endpoint URL is malformed or uses a transport unsupported on this platform.
+    InvalidEndpoint {
+        /// The rejected endpoint URL.
+        url: String,
+        /// Why the endpoint was rejected.
+        reason: String,
+    },
+    /// The OTLP exporter could not be built.
+    Exporter(TraceError),
+    /// [`TelemetryConfig::init_blocking`](crate::TelemetryConfig::init_blocking) was called
+    /// outside of a Tokio runtime while an OTLP endpoint is configured.
+    MissingRuntime,
+    /// The service name, `service_name` or else `app_name`, looks misconfigured and `strict`
+    /// is enabled.
+    InvalidAppName {
+        /// The rejected service name.
+        app_name: String,
+        /// Why the name was rejected.
+        reason: &'static str,
+    },
+    /// The syslog socket could not be connected.
+    Syslog {
+        /// Path of the syslog socket.
+        path: PathBuf,
+        /// Why connecting failed.
+        source: io::Error,
+    },
+    /// Log filter directives could not be parsed.
+    InvalidFilter {
+        /// The rejected directives.
+        directives: String,
+        /// Why parsing failed.
+        reason: String,
+    },
+    /// A configured OTLP header cannot be sent and `strict` is enabled.
+    InvalidHeader {
+        /// Name of the rejected header.
+        name: String,
+        /// Why the header was rejected.
+        reason: &'static str,
+    },
+    /// An OTLP endpoint did not accept a connection at startup and `strict` is enabled.
+    UnreachableEndpoint {
+        /// The unreachable endpoint URL, with credentials redacted.
+        url: String,
+        /// Why connecting failed.
+        source: io::Error,
+    },
+}
+
+impl TelemetryError {
+    /// Creates a [`TelemetryError::InvalidEndpoint`] error for `url`.
+    #[cfg_attr(not(feature = "otlp"), allow(dead_code))]
+    pub(crate) fn invalid_endpoint(url: &str, reason: impl fmt::Display) -> Self {
+        TelemetryError::InvalidEndpoint {
+            url: url.to_string(),
+            reason: reason.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for TelemetryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TelemetryError::MissingEndpoint => f.write_str("no OTLP endpoint configured"),
+            TelemetryError::InvalidEndpoint { url, reason } => {
+                write!(f, "invalid OTLP endpoint `{url}`: {reason}")
+            }
+            TelemetryError::Exporter(err) => write!(f, "failed to build the OTLP exporter: {err}"),
+            TelemetryError::MissingRuntime => {
+                f.write_str("exporting to an OTLP endpoint requires a Tokio runtime")
+            }
+            TelemetryError::InvalidAppName { app_name, reason } => {
+                write!(f, "invalid app_name `{app_name}`: {reason}")
+            }
+            TelemetryError::Syslog { path, source } => {
+                write!(
+                    f,
+                    "failed to connect to syslog at `{}`: {source}",
+                    path.display()
+                )
+            }
+            TelemetryError::InvalidFilter { directives, reason } => {
+                write!(f, "invalid log filter `{directives}`: {reason}")
+            }
+            TelemetryError::InvalidHeader { name, reason } => {
+                write!(f, "invalid OTLP header `{name}`: {reason}")
+            }
+            TelemetryError::UnreachableEndpoint { url, source } => {
+                write!(f, "OTLP endpoint `{url}` is unreachable: {source}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for TelemetryError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            TelemetryError::Exporter(err) => Some(err),
+            TelemetryError::Syslog { source, .. } => Some(source),
+            TelemetryError::UnreachableEndpoint { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<TraceError> for TelemetryError {
+    fn from(err: TraceError) -> Self {
+        TelemetryError::Exporter(err)
+    }
+}
@@ -0,0 +1,469 @@
+//! Log formatters used by the stdout layer.
+
+use crate::{propagation, LogTraceIds, TimestampFormat};
+use opentelemetry::trace::{SpanId, TraceContextExt, TraceId};
+use std::{
+    fmt,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tracing::{
+    field::{Field, Visit},
+    Event, Subscriber,
+};
+use tracing_opentelemetry::OtelData;
+use tracing_subscriber::{
+    fmt::{format::Writer, time::FormatTime, FmtContext, FormatEvent, FormatFields},
+    registry::LookupSpan,
+};
+
+/// Timer writing the current time in the configured [`TimestampFormat`].
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Timestamp(pub(crate) TimestampFormat);
+
+impl FormatTime for Timestamp {
+    fn format_time(&self, w: &mut Writer<'_>) -> fmt::Result {
+        let since_epoch = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        write_timestamp(w, self.0, since_epoch)
+    }
+}
+
+/// Writes the instant `since_epoch` after the Unix epoch in `format`.
+fn write_timestamp(
+    w: &mut impl fmt::Write,
+    format: TimestampFormat,
+    since_epoch: Duration,
+) -> fmt::Result {
+    if format == TimestampFormat::UnixMillis {
+        return write!(w, "{}", since_epoch.as_millis());
+    }
+
+    let secs = since_epoch.as_secs();
+    let (year, month, day) = civil_from_days((secs / 86_400) as i64);
+    let time = secs % 86_400;
+    write!(
+        w,
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.",
+        time / 3600,
+        time / 60 % 60,
+        time % 60
+    )?;
+    match format {
+        TimestampFormat::Rfc3339Nanos => write!(w, "{:09}Z", since_epoch.subsec_nanos()),
+        _ => write!(w, "{:06}Z", since_epoch.subsec_micros()),
+    }
+}
+
+/// Converts days since the Unix epoch to a proleptic Gregorian `(year, month, day)`.
+///
+/// Uses Howard Hinnant's `civil_from_days` algorithm.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// JSON event formatter adding the ids of the enclosing span.
+///
+/// The ids are read from the OpenTelemetry data attached to the span, so every event is
+/// correlated with its trace, including records bridged from the `log` crate. Depending on
+/// [`LogTraceIds`], they are written as hex `trace_id` and `span_id` fields, as Datadog's
+/// decimal `dd.trace_id` and `dd.span_id` fields, or both. Events outside of a span, or logged
+/// without the OpenTelemetry layer, are written unchanged.
+pub(crate) struct TraceContextJson<F>(pub(crate) F, pub(crate) LogTraceIds);
+
+impl<S, N, F> FormatEvent<S, N> for TraceContextJson<F>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+    F: FormatEvent<S, N>,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        let Some((trace_id, span_id)) = span_ids(ctx) else {
+            return self.0.format_event(ctx, writer, event);
+        };
+
+        let mut line = String::new();
+        self.0.format_event(ctx, Writer::new(&mut line), event)?;
+        let Some(rest) = line.strip_prefix('{') else {
+            return writer.write_str(&line);
+        };
+        writer.write_char('{')?;
+        if matches!(self.1, LogTraceIds::OpenTelemetry | LogTraceIds::Both) {
+            write!(
+                writer,
+                "\"trace_id\":\"{trace_id}\",\"span_id\":\"{span_id}\","
+            )?;
+        }
+        if matches!(self.1, LogTraceIds::Datadog | LogTraceIds::Both) {
+            let (dd_trace_id, dd_span_id) = propagation::datadog_ids(trace_id, span_id);
+            write!(
+                writer,
+                "\"dd.trace_id\":\"{dd_trace_id}\",\"dd.span_id\":\"{dd_span_id}\","
+            )?;
+        }
+        writer.write_str(rest)
+    }
+}
+
+/// logfmt event formatter, writing each event as a line of `key=value` pairs.
+///
+/// Lines start with `ts`, `level` and `target`, optionally `file` and `line`, then the trace ids
+/// selected by [`LogTraceIds`] when the event is inside a span, `msg` and the event fields in
+/// the order they were recorded. Span fields are not repeated on events.
+pub(crate) struct Logfmt {
+    timer: Timestamp,
+    trace_ids: LogTraceIds,
+    source_location: bool,
+}
+
+impl Logfmt {
+    pub(crate) fn new(timestamp_format: TimestampFormat, trace_ids: LogTraceIds) -> Self {
+        Self {
+            timer: Timestamp(timestamp_format),
+            trace_ids,
+            source_location: false,
+        }
+    }
+
+    /// Writes the `file` and `line` of the event after its `target` when `enabled`.
+    pub(crate) fn with_source_location(mut self, enabled: bool) -> Self {
+        self.source_location = enabled;
+        self
+    }
+}
+
+impl<S, N> FormatEvent<S, N> for Logfmt
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        let mut ts = String::new();
+        self.timer.format_time(&mut Writer::new(&mut ts))?;
+        let metadata = event.metadata();
+        let mut line = String::new();
+        write_pair(&mut line, "ts", &ts)?;
+        write_pair(
+            &mut line,
+            "level",
+            &metadata.level().as_str().to_ascii_lowercase(),
+        )?;
+        write_pair(&mut line, "target", metadata.target())?;
+        if self.source_location {
+            if let Some(file) = metadata.file() {
+                write_pair(&mut line, "file", file)?;
+            }
+            if let Some(number) = metadata.line() {
+                write_pair(&mut line, "line", &number.to_string())?;
+            }
+        }
+        if let Some((trace_id, span_id)) = span_ids(ctx) {
+            if matches!(
+                self.trace_ids,
+                LogTraceIds::OpenTelemetry | LogTraceIds::Both
+            ) {
+                write_pair(&mut line, "trace_id", &trace_id.to_string())?;
+                write_pair(&mut line, "span_id", &span_id.to_string())?;
+            }
+            if matches!(self.trace_ids, LogTraceIds::Datadog | LogTraceIds::Both) {
+                let (dd_trace_id, dd_span_id) = propagation::datadog_ids(trace_id, span_id);
+                write_pair(&mut line, "dd.trace_id", &dd_trace_id.to_string())?;
+                write_pair(&mut line, "dd.span_id", &dd_span_id.to_string())?;
+            }
+        }
+
+        let mut visitor = LogfmtVisitor::default();
+        event.record(&mut visitor);
+        if let Some(message) = &visitor.message {
+            write_pair(&mut line, "msg", message)?;
+        }
+        line.push_str(&visitor.fields);
+        writeln!(writer, "{}", line.trim_start())
+    }
+}
+
+/// Collects the message and the other fields of an event as logfmt pairs.
+#[derive(Default)]
+struct LogfmtVisitor {
+    message: Option<String>,
+    fields: String,
+}
+
+impl Visit for LogfmtVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message = Some(value.to_string());
+        } else {
+            let _ = write_pair(&mut self.fields, field.name(), value);
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.record_str(field, &format!("{value:?}"));
+    }
+}
+
+/// Writes ` key=value` to `out`, quoting and escaping the value when needed.
+pub(crate) fn write_pair(out: &mut String, key: &str, value: &str) -> fmt::Result {
+    use fmt::Write as _;
+
+    write!(out, " {key}=")?;
+    let needs_quotes = value.is_empty()
+        || value
+            .chars()
+            .any(|c| c == ' ' || c == '=' || c == '"' || c.is_control());
+    if !needs_quotes {
+        out.push_str(value);
+        return Ok(());
+    }
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => write!(out, "\\u{{{:04x}}}", c as u32)?,
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    Ok(())
+}
+
+/// Returns the trace and span ids of the span enclosing the current event.
+fn span_ids<S, N>(ctx: &FmtContext<'_, S, N>) -> Option<(TraceId, SpanId)>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    let span = ctx.event_scope()?.next()?;
+    let extensions = span.extensions();
+    let data = extensions.get::<OtelData>()?;
+    // Root spans carry their own trace id, child spans inherit it from their parent context.
+    let trace_id = data
+        .builder
+        .trace_id
+        .unwrap_or_else(|| data.parent_cx.span().span_context().trace_id());
+    let span_id = data.builder.span_id?;
+    if trace_id == TraceId::INVALID {
+        return None;
+    }
+    Some((trace_id, span_id))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use opentelemetry::trace::TracerProvider as _;
+    use opentelemetry_sdk::trace::TracerProvider;
+    use std::sync::{Arc, Mutex};
+    use tracing_subscriber::{fmt, layer::SubscriberExt, Registry};
+
+    /// Writer capturing formatted output for assertions.
+    #[derive(Clone, Default)]
+    pub(crate) struct CapturedWriter(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for CapturedWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl CapturedWriter {
+        pub(crate) fn lines(&self) -> Vec<String> {
+            String::from_utf8(self.0.lock().unwrap().clone())
+                .unwrap()
+                .lines()
+                .map(str::to_string)
+                .collect()
+        }
+    }
+
+    #[test]
+    fn test_timestamp_formats() {
+        let instant = Duration::new(1_700_000_000, 123_456_789);
+        let format = |format| {
+            let mut out = String::new();
+            write_timestamp(&mut out, format, instant).unwrap();
+            out
+        };
+        assert_eq!(
+            format(TimestampFormat::Rfc3339),
+            "2023-11-14T22:13:20.123456Z"
+        );
+        assert_eq!(
+            format(TimestampFormat::Rfc3339Nanos),
+            "2023-11-14T22:13:20.123456789Z"
+        );
+        assert_eq!(format(TimestampFormat::UnixMillis), "1700000000123");
+
+        let mut epoch = String::new();
+        write_timestamp(&mut epoch, TimestampFormat::Rfc3339, Duration::ZERO).unwrap();
+        assert_eq!(epoch, "1970-01-01T00:00:00.000000Z");
+        // Leap day.
+        assert_eq!(civil_from_days(19_782), (2024, 2, 29));
+    }
+
+    #[test]
+    fn test_json_logs_use_timestamp_format() {
+        let output = CapturedWriter::default();
+        let writer = output.clone();
+        let subscriber = Registry::default().with(
+            fmt::layer()
+                .event_format(TraceContextJson(
+                    fmt::format()
+                        .json()
+                        .with_timer(Timestamp(TimestampFormat::UnixMillis)),
+                    LogTraceIds::default(),
+                ))
+                .fmt_fields(fmt::format::JsonFields::new())
+                .with_writer(move || writer.clone()),
+        );
+        tracing::subscriber::with_default(subscriber, || tracing::info!("started"));
+
+        let line: serde_json::Value = serde_json::from_str(&output.lines()[0]).unwrap();
+        let timestamp = line["timestamp"].as_str().unwrap();
+        assert!(timestamp.len() >= 13);
+        assert!(timestamp.bytes().all(|b| b.is_ascii_digit()));
+    }
+
+    #[test]
+    fn test_logfmt_quoting() {
+        let mut line = String::new();
+        write_pair(&mut line, "plain", "GET").unwrap();
+        write_pair(&mut line, "spaces", "bucket not found").unwrap();
+        write_pair(&mut line, "quotes", r#"say "hi""#).unwrap();
+        write_pair(&mut line, "equals", "a=b").unwrap();
+        write_pair(&mut line, "empty", "").unwrap();
+        write_pair(&mut line, "escapes", "a\\b\nc").unwrap();
+        assert_eq!(
+            line,
+            r#" plain=GET spaces="bucket not found" quotes="say \"hi\"" equals="a=b" empty="" escapes="a\\b\nc""#
+        );
+    }
+
+    #[test]
+    fn test_logfmt_events() {
+        let output = CapturedWriter::default();
+        let writer = output.clone();
+        let provider = TracerProvider::builder().build();
+        let subscriber = Registry::default()
+            .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")))
+            .with(
+                fmt::layer()
+                    .event_format(Logfmt::new(
+                        TimestampFormat::UnixMillis,
+                        LogTraceIds::default(),
+                    ))
+                    .with_writer(move || writer.clone()),
+            );
+
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::warn!(bucket = "user files", retries = 3, "upload failed");
+            tracing::info_span!("request").in_scope(|| tracing::info!(path = "/health", "ok"));
+        });
+
+        let lines = output.lines();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("ts="));
+        assert!(lines[0].ends_with(
+            r#" level=warn target=tembo_telemetry::format::tests msg="upload failed" bucket="user files" retries=3"#
+        ));
+        assert!(!lines[0].contains("trace_id="));
+        let trace_id = lines[1].split(" trace_id=").nth(1).unwrap();
+        assert_eq!(trace_id.split(' ').next().unwrap().len(), 32);
+        assert!(lines[1].contains(" span_id="));
+        assert!(lines[1].ends_with(" msg=ok path=/health"));
+    }
+
+    #[test]
+    fn test_logfmt_source_location() {
+        let output = CapturedWriter::default();
+        let writer = output.clone();
+        let subscriber = Registry::default().with(
+            fmt::layer()
+                .event_format(
+                    Logfmt::new(TimestampFormat::UnixMillis, LogTraceIds::default())
+                        .with_source_location(true),
+                )
+                .with_writer(move || writer.clone()),
+        );
+
+        tracing::subscriber::with_default(subscriber, || tracing::info!("ok"));
+
+        let lines = output.lines();
+        let line = line!() - 3;
+        assert!(lines[0].contains(&format!(
+            " target=tembo_telemetry::format::tests file=src/format.rs line={line} msg=ok"
+        )));
+    }
+
+    #[test]
+    fn test_log_records_carry_trace_context() {
+        // Use a local bridge instead of the global logger, which `init` installs.
+        let bridge = tracing_log::LogTracer::new();
+        log::set_max_level(log::LevelFilter::Trace);
+        let output = CapturedWriter::default();
+        let writer = output.clone();
+        let provider = TracerProvider::builder().build();
+        let subscriber = Registry::default()
+            .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")))
+            .with(
+                fmt::layer()
+                    .event_format(TraceContextJson(fmt::format().json(), LogTraceIds::Both))
+                    .fmt_fields(fmt::format::JsonFields::new())
+                    .with_writer(move || writer.clone()),
+            );
+
+        tracing::subscriber::with_default(subscriber, || {
+            log::info!(logger: bridge, "outside any span");
+            tracing::info_span!("request").in_scope(|| log::info!(logger: bridge, "bridged"));
+        });
+
+        let lines = output.lines();
+        assert_eq!(lines.len(), 2);
+        let outside: serde_json::Value = serde_json::from_str(&lines[0]).unwrap();
+        assert!(outside.get("trace_id").is_none());
+        let bridged: serde_json::Value = serde_json::from_str(&lines[1]).unwrap();
+        assert_eq!(bridged["fields"]["message"], "bridged");
+        assert_eq!(bridged["trace_id"].as_str().unwrap().len(), 32);
+        assert_eq!(bridged["span_id"].as_str().unwrap().len(), 16);
+        let dd_trace_id: u64 = bridged["dd.trace_id"].as_str().unwrap().parse().unwrap();
+        let trace_id = bridged["trace_id"].as_str().unwrap();
+        assert_eq!(
+            dd_trace_id,
+            u64::from_str_radix(&trace_id[16..], 16).unwrap()
+        );
+        assert!(bridged["dd.span_id"]
+            .as_str()
+            .unwrap()
+            .parse::<u64>()
+            .is_ok());
+        assert!(outside.get("dd.trace_id").is_none());
+    }
+}
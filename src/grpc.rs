@@ -0,0 +1,319 @@
+//! Tower layer tracing gRPC requests served by `tonic`.
+
+use crate::propagation;
+use http::{HeaderMap, Request, Response};
+use http_body::{Body, SizeHint};
+use opentelemetry::propagation::Extractor;
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tower::{Layer, Service};
+use tracing::{field::Empty, Instrument, Span};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+/// Header and trailer carrying the status code of a gRPC response.
+const GRPC_STATUS: &str = "grpc-status";
+
+/// Tower layer creating a server span for each gRPC request.
+///
+/// The span follows the OpenTelemetry RPC conventions: it is named `{rpc.service}/{rpc.method}`,
+/// has the `server` kind and records `rpc.system`, `rpc.service`, `rpc.method` and
+/// `rpc.grpc.status_code`. Responses with a status code other than `OK`, and errors returned by
+/// the inner service, mark the span as failed. The parent context is extracted from the request
+/// metadata with the global propagator, so the trace of the caller is continued.
+///
+/// The status code is read from the response headers for errors returned by the handler, and
+/// from the trailers once the response body is complete otherwise, so the span stays open until
+/// the body has been streamed.
+///
+/// ```rust,ignore
+/// tonic::transport::Server::builder()
+///     .layer(GrpcTracingLayer)
+///     .add_service(BillingServer::new(billing))
+///     .serve(addr)
+///     .await?;
+/// ```
+#[derive(Clone, Copy, Debug, Default)]
+pub struct GrpcTracingLayer;
+
+impl<S> Layer<S> for GrpcTracingLayer {
+    type Service = GrpcTracing<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        GrpcTracing { inner }
+    }
+}
+
+/// Service created by [`GrpcTracingLayer`].
+#[derive(Clone, Debug)]
+pub struct GrpcTracing<S> {
+    inner: S,
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for GrpcTracing<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>>,
+    S::Future: Send + 'static,
+{
+    type Response = Response<GrpcBody<ResBody>>;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, S::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<ReqBody>) -> Self::Future {
+        let path = request.uri().path();
+        let (service, method) = path
+            .trim_start_matches('/')
+            .split_once('/')
+            .unwrap_or((path, ""));
+        let span = tracing::info_span!(
+            "gRPC request",
+            otel.name = %format_args!("{service}/{method}"),
+            otel.kind = "server",
+            otel.status_code = Empty,
+            rpc.system = "grpc",
+            rpc.service = service,
+            rpc.method = method,
+            rpc.grpc.status_code = Empty,
+        );
+        span.set_parent(propagation::extract_context(&HttpHeaderExtractor(
+            request.headers(),
+        )));
+
+        let future = span.in_scope(|| self.inner.call(request));
+        Box::pin(
+            async move {
+                let span = Span::current();
+                match future.await {
+                    Ok(response) => {
+                        // Errors returned by the handler are sent as a trailers-only response.
+                        let status = grpc_status(response.headers());
+                        if let Some(status) = status {
+                            record_status(&span, status);
+                        }
+                        let recorded = status.is_some();
+                        Ok(response.map(|body| GrpcBody {
+                            inner: Box::pin(body),
+                            span: (!recorded).then_some(span),
+                        }))
+                    }
+                    Err(err) => {
+                        span.record("otel.status_code", "ERROR");
+                        Err(err)
+                    }
+                }
+            }
+            .instrument(span),
+        )
+    }
+}
+
+/// Response body recording the gRPC status code from its trailers on the request span.
+///
+/// Holds the span until the status is recorded, so it ends once the response is complete.
+pub struct GrpcBody<B> {
+    inner: Pin<Box<B>>,
+    span: Option<Span>,
+}
+
+impl<B: Body> Body for GrpcBody<B> {
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let poll = self.inner.as_mut().poll_data(cx);
+        if let Poll::Ready(Some(Err(_))) = &poll {
+            if let Some(span) = self.span.take() {
+                span.record("otel.status_code", "ERROR");
+            }
+        }
+        poll
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        let poll = self.inner.as_mut().poll_trailers(cx);
+        if let Poll::Ready(result) = &poll {
+            if let Some(span) = self.span.take() {
+                match result {
+                    Ok(trailers) => {
+                        if let Some(status) = trailers.as_ref().and_then(grpc_status) {
+                            record_status(&span, status);
+                        }
+                    }
+                    Err(_) => {
+                        span.record("otel.status_code", "ERROR");
+                    }
+                }
+            }
+        }
+        poll
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+impl<B> std::fmt::Debug for GrpcBody<B> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("GrpcBody").finish_non_exhaustive()
+    }
+}
+
+/// Parses the `grpc-status` entry of `headers`.
+fn grpc_status(headers: &HeaderMap) -> Option<i64> {
+    headers.get(GRPC_STATUS)?.to_str().ok()?.parse().ok()
+}
+
+/// Records the gRPC status code on `span`, marking the span as failed unless it is `OK`.
+fn record_status(span: &Span, status: i64) {
+    span.record("rpc.grpc.status_code", status);
+    if status != 0 {
+        span.record("otel.status_code", "ERROR");
+    }
+}
+
+/// Extractor reading propagation headers from an `http` `HeaderMap`.
+struct HttpHeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HttpHeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|key| key.as_str()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processors::tests::{attribute, collecting_subscriber};
+    use http::HeaderValue;
+    use opentelemetry::{
+        trace::{SpanKind, Status},
+        Value,
+    };
+    use opentelemetry_sdk::{export::trace::SpanData, propagation::TraceContextPropagator};
+    use std::convert::Infallible;
+    use tower::{ServiceBuilder, ServiceExt};
+    use tracing::instrument::WithSubscriber;
+
+    /// Empty body ending with the given trailers.
+    struct TrailersBody(Option<HeaderMap>);
+
+    impl Body for TrailersBody {
+        type Data = &'static [u8];
+        type Error = Infallible;
+
+        fn poll_data(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+        ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+            Poll::Ready(None)
+        }
+
+        fn poll_trailers(
+            mut self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+        ) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+            Poll::Ready(Ok(self.0.take()))
+        }
+    }
+
+    fn status_headers(status: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(GRPC_STATUS, HeaderValue::from_static(status));
+        headers
+    }
+
+    /// Serves one request with `response` and returns the exported span.
+    async fn serve(response: fn() -> Response<TrailersBody>) -> SpanData {
+        let (collector, subscriber) = collecting_subscriber();
+
+        let service = ServiceBuilder::new()
+            .layer(GrpcTracingLayer)
+            .service_fn(move |_: Request<()>| async move { Ok::<_, Infallible>(response()) });
+        let request = Request::post("/billing.v1.Invoices/Get")
+            .header(
+                "traceparent",
+                "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            )
+            .body(())
+            .unwrap();
+        async {
+            let mut body = service.oneshot(request).await.unwrap().into_body();
+            while body.data().await.is_some() {}
+            body.trailers().await.unwrap();
+        }
+        .with_subscriber(subscriber)
+        .await;
+
+        let mut spans = collector.0.lock().unwrap();
+        assert_eq!(spans.len(), 1);
+        spans.pop().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_ok_status_from_trailers() {
+        opentelemetry::global::set_text_map_propagator(TraceContextPropagator::new());
+        let span = serve(|| Response::new(TrailersBody(Some(status_headers("0"))))).await;
+
+        assert_eq!(span.name, "billing.v1.Invoices/Get");
+        assert_eq!(span.span_kind, SpanKind::Server);
+        assert_eq!(
+            span.span_context.trace_id().to_string(),
+            "4bf92f3577b34da6a3ce929d0e0e4736"
+        );
+        assert_eq!(span.status, Status::Unset);
+        assert_eq!(
+            attribute(&span.attributes, "rpc.system"),
+            Some(Value::from("grpc"))
+        );
+        assert_eq!(
+            attribute(&span.attributes, "rpc.service"),
+            Some(Value::from("billing.v1.Invoices"))
+        );
+        assert_eq!(
+            attribute(&span.attributes, "rpc.method"),
+            Some(Value::from("Get"))
+        );
+        assert_eq!(
+            attribute(&span.attributes, "rpc.grpc.status_code"),
+            Some(Value::I64(0))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_error_status_from_headers() {
+        // NOT_FOUND, sent by tonic as a trailers-only response.
+        let span = serve(|| {
+            let mut response = Response::new(TrailersBody(None));
+            *response.headers_mut() = status_headers("5");
+            response
+        })
+        .await;
+
+        assert!(matches!(span.status, Status::Error { .. }));
+        assert_eq!(
+            attribute(&span.attributes, "rpc.grpc.status_code"),
+            Some(Value::I64(5))
+        );
+    }
+}
@@ -0,0 +1,359 @@
+use crate::resource::SharedResource;
+use opentelemetry::{global, trace::TraceError, KeyValue};
+use opentelemetry_sdk::trace::TracerProvider;
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc, Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+#[cfg(feature = "metrics")]
+use crate::metrics::ProcessMetrics;
+#[cfg(all(unix, feature = "signals"))]
+use crate::signals::LogFilterHandle;
+#[cfg(feature = "metrics")]
+use opentelemetry_sdk::metrics::SdkMeterProvider;
+
+/// Guard returned by `TelemetryInit::init` that flushes and shuts down telemetry on drop.
+///
+/// Hold the guard for the lifetime of the application, typically in `main`. When it is dropped,
+/// buffered spans are flushed to the exporter and the global tracer provider is shut down, even
+/// on early returns. With the `metrics` feature, the meter provider is flushed and shut down
+/// the same way.
+///
+/// When running inside an async runtime, the guard must be dropped before the runtime shuts
+/// down, otherwise the batch exporter may no longer be able to deliver the remaining spans.
+///
+/// Flushing and shutdown are bounded by the configured shutdown timeout. If the exporter has
+/// not finished in time, for example because the collector is unreachable, a warning is logged
+/// and the remaining spans are abandoned so the process can exit.
+#[must_use = "dropping the guard immediately shuts down telemetry"]
+#[derive(Debug)]
+pub struct TelemetryGuard {
+    providers: Providers,
+    #[cfg(feature = "metrics")]
+    process_metrics: Option<ProcessMetrics>,
+    resource: Option<SharedResource>,
+    dropped_spans: Arc<AtomicU64>,
+    shutdown_timeout: Duration,
+    #[cfg(all(unix, feature = "signals"))]
+    log_filter: Option<LogFilterHandle>,
+}
+
+/// Providers owned by a [`TelemetryGuard`].
+#[derive(Debug, Default)]
+struct Providers {
+    tracer: Option<TracerProvider>,
+    #[cfg(feature = "metrics")]
+    meter: Option<SdkMeterProvider>,
+}
+
+impl Providers {
+    fn is_empty(&self) -> bool {
+        #[cfg(feature = "metrics")]
+        if self.meter.is_some() {
+            return false;
+        }
+        self.tracer.is_none()
+    }
+
+    /// Exports everything buffered by the providers, reporting errors to the global error
+    /// handler.
+    fn flush(&self) {
+        if let Some(tracer) = &self.tracer {
+            for result in tracer.force_flush() {
+                if let Err(err) = result {
+                    global::handle_error(err);
+                }
+            }
+        }
+        #[cfg(feature = "metrics")]
+        if let Some(meter) = &self.meter {
+            if let Err(err) = meter.force_flush() {
+                global::handle_error(err);
+            }
+        }
+    }
+
+    /// Flushes and shuts down every provider, reporting errors to the global error handler.
+    fn shutdown(self) {
+        if let Some(tracer) = self.tracer {
+            for result in tracer.force_flush() {
+                if let Err(err) = result {
+                    global::handle_error(err);
+                }
+            }
+            // Dropping the last provider handle shuts down its span processors.
+            drop(tracer);
+        }
+        #[cfg(feature = "metrics")]
+        if let Some(meter) = self.meter {
+            // Shutting down the meter provider exports the last collection.
+            if let Err(err) = meter.shutdown() {
+                global::handle_error(err);
+            }
+        }
+    }
+}
+
+impl TelemetryGuard {
+    /// Creates a guard for the given tracer provider.
+    ///
+    /// # Arguments
+    ///
+    /// * `provider` - The provider to flush and shut down, if an exporter is configured.
+    /// * `shutdown_timeout` - How long dropping the guard waits for the flush and shutdown.
+    pub(crate) fn new(provider: Option<TracerProvider>, shutdown_timeout: Duration) -> Self {
+        Self {
+            providers: Providers {
+                tracer: provider,
+                #[cfg(feature = "metrics")]
+                meter: None,
+            },
+            #[cfg(feature = "metrics")]
+            process_metrics: None,
+            resource: None,
+            dropped_spans: Arc::default(),
+            shutdown_timeout,
+            #[cfg(all(unix, feature = "signals"))]
+            log_filter: None,
+        }
+    }
+
+    /// Hands the meter provider and process metrics collector to the guard.
+    ///
+    /// # Arguments
+    ///
+    /// * `meter_provider` - The meter provider to flush and shut down.
+    /// * `process_metrics` - The collector to stop before the meter provider shuts down.
+    #[cfg(feature = "metrics")]
+    pub(crate) fn with_metrics(
+        mut self,
+        meter_provider: Option<SdkMeterProvider>,
+        process_metrics: Option<ProcessMetrics>,
+    ) -> Self {
+        self.providers.meter = meter_provider;
+        self.process_metrics = process_metrics;
+        self
+    }
+
+    /// Hands the resource applied to exported spans to the guard.
+    ///
+    /// # Arguments
+    ///
+    /// * `resource` - The resource updated by [`TelemetryGuard::update_resource`].
+    pub(crate) fn with_resource(mut self, resource: SharedResource) -> Self {
+        self.resource = Some(resource);
+        self
+    }
+
+    /// Hands the counter of spans dropped by the export queues to the guard.
+    ///
+    /// # Arguments
+    ///
+    /// * `dropped_spans` - The counter read by [`TelemetryGuard::dropped_spans`].
+    pub(crate) fn with_dropped_spans(mut self, dropped_spans: Arc<AtomicU64>) -> Self {
+        self.dropped_spans = dropped_spans;
+        self
+    }
+
+    /// Hands the handle replacing the installed log filter to the guard.
+    ///
+    /// # Arguments
+    ///
+    /// * `log_filter` - The handle used by [`TelemetryGuard::handle_log_level_signals`].
+    #[cfg(all(unix, feature = "signals"))]
+    pub(crate) fn with_log_filter(mut self, log_filter: LogFilterHandle) -> Self {
+        self.log_filter = Some(log_filter);
+        self
+    }
+
+    /// Changes the log filter when the process receives `SIGUSR1` or `SIGUSR2`.
+    ///
+    /// `SIGUSR1` replaces the filter with `debug`, `SIGUSR2` restores the filter installed by
+    /// `init`, from `RUST_LOG` or the `info` default. Each change is logged. The handlers run
+    /// as tasks on the current Tokio runtime, so this fails outside of one. Signals are
+    /// delivered to every listener registered through `tokio::signal` or `signal-hook`, so the
+    /// application can still handle them itself.
+    #[cfg(all(unix, feature = "signals"))]
+    pub fn handle_log_level_signals(&self) -> std::io::Result<()> {
+        match &self.log_filter {
+            Some(log_filter) => log_filter.listen(),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns how many spans were dropped because an export queue was full.
+    ///
+    /// Spans are dropped when more than `max_queue_size` are waiting for export, usually
+    /// because the collector is slow or unreachable. With the `metrics` feature, the same value
+    /// is exported as the `telemetry.spans.dropped` counter.
+    pub fn dropped_spans(&self) -> u64 {
+        self.dropped_spans.load(Ordering::Relaxed)
+    }
+
+    /// Returns the counters describing the telemetry pipeline itself.
+    ///
+    /// Counts the spans and events enabled by the log filter since the process started, the
+    /// spans accepted by the OTLP endpoints and the spans dropped by full export queues. With
+    /// an OTLP endpoint, the same values are exported as the `telemetry.spans.created`,
+    /// `telemetry.events.emitted`, `telemetry.spans.exported` and `telemetry.spans.dropped`
+    /// counters.
+    #[cfg(feature = "metrics")]
+    pub fn counters(&self) -> crate::TelemetryCounters {
+        crate::TelemetryCounters::read(&self.dropped_spans)
+    }
+
+    /// Adds attributes to the resource of exported spans, e.g. once cloud metadata resolved.
+    ///
+    /// Values replace existing attributes with the same key. Spans ending after the call carry
+    /// the updated resource, spans that already ended keep the previous one. Metrics keep the
+    /// resource configured at `init`.
+    ///
+    /// # Arguments
+    ///
+    /// * `attributes` - The resource attributes to add or replace.
+    pub fn update_resource(&self, attributes: impl IntoIterator<Item = KeyValue>) {
+        if let Some(resource) = &self.resource {
+            resource.merge(attributes);
+        }
+    }
+
+    /// Exports all buffered spans and, with the `metrics` feature, the current metric values.
+    ///
+    /// Telemetry stays installed, so this can be called at checkpoints of long-running work.
+    /// Short-lived batch jobs should call it before exiting rather than rely on the periodic
+    /// metric reader. Blocks until the exporters finish, errors are reported to the
+    /// OpenTelemetry global error handler.
+    pub fn flush(&self) {
+        self.providers.flush();
+    }
+}
+
+impl Drop for TelemetryGuard {
+    fn drop(&mut self) {
+        #[cfg(feature = "metrics")]
+        drop(self.process_metrics.take());
+
+        let providers = std::mem::take(&mut self.providers);
+        if providers.is_empty() {
+            return;
+        }
+        // Release the global handle first so the provider below is the last one.
+        global::shutdown_tracer_provider();
+
+        // Flush and shut down on a separate thread so a stalled exporter cannot block past the
+        // timeout.
+        let (done_tx, done_rx) = mpsc::channel();
+        let spawned = thread::Builder::new()
+            .name("telemetry-shutdown".to_string())
+            .spawn(move || {
+                providers.shutdown();
+                let _ = done_tx.send(());
+            });
+        match spawned {
+            Ok(_) => {
+                if done_rx.recv_timeout(self.shutdown_timeout).is_err() {
+                    tracing::warn!(
+                        timeout_ms = self.shutdown_timeout.as_millis() as u64,
+                        "telemetry shutdown timed out, abandoning unexported spans"
+                    );
+                }
+            }
+            Err(err) => global::handle_error(TraceError::Other(err.into())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry::{trace::TraceResult, Context};
+    use opentelemetry_sdk::{
+        export::trace::SpanData,
+        trace::{Span, SpanProcessor},
+    };
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    #[derive(Debug, Default)]
+    struct CountingProcessor {
+        flushes: Arc<AtomicUsize>,
+        shutdowns: Arc<AtomicUsize>,
+        flush_delay: Duration,
+    }
+
+    impl SpanProcessor for CountingProcessor {
+        fn on_start(&self, _span: &mut Span, _cx: &Context) {}
+
+        fn on_end(&self, _span: SpanData) {}
+
+        fn force_flush(&self) -> TraceResult<()> {
+            thread::sleep(self.flush_delay);
+            self.flushes.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+
+        fn shutdown(&mut self) -> TraceResult<()> {
+            self.shutdowns.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_guard_flushes_and_shuts_down_on_drop() {
+        let processor = CountingProcessor::default();
+        let flushes = processor.flushes.clone();
+        let shutdowns = processor.shutdowns.clone();
+        let provider = TracerProvider::builder()
+            .with_span_processor(processor)
+            .build();
+
+        let guard = TelemetryGuard::new(Some(provider), Duration::from_secs(5));
+        assert_eq!(flushes.load(Ordering::SeqCst), 0);
+        drop(guard);
+        assert_eq!(flushes.load(Ordering::SeqCst), 1);
+        assert_eq!(shutdowns.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_guard_flush_keeps_provider_running() {
+        let processor = CountingProcessor::default();
+        let flushes = processor.flushes.clone();
+        let shutdowns = processor.shutdowns.clone();
+        let provider = TracerProvider::builder()
+            .with_span_processor(processor)
+            .build();
+
+        let guard = TelemetryGuard::new(Some(provider), Duration::from_secs(5));
+        guard.flush();
+        assert_eq!(flushes.load(Ordering::SeqCst), 1);
+        assert_eq!(shutdowns.load(Ordering::SeqCst), 0);
+        drop(guard);
+        assert_eq!(flushes.load(Ordering::SeqCst), 2);
+        assert_eq!(shutdowns.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_guard_gives_up_after_shutdown_timeout() {
+        let processor = CountingProcessor {
+            flush_delay: Duration::from_secs(2),
+            ..Default::default()
+        };
+        let shutdowns = processor.shutdowns.clone();
+        let provider = TracerProvider::builder()
+            .with_span_processor(processor)
+            .build();
+
+        let guard = TelemetryGuard::new(Some(provider), Duration::from_millis(50));
+        let started = std::time::Instant::now();
+        drop(guard);
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert_eq!(shutdowns.load(Ordering::SeqCst), 0);
+    }
+}
@@ -0,0 +1,184 @@
+//! Health of the span export pipeline, for readiness checks.
+
+use crate::TelemetryConfig;
+use opentelemetry_sdk::export::trace::ExportResult;
+use std::{
+    sync::{Arc, Mutex, OnceLock},
+    time::SystemTime,
+};
+
+/// Outcome of the latest span exports, returned by [`TelemetryConfig::check_export_health`].
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum ExportHealth {
+    /// No export to some endpoint has completed yet, or spans are not exported.
+    #[default]
+    Unknown,
+    /// The latest export to every endpoint succeeded.
+    Healthy {
+        /// When the least recent of these exports completed.
+        last_success: SystemTime,
+    },
+    /// The latest export to at least one endpoint failed.
+    Unhealthy {
+        /// When the most recent failed export completed.
+        last_failure: SystemTime,
+        /// Error returned by that export.
+        error: String,
+    },
+}
+
+impl ExportHealth {
+    /// Returns whether the latest export to some endpoint failed.
+    pub fn is_unhealthy(&self) -> bool {
+        matches!(self, ExportHealth::Unhealthy { .. })
+    }
+}
+
+/// Latest export outcome of one exporter, updated by the exporter after each batch.
+#[cfg_attr(not(feature = "otlp"), allow(dead_code))]
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ExportStatus(Arc<Mutex<ExportHealth>>);
+
+#[cfg_attr(not(feature = "otlp"), allow(dead_code))]
+impl ExportStatus {
+    /// Records the result of an export that just completed.
+    pub(crate) fn record(&self, result: &ExportResult) {
+        let now = SystemTime::now();
+        *self.0.lock().unwrap() = match result {
+            Ok(()) => ExportHealth::Healthy { last_success: now },
+            Err(err) => ExportHealth::Unhealthy {
+                last_failure: now,
+                error: err.to_string(),
+            },
+        };
+    }
+
+    fn get(&self) -> ExportHealth {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+/// Statuses of the exporters of the installed tracer provider.
+fn statuses() -> &'static Mutex<Vec<ExportStatus>> {
+    static STATUSES: OnceLock<Mutex<Vec<ExportStatus>>> = OnceLock::new();
+    STATUSES.get_or_init(Mutex::default)
+}
+
+/// Creates the statuses of `count` exporters, replacing those of a previous pipeline.
+#[cfg_attr(not(feature = "otlp"), allow(dead_code))]
+pub(crate) fn register_exporters(count: usize) -> Vec<ExportStatus> {
+    let registered: Vec<_> = (0..count).map(|_| ExportStatus::default()).collect();
+    *statuses().lock().unwrap() = registered.clone();
+    registered
+}
+
+/// Combines the health of several exporters, reporting the worst of them.
+///
+/// A failure of any exporter is reported first. Otherwise the result is `Unknown` while some
+/// exporter has not completed an export yet, so `Healthy` always covers every exporter.
+fn combine(healths: impl IntoIterator<Item = ExportHealth>) -> ExportHealth {
+    healths
+        .into_iter()
+        .reduce(|combined, health| match (combined, health) {
+            (
+                ExportHealth::Unhealthy {
+                    last_failure,
+                    error,
+                },
+                ExportHealth::Unhealthy {
+                    last_failure: other,
+                    ..
+                },
+            ) if last_failure >= other => ExportHealth::Unhealthy {
+                last_failure,
+                error,
+            },
+            (_, unhealthy @ ExportHealth::Unhealthy { .. }) => unhealthy,
+            (unhealthy @ ExportHealth::Unhealthy { .. }, _) => unhealthy,
+            (
+                ExportHealth::Healthy { last_success },
+                ExportHealth::Healthy {
+                    last_success: other,
+                },
+            ) => ExportHealth::Healthy {
+                last_success: last_success.min(other),
+            },
+            (ExportHealth::Unknown, _) | (_, ExportHealth::Unknown) => ExportHealth::Unknown,
+        })
+        .unwrap_or_default()
+}
+
+impl TelemetryConfig {
+    /// Reports whether the latest span exports reached the collector.
+    ///
+    /// Meant for readiness endpoints, to surface a broken telemetry pipeline. Reads the outcome
+    /// recorded after each exported batch, without exporting anything itself, so the result is
+    /// only as recent as the last batch. Returns [`ExportHealth::Unknown`] before the first
+    /// export completes, and when this config does not export spans. With additional endpoints,
+    /// a failure of any of them is reported, and the result stays `Unknown` until every one of
+    /// them has completed an export.
+    pub fn check_export_health(&self) -> ExportHealth {
+        if !cfg!(feature = "otlp") || self.resolved_endpoint_url().is_none() {
+            return ExportHealth::Unknown;
+        }
+        combine(statuses().lock().unwrap().iter().map(ExportStatus::get))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry::trace::TraceError;
+    use std::time::Duration;
+
+    #[test]
+    fn test_export_status() {
+        let status = ExportStatus::default();
+        assert_eq!(status.get(), ExportHealth::Unknown);
+        status.record(&Err(TraceError::from("connection refused")));
+        assert!(matches!(
+            status.get(),
+            ExportHealth::Unhealthy { error, .. } if error.contains("connection refused")
+        ));
+        status.record(&Ok(()));
+        assert!(matches!(status.get(), ExportHealth::Healthy { .. }));
+    }
+
+    #[test]
+    fn test_combine() {
+        let earlier = SystemTime::UNIX_EPOCH;
+        let later = earlier + Duration::from_secs(1);
+        let healthy = |last_success| ExportHealth::Healthy { last_success };
+        let unhealthy = |last_failure, error: &str| ExportHealth::Unhealthy {
+            last_failure,
+            error: error.to_string(),
+        };
+
+        assert_eq!(combine([]), ExportHealth::Unknown);
+        assert_eq!(
+            combine([healthy(later), healthy(earlier)]),
+            healthy(earlier)
+        );
+        assert_eq!(
+            combine([healthy(later), ExportHealth::Unknown]),
+            ExportHealth::Unknown
+        );
+        assert_eq!(
+            combine([ExportHealth::Unknown, unhealthy(earlier, "a")]),
+            unhealthy(earlier, "a")
+        );
+        assert_eq!(
+            combine([
+                unhealthy(earlier, "a"),
+                healthy(later),
+                unhealthy(later, "b")
+            ]),
+            unhealthy(later, "b")
+        );
+        assert_eq!(
+            combine([unhealthy(later, "a"), unhealthy(earlier, "b")]),
+            unhealthy(later, "a")
+        );
+        assert!(TelemetryConfig::default().check_export_health() == ExportHealth::Unknown);
+    }
+}
@@ -10,41 +10,643 @@
 //! # Usage
 //! Refer to the `TelemetryConfig` and `TelemetryInit` traits for setting up and initializing telemetry.
 
+pub mod actix_client;
+#[cfg(feature = "http-client")]
+mod client;
+#[cfg(feature = "serde")]
+mod duration_serde;
+mod effective;
+mod error;
+mod format;
+#[cfg(feature = "grpc")]
+mod grpc;
+mod guard;
+mod health;
+#[cfg(feature = "metrics")]
+mod metrics;
+pub mod middleware;
+#[cfg(feature = "otlp")]
+mod otlp;
+mod panic;
+pub mod processors;
+pub mod propagation;
+mod recent_logs;
+mod resource;
+mod sampling;
+#[cfg(all(unix, feature = "signals"))]
+mod signals;
+mod span_durations;
+mod status;
+#[cfg(feature = "syslog")]
+mod syslog;
+#[cfg(feature = "testing")]
+pub mod testing;
+mod timestamps;
+
+#[cfg(feature = "http-client")]
+pub use client::{ClientTracing, ClientTracingLayer};
+pub use effective::EffectiveConfig;
+pub use error::TelemetryError;
+#[cfg(feature = "grpc")]
+pub use grpc::{GrpcTracing, GrpcTracingLayer};
+pub use guard::TelemetryGuard;
+pub use health::ExportHealth;
+#[cfg(feature = "metrics")]
+pub use metrics::{MetricExportRetry, TelemetryCounters};
+pub use middleware::{CorrelationId, CustomLogger, TraceIdHeader};
+pub use processors::SpanTransform;
+pub use tracing_actix_web::RequestId;
+
+use format::{Logfmt, Timestamp, TraceContextJson};
+pub use recent_logs::{recent_logs, take_recent_logs, RecentLogs};
+pub use sampling::{force_sample, startup_span, ForceSampleGuard, Sampler, TailSampling};
+pub use status::record_error;
+#[cfg(feature = "syslog")]
+pub use syslog::{SyslogConfig, SyslogFacility, DEFAULT_SYSLOG_SOCKET};
+pub use timestamps::{add_event_at, end_span_at, set_start_time};
+
 use actix_web::{
     body::MessageBody,
     dev::{ServiceRequest, ServiceResponse},
+    http::StatusCode,
     Error,
 };
 use async_trait::async_trait;
-use opentelemetry::{global, trace::TraceId, KeyValue};
-use opentelemetry_otlp::WithExportConfig;
-use opentelemetry_sdk::{
-    propagation::TraceContextPropagator, runtime::TokioCurrentThread, trace, Resource,
+use opentelemetry::{
+    global,
+    trace::{SpanContext, TraceContextExt, TraceId},
+    KeyValue,
 };
-use tracing::Span;
-use tracing_actix_web::{DefaultRootSpanBuilder, RootSpanBuilder, TracingLogger};
+use opentelemetry_sdk::{trace, Resource};
+use tracing::{level_filters::LevelFilter, Level, Span, Subscriber};
+use tracing_actix_web::{DefaultRootSpanBuilder, RootSpanBuilder};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
 use tracing_subscriber::{
     fmt::{self, format::FmtSpan},
     layer::SubscriberExt,
-    EnvFilter, Registry,
+    registry::LookupSpan,
+    Layer, Registry,
 };
 
-use std::{borrow::Cow, cell::RefCell};
+use std::{
+    borrow::Cow,
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    ffi::OsString,
+    io::IsTerminal,
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
 
 /// Configuration for telemetry setup.
 ///
 /// This struct provides fields to set up OpenTelemetry exporters, specify the application name,
 /// environment, endpoint URL, and an optional tracer ID.
-#[derive(Clone, Default, Debug)]
+///
+/// With the `serde` feature, it can be deserialized from application config files. Missing
+/// fields take their default values, durations are written as strings such as `"250ms"` or
+/// `"5s"`, and `span_transform` is skipped.
+#[derive(Clone, Debug)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
 pub struct TelemetryConfig {
-    /// Name of the application.
+    /// Name of the application, also recorded as `service.name` unless `service_name` is set.
     pub app_name: String,
+    /// Name recorded as the `service.name` resource attribute, when it differs from
+    /// `app_name`.
+    ///
+    /// Backends group traces and metrics by `service.name`, which is usually a short
+    /// identifier such as `tembo-cp`, while `app_name` can stay human-facing. Defaults to
+    /// `None`, recording `app_name`.
+    pub service_name: Option<String>,
     /// Specifies the environment (e.g., "development" or "production").
+    ///
+    /// Recorded as the `deployment.environment` resource attribute. Unless `environment` is
+    /// set, it is also parsed into an [`Environment`] to select the log format.
     pub env: String,
     /// Optional URL for the OTLP exporter.
+    ///
+    /// Use `unix:///path/to/socket` to export over a unix domain socket, e.g. to a sidecar
+    /// collector. `http://` and `https://` endpoints connect over TCP. Ignored without the
+    /// `otlp` feature.
     pub endpoint_url: Option<String>,
+    /// Template deriving the OTLP endpoint from `env` when `endpoint_url` is not set.
+    ///
+    /// `{env}` is replaced by `env`, e.g. `http://otel-collector.{env}.svc:4317`, so services
+    /// deployed to the same environments can share one template. The derived URL is used
+    /// exactly like `endpoint_url`. No endpoint is derived while `env` is empty. Defaults to
+    /// `None`.
+    pub endpoint_template: Option<String>,
+    /// Additional OTLP endpoints spans are exported to alongside `endpoint_url`.
+    ///
+    /// Each endpoint gets its own batch processor and connection, e.g. to dual-write to a new
+    /// collector and a legacy Jaeger agent accepting OTLP during a migration. Ignored unless
+    /// `endpoint_url` is set. Metrics are only exported to `endpoint_url`, or to
+    /// `metrics_endpoint_url` when set.
+    pub additional_endpoint_urls: Vec<String>,
+    /// Headers sent with every OTLP export, e.g. an API key required by the backend.
+    ///
+    /// Sent as gRPC metadata to every endpoint, for traces and metrics. Names are
+    /// case-insensitive. Headers with an invalid name or value are skipped with a warning.
+    /// Defaults to empty.
+    pub headers: HashMap<String, String>,
+    /// Headers sent with trace exports only, replacing `headers` entries of the same name.
+    ///
+    /// Lets traces authenticate to another backend than metrics. Defaults to empty.
+    pub trace_headers: HashMap<String, String>,
     /// Optional tracer ID.
     pub tracer_id: Option<String>,
+    /// Whether to emit logs to stdout when an OTLP endpoint is configured.
+    ///
+    /// Defaults to `true`. When set to `false` and `endpoint_url` is provided, spans and events
+    /// are only sent to the collector, and to syslog if enabled. Without an endpoint or syslog,
+    /// stdout logging is always enabled.
+    pub stdout_logging: bool,
+    /// Filter directives selecting the spans and events exported over OTLP, in `RUST_LOG`
+    /// syntax.
+    ///
+    /// Lets traces be more verbose than logs, e.g. `debug` spans exported while stdout only
+    /// prints `info` and above. `RUST_LOG` then only applies to logs, and changing the log
+    /// level with signals leaves exports unchanged. `init` fails with
+    /// [`TelemetryError::InvalidFilter`] when the directives are invalid. Defaults to `None`,
+    /// filtering exports with `RUST_LOG` as well.
+    pub trace_filter: Option<String>,
+    /// Optional environment used to select the log format.
+    ///
+    /// When `None`, it is parsed from `env`, falling back to [`Environment::Production`] for
+    /// unrecognized values.
+    pub environment: Option<Environment>,
+    /// Whether development logs are colored with ANSI escape codes.
+    ///
+    /// When `None`, colors are used if stdout is a terminal and the `NO_COLOR` environment
+    /// variable is unset or empty. `Some` forces colors on or off. JSON logs are never colored.
+    pub ansi: Option<bool>,
+    /// Format of log timestamps. Defaults to [`TimestampFormat::Rfc3339`].
+    pub timestamp_format: TimestampFormat,
+    /// Format of stdout logs.
+    ///
+    /// When `None`, compact logs are written in [`Environment::Development`] and JSON logs
+    /// otherwise.
+    pub log_format: Option<LogFormat>,
+    /// Whether stdout logs include the source file and line of each event.
+    ///
+    /// Written as `filename` and `line_number` in JSON logs, `file` and `line` in logfmt logs
+    /// and as `file:line` after the target in compact logs. The module path is the `target` of
+    /// events that do not override it. When `None`, the location is only included in
+    /// [`Environment::Development`], to keep production logs small. Exported spans record
+    /// their location as `code.filepath`, `code.namespace` and `code.lineno` regardless.
+    pub log_source_location: Option<bool>,
+    /// Trace and span id fields added to JSON and logfmt logs. Defaults to
+    /// [`LogTraceIds::OpenTelemetry`].
+    pub log_trace_ids: LogTraceIds,
+    /// Whether an `INFO` event reporting the duration of each span is logged when it closes.
+    ///
+    /// Meant for latency dashboards built from logs when no trace backend is available. The
+    /// event is logged with the `tembo_telemetry::span_durations` target and the message
+    /// `span closed`, regardless of the log format, with the fields `span.name`,
+    /// `span.target`, `span.fields` and the numeric `duration_ms`, measured from the creation
+    /// of the span. Only spans enabled by `RUST_LOG` are timed. Defaults to `false`.
+    pub log_span_durations: bool,
+    /// Whether stdout and syslog only keep `INFO` and more verbose events of sampled traces.
+    ///
+    /// Ties log volume to trace volume: events of traces dropped by the sampler are only
+    /// logged at `WARN` and `ERROR`, while every event of sampled traces is logged. Events
+    /// outside any span belong to no trace and are always logged. Without an OTLP endpoint
+    /// nothing is sampled, so this has no effect. Defaults to `false`.
+    pub sample_logs: bool,
+    /// Sampler applied to new spans.
+    ///
+    /// Defaults to `Sampler::ParentBased(Box::new(Sampler::AlwaysOn))`, which keeps the
+    /// decision of a sampled or unsampled parent and samples every new trace.
+    pub sampler: Sampler,
+    /// Sampler replacing `sampler`, for decisions the built-in samplers cannot express.
+    ///
+    /// The sampler receives the attributes a span has when it is sampled, e.g. to always sample
+    /// root spans of enterprise tenants and ratio-sample the others. Root spans of requests are
+    /// sampled as soon as they are created, so attributes must be added through
+    /// `CustomLoggerBuilder::root_span_attributes` to be seen. It also decides for spans with a
+    /// parent, so wrap it in `opentelemetry_sdk::trace::Sampler::ParentBased` to keep the
+    /// decision of the caller. Spans under [`force_sample`] are sampled without consulting it.
+    /// Defaults to `None`.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub custom_sampler: Option<Box<dyn trace::ShouldSample>>,
+    /// Generator of the trace and span ids of new spans, replacing the SDK's random generator.
+    ///
+    /// Lets trace ids be derived from identifiers of systems that do not use OpenTelemetry,
+    /// e.g. mapping an incoming request id to a trace id, so their logs share the id. Spans
+    /// continuing a remote trace keep the caller's trace id. Backends assume trace ids are
+    /// random and unique: a non-random generator must not map distinct operations to the same
+    /// trace id, or their spans are merged into one trace, and ratio sampling, which reads the
+    /// trace id, must still see evenly distributed ids. Defaults to `None`, generating random
+    /// ids.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub id_generator: Option<Arc<dyn trace::IdGenerator>>,
+    /// Exports traces that were not sampled when one of their spans fails.
+    ///
+    /// Head sampling decides before a trace fails, so at low ratios most failed traces are
+    /// lost. With tail sampling, spans that were not sampled are still recorded and buffered
+    /// per trace, within the [`TailSampling`] window and memory bounds. Once a span with an
+    /// error status ends, the buffered spans of its trace and its later spans are exported as
+    /// sampled. Recording every span costs CPU and memory, and the decision is local: other
+    /// services were told the trace is not sampled. Defaults to `None`.
+    pub tail_sampling: Option<TailSampling>,
+    /// Whether HTTP/2 keep-alive pings are sent on idle exporter connections.
+    ///
+    /// Defaults to tonic's behavior (disabled) when `None`.
+    pub keep_alive_while_idle: Option<bool>,
+    /// Interval between HTTP/2 keep-alive pings on the exporter connection.
+    ///
+    /// Defaults to tonic's behavior (no pings) when `None`.
+    #[cfg_attr(feature = "serde", serde(with = "duration_serde::option"))]
+    pub http2_keep_alive_interval: Option<Duration>,
+    /// Time to wait for a keep-alive ping acknowledgement before closing the connection.
+    ///
+    /// Defaults to tonic's behavior (20 seconds) when `None`.
+    #[cfg_attr(feature = "serde", serde(with = "duration_serde::option"))]
+    pub keep_alive_timeout: Option<Duration>,
+    /// Optional transform applied to every span before it is exported.
+    ///
+    /// Use it to redact or drop noisy, high-cardinality or sensitive attributes. The transform
+    /// runs when a span ends, before the span is queued by the batch processor, so it sees
+    /// every sampled span and its changes are what the collector receives.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub span_transform: Option<SpanTransform>,
+    /// Spans ending sooner than this after they started are not exported.
+    ///
+    /// Spans with an error status are always exported. The filter runs locally when spans end
+    /// and does not affect sampling, so exported spans may reference dropped parents. Defaults
+    /// to `None`, exporting every sampled span.
+    #[cfg_attr(feature = "serde", serde(with = "duration_serde::option"))]
+    pub min_span_duration: Option<Duration>,
+    /// Names of spans that are not exported, where `*` matches any sequence of characters.
+    ///
+    /// Drops spans of instrumented dependencies, e.g. `pool.acquire` or `sqlx::*`, from export
+    /// without affecting logs, unlike `RUST_LOG`. The filter runs first when a span ends, before
+    /// `min_span_duration`, and drops matching spans even when they failed. Children of dropped
+    /// spans are still exported. Defaults to empty, exporting spans of every name.
+    pub excluded_span_names: Vec<String>,
+    /// Baggage keys recorded as attributes on every span whose context carries them.
+    ///
+    /// Makes entries set upstream with W3C baggage, such as `tenant_id`, filterable on every
+    /// span of the service. Attributes are named after the key and set when spans start. Each
+    /// key costs a lookup per span, so keep the list short. Defaults to empty.
+    pub baggage_attributes: Vec<String>,
+    /// Maximum length in bytes of string attribute values exported on spans and events.
+    ///
+    /// Longer values are cut and end with `...[truncated]`, so an accidentally recorded payload
+    /// cannot inflate export costs or overwhelm the collector. Truncation runs after
+    /// `span_transform`. Defaults to 4096, `None` exports values unchanged.
+    pub max_attribute_length: Option<usize>,
+    /// Maximum length in bytes of the status description of failed spans.
+    ///
+    /// Failed requests are described by their error, or by the reason phrase of their status
+    /// code, and spans passed to [`record_error`] by the error. Longer descriptions are cut and
+    /// end with `...[truncated]`. Defaults to 1024, `None` keeps descriptions unchanged.
+    pub max_status_message_length: Option<usize>,
+    /// Schema URL of the resource, naming the semantic conventions version it follows.
+    ///
+    /// Some backends reject resources without one. Defaults to [`DEFAULT_SCHEMA_URL`], `None`
+    /// leaves the resource without a schema URL.
+    pub resource_schema_url: Option<String>,
+    /// Whether `init` adds Kubernetes resource attributes from downward API variables.
+    ///
+    /// Reads `POD_NAME`, `POD_UID`, `POD_NAMESPACE`, `NODE_NAME` and `DEPLOYMENT_NAME` and
+    /// records them as `k8s.pod.name`, `k8s.pod.uid`, `k8s.namespace.name`, `k8s.node.name`
+    /// and `k8s.deployment.name`. Unset variables are skipped. Defaults to `false`.
+    pub detect_k8s: bool,
+    /// Whether `init` installs a panic hook recording panics on the current span.
+    ///
+    /// Panics are recorded as error events with `exception.message` and
+    /// `exception.stacktrace`, and with the `metrics` feature counted by `process.panics`.
+    /// The previously installed hook still runs. Defaults to `false`.
+    pub record_panics: bool,
+    /// Maximum number of spans waiting for export, per endpoint.
+    ///
+    /// Includes spans queued in the batch processor and spans of batches being exported. When
+    /// the collector falls behind and the limit is hit, ending spans are dropped and counted,
+    /// see [`TelemetryGuard::dropped_spans`]. Defaults to `None`, using the SDK's queue size:
+    /// `OTEL_BSP_MAX_QUEUE_SIZE` when set, or else 2048.
+    pub max_queue_size: Option<usize>,
+    /// Number of queued spans that starts an export right away, per endpoint.
+    ///
+    /// Spans are otherwise exported every 5 seconds, or once 512 are queued, which delays
+    /// traces of services that produce a burst of spans and then go idle. A low watermark gets
+    /// spans to the collector promptly during bursts, at the cost of more and smaller export
+    /// requests. Values above `max_queue_size` are capped to it. Defaults to `None`, keeping the
+    /// SDK batch size.
+    pub flush_watermark: Option<usize>,
+    /// Maximum number of export requests in flight at once, per endpoint.
+    ///
+    /// Keeps a collector limiting concurrent gRPC streams from rejecting exports. Spans of
+    /// in-flight batches count towards `max_queue_size`, so under sustained load the queue
+    /// fills up and spans are dropped once exports cannot keep up. Values below 1 are raised
+    /// to 1. Defaults to `None`, keeping the SDK's limit of one export at a time unless
+    /// `OTEL_BSP_MAX_CONCURRENT_EXPORTS` is set.
+    pub max_concurrent_exports: Option<usize>,
+    /// Whether `init` fails instead of warning about a misconfiguration.
+    ///
+    /// In strict mode, `init` checks the following before installing anything, in this order:
+    ///
+    /// - The name recorded as `service.name`, `service_name` or else `app_name`, is not empty,
+    ///   does not equal `env` and is not an environment name such as `production`, since
+    ///   traces are hard to identify in the backend otherwise. Fails with
+    ///   [`TelemetryError::InvalidAppName`].
+    /// - `RUST_LOG`, when set, parses as filter directives. Otherwise `init` falls back to
+    ///   `info`. Fails with [`TelemetryError::InvalidFilter`].
+    /// - With the `otlp` feature, every entry of `headers`, `trace_headers` and
+    ///   `metric_headers` can be sent as gRPC metadata. Otherwise the header is skipped.
+    ///   Fails with [`TelemetryError::InvalidHeader`].
+    /// - With the `otlp` feature, the trace, additional and metrics endpoints accept a
+    ///   connection within 3 seconds. Otherwise exports fail until the endpoint comes up.
+    ///   Fails with [`TelemetryError::UnreachableEndpoint`]. The check blocks the calling
+    ///   thread while connecting.
+    ///
+    /// Without strict mode, each of these is logged as a warning once `init` installed the
+    /// subscriber, except unreachable endpoints, which are reported by export failures. Invalid
+    /// `trace_filter` and `recent_logs` filters, malformed endpoint URLs and an unreachable
+    /// syslog socket fail `init` in either mode. Defaults to `false`.
+    pub strict: bool,
+    /// Maximum time dropping the [`TelemetryGuard`] waits for spans to be flushed.
+    ///
+    /// Defaults to 5 seconds. When the deadline is hit, unexported spans are abandoned and a
+    /// warning is logged, so an unreachable collector cannot stall shutdown.
+    #[cfg_attr(feature = "serde", serde(with = "duration_serde"))]
+    pub shutdown_timeout: Duration,
+    /// Async runtime used by the batch span processor and the periodic metric reader.
+    ///
+    /// Defaults to [`ExportRuntime::TokioCurrentThread`].
+    pub runtime: ExportRuntime,
+    /// Whether process metrics are exported alongside custom metrics.
+    ///
+    /// Registers `process.memory.usage` and `process.open_file_descriptor.count` gauges (read
+    /// on Linux only) and, with the `tokio-metrics` feature, gauges for the tokio runtime `init`
+    /// runs on. Requires an OTLP endpoint. Defaults to `false`.
+    #[cfg(feature = "metrics")]
+    pub process_metrics: bool,
+    /// How often process metrics are sampled. Defaults to 10 seconds.
+    #[cfg(feature = "metrics")]
+    #[cfg_attr(feature = "serde", serde(with = "duration_serde"))]
+    pub process_metrics_interval: Duration,
+    /// How often the periodic reader exports metrics to the collector.
+    ///
+    /// Defaults to 60 seconds, the SDK default. Short-lived jobs can lower it, or call
+    /// [`TelemetryGuard::flush`] before exiting to push the final values.
+    #[cfg(feature = "metrics")]
+    #[cfg_attr(feature = "serde", serde(with = "duration_serde"))]
+    pub metric_export_interval: Duration,
+    /// Retries metric exports that failed, waiting longer before each retry.
+    ///
+    /// Only the interval being exported is retried, so memory stays bounded by one interval of
+    /// data, but an outage longer than the retries still loses that interval and increments
+    /// the `telemetry.metric_exports.failed` counter. With the default cumulative temporality,
+    /// counters and histograms catch up with the next successful export, only their resolution
+    /// is lost, while gauges lose the values of the interval. Retries delay the next export
+    /// and stop after [`MetricExportRetry::max_elapsed`], before the periodic reader would
+    /// cancel the export. Defaults to `None`, exporting each interval once.
+    #[cfg(feature = "metrics")]
+    pub metric_export_retry: Option<MetricExportRetry>,
+    /// Bucket boundaries, in seconds, of the `http.server.duration` histogram.
+    ///
+    /// The histogram is recorded by [`CustomLogger`] for every request outside the excluded
+    /// routes. The boundaries are applied through a view, so they also apply to a histogram of
+    /// that name recorded by the application. Boundaries must be strictly increasing, otherwise
+    /// `init` fails. Defaults to `None`, keeping the SDK boundaries.
+    #[cfg(feature = "metrics")]
+    pub http_duration_buckets: Option<Vec<f64>>,
+    /// OTLP endpoint metrics are exported to, when it differs from the trace endpoint.
+    ///
+    /// Defaults to `None`, exporting metrics to the resolved `endpoint_url`. When set, metrics
+    /// are exported even without a trace endpoint.
+    #[cfg(feature = "metrics")]
+    pub metrics_endpoint_url: Option<String>,
+    /// Headers sent with metric exports only, replacing `headers` entries of the same name.
+    ///
+    /// Lets metrics authenticate to another backend than traces. Defaults to empty.
+    #[cfg(feature = "metrics")]
+    pub metric_headers: HashMap<String, String>,
+    /// Sends logs to the local syslog daemon, in addition to stdout.
+    ///
+    /// Each event is sent as one datagram prefixed with its priority, combining the configured
+    /// facility with a severity mapped from the level: `ERROR` to `err`, `WARN` to `warning`,
+    /// `INFO` to `notice`, `DEBUG` to `info` and `TRACE` to `debug`. Set `stdout_logging` to
+    /// `false` to make syslog the only log sink. `init` fails with [`TelemetryError::Syslog`]
+    /// when the socket cannot be connected. Defaults to `None`.
+    #[cfg(feature = "syslog")]
+    pub syslog: Option<SyslogConfig>,
+    /// Keeps the most recent log lines in memory, returned by [`recent_logs`].
+    ///
+    /// The buffer has its own filter, so it can hold `debug` events leading up to an error
+    /// while only `info` logs are written. `RUST_LOG` then applies to the other sinks only.
+    /// `init` fails with [`TelemetryError::InvalidFilter`] when the buffer filter is invalid.
+    /// Defaults to `None`, buffering nothing.
+    pub recent_logs: Option<RecentLogs>,
+}
+
+impl Default for TelemetryConfig {
+    fn default() -> Self {
+        Self {
+            app_name: String::new(),
+            service_name: None,
+            env: String::new(),
+            endpoint_url: None,
+            endpoint_template: None,
+            additional_endpoint_urls: Vec::new(),
+            headers: HashMap::new(),
+            trace_headers: HashMap::new(),
+            tracer_id: None,
+            stdout_logging: true,
+            trace_filter: None,
+            environment: None,
+            ansi: None,
+            timestamp_format: TimestampFormat::default(),
+            log_format: None,
+            log_source_location: None,
+            log_trace_ids: LogTraceIds::default(),
+            log_span_durations: false,
+            sample_logs: false,
+            sampler: Sampler::default(),
+            custom_sampler: None,
+            id_generator: None,
+            tail_sampling: None,
+            keep_alive_while_idle: None,
+            http2_keep_alive_interval: None,
+            keep_alive_timeout: None,
+            span_transform: None,
+            min_span_duration: None,
+            excluded_span_names: Vec::new(),
+            baggage_attributes: Vec::new(),
+            max_attribute_length: Some(4096),
+            max_status_message_length: Some(status::DEFAULT_MAX_STATUS_MESSAGE_LENGTH),
+            resource_schema_url: Some(DEFAULT_SCHEMA_URL.to_string()),
+            detect_k8s: false,
+            record_panics: false,
+            max_queue_size: None,
+            flush_watermark: None,
+            max_concurrent_exports: None,
+            strict: false,
+            shutdown_timeout: DEFAULT_SHUTDOWN_TIMEOUT,
+            runtime: ExportRuntime::default(),
+            #[cfg(feature = "metrics")]
+            process_metrics: false,
+            #[cfg(feature = "metrics")]
+            process_metrics_interval: Duration::from_secs(10),
+            #[cfg(feature = "metrics")]
+            metric_export_interval: Duration::from_secs(60),
+            #[cfg(feature = "metrics")]
+            metric_export_retry: None,
+            #[cfg(feature = "metrics")]
+            http_duration_buckets: None,
+            #[cfg(feature = "metrics")]
+            metrics_endpoint_url: None,
+            #[cfg(feature = "metrics")]
+            metric_headers: HashMap::new(),
+            #[cfg(feature = "syslog")]
+            syslog: None,
+            recent_logs: None,
+        }
+    }
+}
+
+/// Default for [`TelemetryConfig::resource_schema_url`].
+///
+/// The semantic conventions version whose attribute names the crate records, such as
+/// `client.address` and `user_agent.original`.
+pub const DEFAULT_SCHEMA_URL: &str = "https://opentelemetry.io/schemas/1.21.0";
+
+/// Default for [`TelemetryConfig::shutdown_timeout`].
+const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Async runtime the exporters run their background work on.
+///
+/// The runtime must match how the application is executed. Picking `Tokio` on anything other
+/// than a multi-threaded Tokio runtime can stall exports, and flushing from within a
+/// current-thread runtime may deadlock.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum ExportRuntime {
+    /// Runs exports on a dedicated background thread with its own current-thread runtime.
+    ///
+    /// Works with any executor, including actix-web's per-worker runtimes, at the cost of a
+    /// single export thread.
+    #[default]
+    TokioCurrentThread,
+    /// Spawns exports onto the multi-threaded Tokio runtime `init` is called from.
+    ///
+    /// Use this for services running on `#[tokio::main]` with the multi-threaded flavor, where
+    /// the single export thread of `TokioCurrentThread` can become a bottleneck under load.
+    Tokio,
+}
+
+/// Format of the timestamps written by the stdout logger.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum TimestampFormat {
+    /// RFC 3339 in UTC with microsecond precision, e.g. `2024-05-01T12:00:00.123456Z`.
+    #[default]
+    Rfc3339,
+    /// RFC 3339 in UTC with nanosecond precision, e.g. `2024-05-01T12:00:00.123456789Z`.
+    Rfc3339Nanos,
+    /// Milliseconds since the Unix epoch, e.g. `1714564800123`.
+    ///
+    /// JSON logs write the value as a string, like the other timestamp formats.
+    UnixMillis,
+}
+
+/// Format of the logs written to stdout.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum LogFormat {
+    /// Compact human-readable lines, the default in [`Environment::Development`].
+    Compact,
+    /// JSON objects, the default in [`Environment::Production`].
+    Json,
+    /// Space separated `key=value` pairs, for pipelines that only parse logfmt.
+    ///
+    /// Each line starts with `ts`, `level` and `target`, followed by the trace ids selected by
+    /// [`LogTraceIds`] inside a span, `msg` and the event fields. Values containing spaces,
+    /// quotes, `=` or control characters are quoted and escaped.
+    Logfmt,
+}
+
+/// Trace and span id fields added to JSON and logfmt logs emitted inside a span.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum LogTraceIds {
+    /// Hex `trace_id` and `span_id` fields, as used by OpenTelemetry.
+    #[default]
+    OpenTelemetry,
+    /// Decimal `dd.trace_id` and `dd.span_id` fields, as expected by Datadog.
+    ///
+    /// `dd.trace_id` holds the lower 64 bits of the trace id, see
+    /// [`propagation::datadog_ids`].
+    Datadog,
+    /// Both the OpenTelemetry and the Datadog fields.
+    Both,
+}
+
+/// Environment used to select the log format.
+///
+/// `Development` produces compact human-readable logs, while `Production` produces JSON logs
+/// suitable for log aggregation systems.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum Environment {
+    /// Compact, human-readable logs.
+    Development,
+    /// Structured JSON logs.
+    Production,
+}
+
+/// Error returned when parsing an unrecognized [`Environment`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseEnvironmentError(String);
+
+impl std::fmt::Display for ParseEnvironmentError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "unrecognized environment `{}`", self.0)
+    }
+}
+
+impl std::error::Error for ParseEnvironmentError {}
+
+impl FromStr for Environment {
+    type Err = ParseEnvironmentError;
+
+    /// Parses an environment from common aliases, ignoring case and surrounding whitespace.
+    ///
+    /// `development`, `dev`, `local` and `test` map to `Development`, while `production`,
+    /// `prod`, `staging` and `stage` map to `Production`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "development" | "dev" | "local" | "test" => Ok(Environment::Development),
+            "production" | "prod" | "staging" | "stage" => Ok(Environment::Production),
+            _ => Err(ParseEnvironmentError(s.to_string())),
+        }
+    }
 }
 
 /// Trait to initialize telemetry based on the provided configuration.
@@ -54,10 +656,91 @@ pub trait TelemetryInit {
     ///
     /// This method sets up the global tracer provider, OTLP exporter (if specified),
     /// and logger based on the environment.
-    async fn init(&self) -> Result<(), Box<dyn std::error::Error>>;
+    ///
+    /// The returned [`TelemetryGuard`] flushes and shuts down telemetry when dropped, so it
+    /// should be held until the application exits.
+    async fn init(&self) -> Result<TelemetryGuard, Box<dyn std::error::Error>>;
+
+    /// Initializes telemetry like [`TelemetryInit::init`], adding `layers` to the subscriber.
+    ///
+    /// The extra layers sit directly on top of the `Registry`, below the OpenTelemetry and
+    /// stdout layers, and are subject to the same `EnvFilter`. Use this to plug in integrations
+    /// the crate does not provide, such as error reporting layers.
+    ///
+    /// # Arguments
+    ///
+    /// * `layers` - Additional layers to install in the global subscriber.
+    async fn init_with_layers(
+        &self,
+        layers: Vec<BoxedLayer>,
+    ) -> Result<TelemetryGuard, Box<dyn std::error::Error>>;
 }
 
+/// A type-erased layer that can be passed to [`TelemetryInit::init_with_layers`].
+pub type BoxedLayer = Box<dyn Layer<Registry> + Send + Sync>;
+
 impl TelemetryConfig {
+    /// Creates a config for local development, logging compact lines to stdout.
+    ///
+    /// No exporter is configured, so spans are only visible through their log events.
+    ///
+    /// # Arguments
+    ///
+    /// * `app_name` - The name of the application, recorded as `service.name`.
+    pub fn development(app_name: &str) -> Self {
+        Self {
+            app_name: app_name.to_string(),
+            env: "development".to_string(),
+            environment: Some(Environment::Development),
+            log_format: Some(LogFormat::Compact),
+            ..Default::default()
+        }
+    }
+
+    /// Creates a config for production, logging JSON lines and exporting spans over OTLP.
+    ///
+    /// Continues the sampling decision of incoming requests and samples every new trace, and
+    /// registers a global tracer named after the application.
+    ///
+    /// # Arguments
+    ///
+    /// * `app_name` - The name of the application, recorded as `service.name`.
+    /// * `endpoint_url` - The OTLP endpoint of the collector.
+    pub fn production(app_name: &str, endpoint_url: &str) -> Self {
+        Self {
+            app_name: app_name.to_string(),
+            env: "production".to_string(),
+            endpoint_url: Some(endpoint_url.to_string()),
+            tracer_id: Some(app_name.to_string()),
+            environment: Some(Environment::Production),
+            log_format: Some(LogFormat::Json),
+            sampler: Sampler::ParentBased(Box::new(Sampler::AlwaysOn)),
+            ..Default::default()
+        }
+    }
+
+    /// Creates a config exporting every span to a collector running locally.
+    ///
+    /// Meant for inspecting traces during development, e.g. with a Jaeger all-in-one
+    /// container: logs are compact and every span is sampled, whatever the caller decided.
+    ///
+    /// # Arguments
+    ///
+    /// * `app_name` - The name of the application, recorded as `service.name`.
+    /// * `endpoint_url` - The OTLP endpoint of the local collector, e.g. `http://localhost:4317`.
+    pub fn local_otlp(app_name: &str, endpoint_url: &str) -> Self {
+        Self {
+            app_name: app_name.to_string(),
+            env: "local".to_string(),
+            endpoint_url: Some(endpoint_url.to_string()),
+            tracer_id: Some(app_name.to_string()),
+            environment: Some(Environment::Development),
+            log_format: Some(LogFormat::Compact),
+            sampler: Sampler::AlwaysOn,
+            ..Default::default()
+        }
+    }
+
     /// Retrieves the current trace ID.
     ///
     /// This method fetches the trace ID from the current span context.
@@ -71,71 +754,360 @@ impl TelemetryConfig {
             .span_context()
             .trace_id()
     }
+
+    /// Resolves the environment used to select the log format.
+    ///
+    /// Uses `environment` when set, otherwise parses `env`, falling back to `Production`.
+    pub fn environment(&self) -> Environment {
+        self.environment
+            .or_else(|| self.env.parse().ok())
+            .unwrap_or(Environment::Production)
+    }
+
+    /// Resolves the format of stdout logs.
+    ///
+    /// Uses `log_format` when set, otherwise derives it from [`TelemetryConfig::environment`].
+    pub fn log_format(&self) -> LogFormat {
+        self.log_format.unwrap_or_else(|| match self.environment() {
+            Environment::Development => LogFormat::Compact,
+            Environment::Production => LogFormat::Json,
+        })
+    }
+
+    /// Resolves whether logs include the source location of events.
+    ///
+    /// Uses `log_source_location` when set, otherwise only includes it in
+    /// [`Environment::Development`].
+    pub fn log_source_location(&self) -> bool {
+        self.log_source_location
+            .unwrap_or(self.environment() == Environment::Development)
+    }
+
+    /// Resolves the OTLP endpoint, from `endpoint_url` or else from `endpoint_template`.
+    pub fn resolved_endpoint_url(&self) -> Option<String> {
+        if let Some(endpoint_url) = &self.endpoint_url {
+            return Some(endpoint_url.clone());
+        }
+        let template = self.endpoint_template.as_ref()?;
+        (!self.env.is_empty()).then(|| template.replace("{env}", &self.env))
+    }
+
+    /// Resolves the OTLP endpoint of metrics, from `metrics_endpoint_url` or else like traces.
+    #[cfg(feature = "metrics")]
+    pub fn resolved_metrics_endpoint_url(&self) -> Option<String> {
+        self.metrics_endpoint_url
+            .clone()
+            .or_else(|| self.resolved_endpoint_url())
+    }
+
+    /// Returns the name recorded as `service.name`, `service_name` or else `app_name`.
+    pub fn service_name(&self) -> &str {
+        self.service_name.as_deref().unwrap_or(&self.app_name)
+    }
+
+    /// Returns why the service name looks misconfigured, if it does.
+    fn service_name_problem(&self) -> Option<&'static str> {
+        let service_name = self.service_name().trim();
+        if service_name.is_empty() {
+            Some("it is empty")
+        } else if service_name.eq_ignore_ascii_case(self.env.trim()) {
+            Some("it equals `env`")
+        } else if service_name.parse::<Environment>().is_ok() {
+            Some("it is an environment name")
+        } else {
+            None
+        }
+    }
+
+    /// Runs the checks of strict mode, see [`TelemetryConfig::strict`].
+    fn check_strict(&self) -> Result<(), TelemetryError> {
+        if let Some(reason) = self.service_name_problem() {
+            return Err(TelemetryError::InvalidAppName {
+                app_name: self.service_name().to_string(),
+                reason,
+            });
+        }
+        effective::check_env_filter()?;
+        #[cfg(feature = "otlp")]
+        {
+            self.check_headers()?;
+            self.check_endpoints_reachable()?;
+        }
+        Ok(())
+    }
+
+    /// Builds the resource describing the service, shared by traces and metrics.
+    fn resource(&self) -> Resource {
+        let mut attributes = vec![KeyValue::new(
+            "service.name",
+            self.service_name().to_string(),
+        )];
+        if !self.env.is_empty() {
+            attributes.push(KeyValue::new("deployment.environment", self.env.clone()));
+        }
+        if self.detect_k8s {
+            attributes.extend(resource::k8s_attributes(|var| std::env::var(var).ok()));
+        }
+        match &self.resource_schema_url {
+            Some(schema_url) => Resource::from_schema_url(attributes, schema_url.clone()),
+            None => Resource::new(attributes),
+        }
+    }
+
+    /// Builds the tracer provider configuration with the configured sampler and `resource`.
+    fn trace_config(&self, resource: Resource) -> trace::Config {
+        let sampler = match &self.custom_sampler {
+            Some(sampler) => sampler.clone(),
+            None => Box::new(self.sampler.to_sdk()),
+        };
+        let sampler: Box<dyn trace::ShouldSample> = match self.tail_sampling {
+            Some(_) => Box::new(sampling::RecordUnsampled(sampler)),
+            None => sampler,
+        };
+        let config = trace::config()
+            .with_sampler(sampling::ForceableSampler(sampler))
+            .with_resource(resource);
+        match &self.id_generator {
+            Some(id_generator) => config.with_id_generator(SharedIdGenerator(id_generator.clone())),
+            None => config,
+        }
+    }
+
+    /// Builds the stdout fmt layer for the configured log format.
+    ///
+    /// Returns `None` when an exporter or syslog is in use and `stdout_logging` is disabled.
+    /// Without another sink stdout is the only one, so the layer is always returned.
+    fn stdout_layer<S>(&self, other_sink: bool) -> Option<Box<dyn Layer<S> + Send + Sync>>
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        if other_sink && !self.stdout_logging {
+            return None;
+        }
+        let source_location = self.log_source_location();
+        let layer = match self.log_format() {
+            LogFormat::Compact => {
+                let ansi = ansi_enabled(
+                    self.ansi,
+                    std::env::var_os("NO_COLOR"),
+                    std::io::stdout().is_terminal(),
+                );
+                fmt::layer()
+                    .compact()
+                    .with_ansi(ansi)
+                    .with_timer(Timestamp(self.timestamp_format))
+                    .with_file(source_location)
+                    .with_line_number(source_location)
+                    .boxed()
+            }
+            LogFormat::Json => fmt::layer()
+                .json()
+                .with_span_events(FmtSpan::NONE)
+                .event_format(TraceContextJson(
+                    fmt::format()
+                        .json()
+                        .with_timer(Timestamp(self.timestamp_format))
+                        .with_file(source_location)
+                        .with_line_number(source_location),
+                    self.log_trace_ids,
+                ))
+                .boxed(),
+            LogFormat::Logfmt => fmt::layer()
+                .with_ansi(false)
+                .event_format(
+                    Logfmt::new(self.timestamp_format, self.log_trace_ids)
+                        .with_source_location(source_location),
+                )
+                .boxed(),
+        };
+        Some(layer)
+    }
+}
+
+/// Resolves whether development logs are colored.
+///
+/// An explicit setting wins. Otherwise colors follow <https://no-color.org/> and are only used
+/// when writing to a terminal.
+fn ansi_enabled(forced: Option<bool>, no_color: Option<OsString>, is_terminal: bool) -> bool {
+    forced.unwrap_or_else(|| no_color.map_or(true, |value| value.is_empty()) && is_terminal)
+}
+
+/// Id generator shared between the clones of a [`TelemetryConfig`].
+#[derive(Debug)]
+struct SharedIdGenerator(Arc<dyn trace::IdGenerator>);
+
+impl trace::IdGenerator for SharedIdGenerator {
+    fn new_trace_id(&self) -> opentelemetry::trace::TraceId {
+        self.0.new_trace_id()
+    }
+
+    fn new_span_id(&self) -> opentelemetry::trace::SpanId {
+        self.0.new_span_id()
+    }
+}
+
+/// Without the `otlp` feature no exporter is available, so traces are never exported.
+#[cfg(not(feature = "otlp"))]
+impl TelemetryConfig {
+    fn tracer_pipeline(
+        &self,
+        _trace_config: trace::Config,
+        _resource: &resource::SharedResource,
+        _dropped: &Arc<std::sync::atomic::AtomicU64>,
+    ) -> Result<Option<(trace::TracerProvider, trace::Tracer)>, TelemetryError> {
+        Ok(None)
+    }
 }
 
 /// Initializes telemetry based on the provided configuration.
 ///
 /// This method will:
-/// - Set the global text map propagator to `TraceContextPropagator`.
-/// - Check for an OTLP endpoint and set up the OTLP exporter if present.
-/// - Configure a logger based on the environment (`development` or other).
+/// - Set the global text map propagator to W3C trace context composed with W3C baggage.
+/// - Check for an OTLP endpoint and set up the OTLP exporter if present, applying
+///   `span_transform` to each span before it reaches the batch processor.
+/// - With the `metrics` feature, install a global OTLP meter provider for the same endpoint,
+///   optionally reporting process metrics.
+/// - Configure a logger based on the resolved [`Environment`], unless
+///   `stdout_logging` is disabled and an OTLP exporter or syslog is in use.
+/// - Install the global subscriber, including any layers passed to `init_with_layers`.
 /// - Optionally, set a global tracer if `tracer_id` is provided.
+/// - Emit an `info` event summarizing the effective configuration.
+/// - Return a [`TelemetryGuard`] that flushes and shuts down the exporter on drop.
 #[async_trait]
 impl TelemetryInit for TelemetryConfig {
-    async fn init(&self) -> Result<(), Box<dyn std::error::Error>> {
-        let env_filter =
-            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
-        let sampler = trace::Sampler::AlwaysOn;
-        let resource = Resource::new(vec![KeyValue::new("service.name", self.app_name.clone())]);
-        let trace_config = trace::config()
-            .with_sampler(sampler)
-            .with_resource(resource);
-        global::set_text_map_propagator(TraceContextPropagator::new());
+    async fn init(&self) -> Result<TelemetryGuard, Box<dyn std::error::Error>> {
+        self.init_with_layers(Vec::new()).await
+    }
+
+    async fn init_with_layers(
+        &self,
+        layers: Vec<BoxedLayer>,
+    ) -> Result<TelemetryGuard, Box<dyn std::error::Error>> {
+        self.install(layers)
+    }
+}
+
+impl TelemetryConfig {
+    /// Initializes telemetry like [`TelemetryInit::init`], without being awaited.
+    ///
+    /// Meant for CLI tools and other binaries without an async runtime. Stdout logging needs
+    /// no runtime, but the OTLP exporter does: when an endpoint is configured, this must be
+    /// called from within a Tokio runtime, otherwise [`TelemetryError::MissingRuntime`] is
+    /// returned before anything is installed.
+    pub fn init_blocking(&self) -> Result<TelemetryGuard, Box<dyn std::error::Error>> {
+        #[cfg(feature = "otlp")]
+        if self.resolved_endpoint_url().is_some() && tokio::runtime::Handle::try_current().is_err()
+        {
+            return Err(TelemetryError::MissingRuntime.into());
+        }
+        self.install(Vec::new())
+    }
+
+    /// Builds the pipelines and installs the global subscriber, shared by all init methods.
+    fn install(
+        &self,
+        layers: Vec<BoxedLayer>,
+    ) -> Result<TelemetryGuard, Box<dyn std::error::Error>> {
+        let service_name_problem = self.service_name_problem();
+        if self.strict {
+            self.check_strict()?;
+        }
+        let env_filter = effective::env_filter();
+        #[cfg(all(unix, feature = "signals"))]
+        let (env_filter, log_filter) = {
+            let initial = env_filter.to_string();
+            let (env_filter, handle) = tracing_subscriber::reload::Layer::new(env_filter);
+            (env_filter, signals::LogFilterHandle::new(initial, handle))
+        };
+        status::set_max_status_message_length(self.max_status_message_length);
+        let resource = self.resource();
+        let trace_config = self.trace_config(resource.clone());
+        global::set_text_map_propagator(propagation::propagator());
 
         // Check if OPENTELEMERTY_OTLP_ENDPOINT is set, if not enable standard logger
-        match &self.endpoint_url {
+        let shared_resource = resource::SharedResource::new(resource.clone());
+        let dropped_spans = Arc::default();
+        let (provider, tracer) = self
+            .tracer_pipeline(trace_config, &shared_resource, &dropped_spans)?
+            .unzip();
+        let log_sampling = tracer
+            .as_ref()
+            .filter(|_| self.sample_logs)
+            .map(|tracer| sampling::SampledLogsFilter(tracer.clone()));
+        let telemetry = tracer.map(|tracer| tracing_opentelemetry::layer().with_tracer(tracer));
+        // With its own filter, the OpenTelemetry layer is moved out of the sinks filtered by
+        // `RUST_LOG`.
+        let (telemetry, filtered_telemetry) = match &self.trace_filter {
+            Some(directives) => {
+                let trace_filter = effective::parse_filter(directives)?;
+                (None, telemetry.map(|layer| layer.with_filter(trace_filter)))
+            }
+            None => (telemetry, None),
+        };
+
+        #[cfg(feature = "metrics")]
+        let (meter_provider, process_metrics) = match self.resolved_metrics_endpoint_url() {
             Some(endpoint_url) => {
-                let exporter = opentelemetry_otlp::new_exporter()
-                    .tonic()
-                    .with_endpoint(endpoint_url);
-                let tracer = opentelemetry_otlp::new_pipeline()
-                    .tracing()
-                    .with_exporter(exporter)
-                    .with_trace_config(trace_config)
-                    .install_batch(TokioCurrentThread)?;
-                let telemetry = tracing_opentelemetry::layer().with_tracer(tracer);
-                if self.env == "development" {
-                    let logger = fmt::layer().compact();
-                    let subscriber = Registry::default()
-                        .with(telemetry)
-                        .with(logger)
-                        .with(env_filter);
-                    tracing::subscriber::set_global_default(subscriber)
-                        .expect("setting default subscriber failed");
+                let meter_provider = metrics::meter_provider(
+                    self.otlp_exporter(&endpoint_url)?
+                        .with_metadata(otlp::metadata(&self.headers, &self.metric_headers)),
+                    resource,
+                    self.runtime,
+                    self.metric_export_interval,
+                    self.http_duration_buckets.as_deref(),
+                    self.metric_export_retry,
+                )?;
+                global::set_meter_provider(meter_provider.clone());
+                metrics::register_telemetry_counters(&meter_provider, &dropped_spans);
+                let process_metrics = if self.process_metrics {
+                    Some(metrics::ProcessMetrics::start(
+                        &meter_provider,
+                        self.process_metrics_interval,
+                    )?)
                 } else {
-                    let subscriber = Registry::default()
-                        .with(telemetry)
-                        .with(env_filter)
-                        .with(fmt::layer().json().with_span_events(FmtSpan::NONE));
-                    tracing::subscriber::set_global_default(subscriber)
-                        .expect("setting default subscriber failed");
+                    None
                 };
+                (Some(meter_provider), process_metrics)
             }
-            None => {
-                if self.env == "development" {
-                    let logger = fmt::layer().compact();
-                    let subscriber = Registry::default().with(logger).with(env_filter);
-                    tracing::subscriber::set_global_default(subscriber)
-                        .expect("setting default subscriber failed");
-                } else {
-                    let subscriber = Registry::default()
-                        .with(fmt::layer().json().with_span_events(FmtSpan::NONE))
-                        .with(env_filter);
-                    tracing::subscriber::set_global_default(subscriber)
-                        .expect("setting default subscriber failed");
-                }
-            }
-        }
+            None => (None, None),
+        };
+
+        #[cfg(feature = "syslog")]
+        let syslog = match &self.syslog {
+            Some(config) => Some(syslog::layer(config, self.service_name())?),
+            None => None,
+        };
+        #[cfg(not(feature = "syslog"))]
+        let syslog: Option<tracing_subscriber::layer::Identity> = None;
+
+        let logger = self
+            .stdout_layer(telemetry.is_some() || filtered_telemetry.is_some() || syslog.is_some());
+        #[cfg(feature = "metrics")]
+        let counting = metrics::CountingLayer;
+        #[cfg(not(feature = "metrics"))]
+        let counting = tracing_subscriber::layer::Identity::new();
+        let span_durations = self
+            .log_span_durations
+            .then_some(span_durations::SpanDurationLayer);
+        let recent_logs = match &self.recent_logs {
+            Some(config) => Some(recent_logs::layer(config, self.timestamp_format)?),
+            None => None,
+        };
+        // `RUST_LOG` only applies to the sinks, so the buffer and an exporter with its own
+        // filter can keep events it rejects.
+        // Log sampling applies to the log sinks only, not to exported spans and events.
+        let logs = Layer::and_then(logger, syslog).with_filter(log_sampling);
+        let sinks = layers
+            .and_then(counting)
+            .and_then(telemetry)
+            .and_then(logs)
+            .and_then(span_durations)
+            .with_filter(env_filter)
+            .and_then(filtered_telemetry);
+        let subscriber = Registry::default().with(sinks).with(recent_logs);
+        tracing::subscriber::set_global_default(subscriber)
+            .expect("setting default subscriber failed");
+
         if let Some(tracer_id) = &self.tracer_id {
             let name: Cow<'static, str> = tracer_id.to_string().into();
             global::tracer(name);
@@ -145,7 +1117,54 @@ impl TelemetryInit for TelemetryConfig {
         // uses this crate, then if they use the log crate, they will get
         // the logs printed into the tracing session.
         tracing_log::LogTracer::init()?;
-        Ok(())
+
+        if self.record_panics {
+            panic::install_panic_hook();
+        }
+
+        #[cfg(feature = "otlp")]
+        for (name, reason) in self.invalid_headers() {
+            tracing::warn!(header = %name, "skipping invalid OTLP header: {reason}");
+        }
+
+        if let Err(err) = effective::check_env_filter() {
+            tracing::warn!("{err}, falling back to `info`");
+        }
+
+        if let Some(reason) = service_name_problem {
+            tracing::warn!(
+                service_name = %self.service_name(),
+                "the service name looks misconfigured ({reason}), traces will be hard to \
+                 identify by `service.name`"
+            );
+        }
+
+        #[cfg(not(feature = "otlp"))]
+        if self.resolved_endpoint_url().is_some() {
+            tracing::warn!("`endpoint_url` is ignored because the `otlp` feature is disabled");
+        }
+
+        // Record the effective configuration once the subscriber is installed. The endpoint
+        // itself is not logged since it may embed credentials.
+        tracing::info!(
+            app_name = %self.app_name,
+            service_name = %self.service_name(),
+            env = %self.env,
+            otlp_enabled = provider.is_some(),
+            sampler = ?self.sampler,
+            custom_sampler = self.custom_sampler.as_ref().map(tracing::field::debug),
+            protocol = "grpc",
+            stdout_logging = self.stdout_logging,
+            "telemetry initialized"
+        );
+        let guard = TelemetryGuard::new(provider, self.shutdown_timeout)
+            .with_resource(shared_resource)
+            .with_dropped_spans(dropped_spans);
+        #[cfg(feature = "metrics")]
+        let guard = guard.with_metrics(meter_provider, process_metrics);
+        #[cfg(all(unix, feature = "signals"))]
+        let guard = guard.with_log_filter(log_filter);
+        Ok(guard)
     }
 }
 
@@ -154,12 +1173,72 @@ thread_local! {
     ///
     /// Contains a list of routes (endpoints) that should not be logged.
     static EXCLUDED_ROUTES: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+
+    /// Thread-local flag controlling whether `X-Forwarded-For` is trusted.
+    ///
+    /// When set, the first address in `X-Forwarded-For` is used as `client.address`.
+    static TRUST_FORWARDED_FOR: Cell<bool> = const { Cell::new(false) };
+
+    /// Thread-local storage for per-route sampling ratios.
+    ///
+    /// Requests to these routes only create a root span with the given probability.
+    static SAMPLED_ROUTES: RefCell<Vec<(String, f64)>> = const { RefCell::new(Vec::new()) };
+
+    /// Thread-local request counter used to seed per-request sampling decisions.
+    ///
+    /// Starts at the seed set by [`CustomFilterRootSpanBuilder::set_sample_seed`], which
+    /// [`CustomLogger`] offsets per worker so workers do not replay the same decisions.
+    static SAMPLE_SEED: Cell<u64> = const { Cell::new(0) };
+
+    /// Thread-local lowest response status code that marks a root span as an error.
+    static ERROR_STATUS_THRESHOLD: Cell<u16> = const { Cell::new(500) };
+
+    /// Thread-local level at which root spans are created.
+    static ROOT_SPAN_LEVEL: Cell<Level> = const { Cell::new(Level::INFO) };
+
+    /// Thread-local storage for routes whose successful requests are not exported.
+    static DISCARD_ON_SUCCESS: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+
+    /// Thread-local flag controlling whether an access log event is emitted per request.
+    static ACCESS_LOG: Cell<bool> = const { Cell::new(false) };
+
+    /// Thread-local flag controlling whether requests complete once their body is written.
+    static TRACE_RESPONSE_BODY: Cell<bool> = const { Cell::new(false) };
+
+    /// Thread-local function deciding whether a request continues its incoming trace context.
+    ///
+    /// When unset, every request continues the trace context it carries.
+    static PARENT_POLICY: RefCell<Option<RequestParentPolicy>> = const { RefCell::new(None) };
+
+    /// Thread-local function returning attributes recorded on root spans before sampling.
+    static ROOT_SPAN_ATTRIBUTES: RefCell<Option<RequestAttributes>> = const { RefCell::new(None) };
+}
+
+/// Predicate evaluated against an incoming request.
+pub type RequestPredicate = Arc<dyn Fn(&ServiceRequest) -> bool + Send + Sync>;
+
+/// Function deriving span attributes from an incoming request.
+pub type RequestAttributes = Arc<dyn Fn(&ServiceRequest) -> Vec<KeyValue> + Send + Sync>;
+
+/// Function deciding the [`ParentPolicy`] of an incoming request.
+pub type RequestParentPolicy = Arc<dyn Fn(&ServiceRequest) -> ParentPolicy + Send + Sync>;
+
+/// Whether the root span of a request continues the trace context the request carries.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ParentPolicy {
+    /// Continue the incoming trace, including its sampled flag. The default.
+    #[default]
+    Continue,
+    /// Start a new trace, sampled by the configured sampler, linking to the remote span.
+    NewRoot,
 }
 
 /// Custom root span builder that allows for filtering out specific routes.
 ///
 /// This builder will check if a request's path is in the list of excluded routes,
-/// and if so, it won't log that request.
+/// and if so, it won't log that request. Root spans additionally record `client.address`
+/// and `user_agent.original` following the OpenTelemetry semantic conventions. Requests that
+/// match no route record [`NOT_FOUND_ROUTE`] as `http.route`.
 pub struct CustomFilterRootSpanBuilder;
 
 impl CustomFilterRootSpanBuilder {
@@ -173,37 +1252,440 @@ impl CustomFilterRootSpanBuilder {
             *excluded.borrow_mut() = routes;
         });
     }
-}
 
-impl RootSpanBuilder for CustomFilterRootSpanBuilder {
-    fn on_request_start(request: &ServiceRequest) -> Span {
-        let should_exclude = EXCLUDED_ROUTES
-            .with(|excluded| excluded.borrow().contains(&request.path().to_string()));
+    /// Sets whether the `X-Forwarded-For` header is honored for `client.address`.
+    ///
+    /// Only enable this when the service runs behind a proxy that overwrites the header,
+    /// otherwise clients can spoof their address.
+    ///
+    /// # Arguments
+    ///
+    /// * `trust` - Whether to trust `X-Forwarded-For`.
+    pub fn set_trust_forwarded_for(trust: bool) {
+        TRUST_FORWARDED_FOR.with(|trusted| trusted.set(trust));
+    }
 
-        if should_exclude {
-            Span::none()
-        } else {
-            tracing_actix_web::root_span!(level = tracing::Level::INFO, request)
-        }
+    /// Sets the per-route sampling ratios.
+    ///
+    /// # Arguments
+    ///
+    /// * `routes` - A list of route paths and the ratio (between `0.0` and `1.0`) of requests
+    ///   to them that should create a root span.
+    pub fn set_sampled_routes(routes: Vec<(String, f64)>) {
+        SAMPLED_ROUTES.with(|sampled| {
+            *sampled.borrow_mut() = routes;
+        });
     }
 
-    fn on_request_end<B: MessageBody>(span: Span, outcome: &Result<ServiceResponse<B>, Error>) {
-        DefaultRootSpanBuilder::on_request_end(span, outcome);
+    /// Sets the seed of the sampling decisions of sampled routes on the current thread.
+    ///
+    /// Each request to a sampled route draws the next seed of the sequence, so the decisions
+    /// are reproducible for a given seed and request order.
+    ///
+    /// # Arguments
+    ///
+    /// * `seed` - The seed of the next sampled request.
+    pub fn set_sample_seed(seed: u64) {
+        SAMPLE_SEED.with(|current| current.set(seed));
     }
-}
 
-/// Builder for creating a custom logging middleware.
-///
-/// This builder provides methods to specify which routes to exclude from logging.
-pub struct CustomLoggerBuilder {
-    excluded_routes: Vec<String>,
-}
+    /// Sets the level at which root spans are created.
+    ///
+    /// # Arguments
+    ///
+    /// * `level` - The root span level.
+    pub fn set_root_span_level(level: Level) {
+        ROOT_SPAN_LEVEL.with(|root_level| root_level.set(level));
+    }
 
-impl CustomLoggerBuilder {
+    /// Sets the lowest response status code that marks a root span as an error.
+    ///
+    /// # Arguments
+    ///
+    /// * `status` - Responses with this status code or above set the span status to error.
+    pub fn set_error_status_threshold(status: StatusCode) {
+        ERROR_STATUS_THRESHOLD.with(|threshold| threshold.set(status.as_u16()));
+    }
+
+    /// Sets the predicate selecting requests whose incoming trace context is continued.
+    ///
+    /// # Arguments
+    ///
+    /// * `predicate` - Returns `true` for trusted requests. `None` trusts every request.
+    pub fn set_trusted_parent(predicate: Option<RequestPredicate>) {
+        Self::set_parent_policy(predicate.map(trusted_parent_policy));
+    }
+
+    /// Sets the function deciding whether a request continues its incoming trace context.
+    ///
+    /// # Arguments
+    ///
+    /// * `policy` - Returns the policy of a request. `None` continues every trace.
+    pub fn set_parent_policy(policy: Option<RequestParentPolicy>) {
+        PARENT_POLICY.with(|parent_policy| {
+            *parent_policy.borrow_mut() = policy;
+        });
+    }
+
+    /// Sets the function returning attributes recorded on root spans before they are sampled.
+    ///
+    /// # Arguments
+    ///
+    /// * `attributes` - Derives the attributes from the request. `None` adds no attributes.
+    pub fn set_root_span_attributes(attributes: Option<RequestAttributes>) {
+        ROOT_SPAN_ATTRIBUTES.with(|root_attributes| {
+            *root_attributes.borrow_mut() = attributes;
+        });
+    }
+
+    /// Sets whether an access log event is emitted for each completed request.
+    ///
+    /// # Arguments
+    ///
+    /// * `enabled` - Whether to emit access log events.
+    pub fn set_access_log(enabled: bool) {
+        ACCESS_LOG.with(|access_log| access_log.set(enabled));
+    }
+
+    /// Sets whether requests are completed once their response body has been written.
+    ///
+    /// # Arguments
+    ///
+    /// * `enabled` - Whether to trace the response body.
+    pub fn set_trace_response_body(enabled: bool) {
+        TRACE_RESPONSE_BODY.with(|trace_body| trace_body.set(enabled));
+    }
+
+    /// Sets the routes whose successful requests are not exported.
+    ///
+    /// # Arguments
+    ///
+    /// * `routes` - A list of route paths whose traces are only exported on failure.
+    pub fn set_discard_on_success(routes: Vec<String>) {
+        DISCARD_ON_SUCCESS.with(|discarded| {
+            *discarded.borrow_mut() = routes;
+        });
+    }
+
+    /// Returns whether successful requests to `path` are not exported.
+    fn discards_on_success(path: &str) -> bool {
+        DISCARD_ON_SUCCESS.with(|discarded| discarded.borrow().iter().any(|route| route == path))
+    }
+
+    /// Returns whether any route discards its successful requests.
+    fn any_discard_on_success() -> bool {
+        DISCARD_ON_SUCCESS.with(|discarded| !discarded.borrow().is_empty())
+    }
+
+    /// Returns whether access log events are emitted.
+    pub(crate) fn access_log_enabled() -> bool {
+        ACCESS_LOG.with(Cell::get)
+    }
+
+    /// Returns whether requests are completed once their response body has been written.
+    pub(crate) fn trace_response_body_enabled() -> bool {
+        TRACE_RESPONSE_BODY.with(Cell::get)
+    }
+
+    /// Returns whether requests to `path` are excluded from logging.
+    pub(crate) fn is_excluded(path: &str) -> bool {
+        EXCLUDED_ROUTES.with(|excluded| excluded.borrow().iter().any(|route| route == path))
+    }
+
+    /// Returns whether the trace context carried by `request` should be continued.
+    fn parent_policy(request: &ServiceRequest) -> ParentPolicy {
+        PARENT_POLICY.with(|parent_policy| {
+            parent_policy
+                .borrow()
+                .as_ref()
+                .map_or(ParentPolicy::Continue, |policy| policy(request))
+        })
+    }
+
+    /// Returns the attributes configured for the root span of `request`.
+    fn root_span_attributes(request: &ServiceRequest) -> Vec<KeyValue> {
+        ROOT_SPAN_ATTRIBUTES.with(|attributes| {
+            attributes
+                .borrow()
+                .as_ref()
+                .map_or_else(Vec::new, |attributes| attributes(request))
+        })
+    }
+
+    /// Adds `attributes` to `span` and discards its sampling decision.
+    ///
+    /// `root_span!` samples the span as soon as it is created, so the sampler runs again with
+    /// the attributes the next time the span context is read.
+    fn resample_with(span: &Span, attributes: Vec<KeyValue>) {
+        sampling::with_otel_data(span, |data| {
+            data.builder
+                .attributes
+                .get_or_insert_with(Vec::new)
+                .extend(attributes);
+            data.builder.sampling_result = None;
+        });
+    }
+
+    /// Starts a new trace for `span` instead of continuing the remote parent it was given.
+    ///
+    /// The remote span is kept as a link, so the traces can still be correlated without
+    /// letting the caller decide whether the request is sampled.
+    fn detach_remote_parent(span: &Span, remote: SpanContext) {
+        span.set_parent(opentelemetry::Context::new());
+        span.add_link(remote);
+        let trace_id = span.context().span().span_context().trace_id();
+        span.record("trace_id", tracing::field::display(trace_id));
+    }
+
+    /// Returns whether a response with `status` marks the root span as an error.
+    fn is_error_status(status: StatusCode) -> bool {
+        status.as_u16() >= ERROR_STATUS_THRESHOLD.with(Cell::get)
+    }
+
+    /// Describes a failed request by its error, or else by the reason phrase of `status`.
+    fn error_message<B>(status: StatusCode, outcome: &Result<ServiceResponse<B>, Error>) -> String {
+        let error = match outcome {
+            Ok(response) => response.response().error(),
+            Err(error) => Some(error),
+        };
+        match error {
+            Some(error) => error.to_string(),
+            None => status
+                .canonical_reason()
+                .map_or_else(|| status.to_string(), str::to_string),
+        }
+    }
+
+    /// Decides whether a request should create a root span based on its route's sampling ratio.
+    ///
+    /// Requests to routes without a configured ratio are always sampled. Each request draws a
+    /// new seed from a per-thread counter, so decisions are reproducible for a given sequence,
+    /// see [`CustomLoggerBuilder::sample_seed`].
+    fn route_sampled(request: &ServiceRequest) -> bool {
+        let ratio = SAMPLED_ROUTES.with(|sampled| {
+            sampled
+                .borrow()
+                .iter()
+                .find(|(route, _)| route == request.path())
+                .map(|(_, ratio)| *ratio)
+        });
+        match ratio {
+            Some(ratio) => {
+                let seed = SAMPLE_SEED.with(|seed| {
+                    let current = seed.get();
+                    seed.set(current.wrapping_add(1));
+                    current
+                });
+                sample_with_seed(ratio, seed)
+            }
+            None => true,
+        }
+    }
+
+    /// Resolves the client address for a request.
+    ///
+    /// Uses the first entry of `X-Forwarded-For` when trusted, falling back to the peer address.
+    fn client_address(request: &ServiceRequest) -> String {
+        if TRUST_FORWARDED_FOR.with(Cell::get) {
+            let forwarded = request
+                .headers()
+                .get("X-Forwarded-For")
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.split(',').next())
+                .map(str::trim)
+                .filter(|client| !client.is_empty());
+            if let Some(client) = forwarded {
+                return client.to_string();
+            }
+        }
+        request
+            .peer_addr()
+            .map(|addr| addr.ip().to_string())
+            .unwrap_or_default()
+    }
+}
+
+/// Returns whether a root span at `level` can be enabled under the `max` level filter.
+fn root_span_enabled(level: Level, max: LevelFilter) -> bool {
+    level <= max
+}
+
+/// Increment between consecutive SplitMix64 states.
+pub(crate) const SPLITMIX64_GAMMA: u64 = 0x9E37_79B9_7F4A_7C15;
+
+/// Returns the SplitMix64 output following `state`.
+///
+/// Consecutive states produce well distributed, uncorrelated values.
+pub(crate) fn splitmix64(state: u64) -> u64 {
+    let mut z = state.wrapping_add(SPLITMIX64_GAMMA);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+/// Makes a deterministic sampling decision for `ratio` from `seed`.
+///
+/// The seed is mixed with [`splitmix64`] so consecutive seeds produce well distributed values.
+fn sample_with_seed(ratio: f64, seed: u64) -> bool {
+    let z = splitmix64(seed);
+    // Use the top 53 bits to build a uniform value in [0, 1).
+    let value = (z >> 11) as f64 / (1u64 << 53) as f64;
+    value < ratio
+}
+
+/// Value recorded as `http.route` for requests that do not match any route.
+///
+/// Using a fixed sentinel instead of the raw path keeps the span name and route low
+/// cardinality when scanners probe random paths.
+pub const NOT_FOUND_ROUTE: &str = "NOT_FOUND";
+
+/// Request wrapper resolving unmatched routes to [`NOT_FOUND_ROUTE`].
+///
+/// `root_span!` derives `http.route` and the span name from `match_pattern()`. This wrapper
+/// shadows that method and dereferences to the request for everything else.
+struct RoutedRequest<'a>(&'a ServiceRequest);
+
+impl RoutedRequest<'_> {
+    fn match_pattern(&self) -> Option<String> {
+        Some(
+            self.0
+                .match_pattern()
+                .unwrap_or_else(|| NOT_FOUND_ROUTE.to_string()),
+        )
+    }
+}
+
+impl std::ops::Deref for RoutedRequest<'_> {
+    type Target = ServiceRequest;
+
+    fn deref(&self) -> &Self::Target {
+        self.0
+    }
+}
+
+impl RootSpanBuilder for CustomFilterRootSpanBuilder {
+    fn on_request_start(request: &ServiceRequest) -> Span {
+        // Bail out before any per-request work when the filter disables the root span level,
+        // e.g. with `RUST_LOG=warn`. The span macro still applies per-target directives.
+        let level = ROOT_SPAN_LEVEL.with(Cell::get);
+        if !root_span_enabled(level, LevelFilter::current()) {
+            return Span::none();
+        }
+
+        if Self::is_excluded(request.path()) || !Self::route_sampled(request) {
+            Span::none()
+        } else {
+            let client_address = Self::client_address(request);
+            let user_agent = request
+                .headers()
+                .get("User-Agent")
+                .and_then(|value| value.to_str().ok())
+                .unwrap_or("");
+            let untrusted_parent = if Self::parent_policy(request) == ParentPolicy::Continue {
+                None
+            } else {
+                let remote =
+                    propagation::extract_context(&propagation::HeaderExtractor(request.headers()));
+                let remote = remote.span().span_context().clone();
+                remote.is_valid().then_some(remote)
+            };
+            let discard_on_success = Self::discards_on_success(request.path());
+            let attributes = Self::root_span_attributes(request);
+            let request = &RoutedRequest(request);
+            let span = tracing_actix_web::root_span!(
+                level = level,
+                request,
+                client.address = %client_address,
+                user_agent.original = %user_agent
+            );
+            if !attributes.is_empty() {
+                Self::resample_with(&span, attributes);
+            }
+            if let Some(remote) = untrusted_parent {
+                Self::detach_remote_parent(&span, remote);
+            }
+            if discard_on_success {
+                // Hold back the spans of the trace until the response status is known.
+                let span_context = span.context().span().span_context().clone();
+                if span_context.is_sampled() {
+                    processors::defer_trace(span_context.trace_id(), span_context.span_id());
+                }
+            }
+            span
+        }
+    }
+
+    fn on_request_end<B: MessageBody>(span: Span, outcome: &Result<ServiceResponse<B>, Error>) {
+        DefaultRootSpanBuilder::on_request_end(span.clone(), outcome);
+
+        // The default builder only marks server errors, so override the status using the
+        // configured threshold.
+        let status = match outcome {
+            Ok(response) => response.status(),
+            Err(error) => error.as_response_error().status_code(),
+        };
+        if Self::is_error_status(status) {
+            span.record("otel.status_code", "ERROR");
+            status::set_error_status(&span, &Self::error_message(status, outcome));
+        } else {
+            span.record("otel.status_code", "OK");
+        }
+
+        if outcome.is_ok()
+            && status.is_success()
+            && !Self::is_error_status(status)
+            && Self::any_discard_on_success()
+        {
+            let trace_id = span.context().span().span_context().trace_id();
+            processors::discard_deferred_trace(trace_id);
+        }
+    }
+}
+
+/// Continues the trace context of requests accepted by `predicate` only.
+fn trusted_parent_policy(predicate: RequestPredicate) -> RequestParentPolicy {
+    Arc::new(move |request| {
+        if predicate(request) {
+            ParentPolicy::Continue
+        } else {
+            ParentPolicy::NewRoot
+        }
+    })
+}
+
+/// Builder for creating a custom logging middleware.
+///
+/// This builder provides methods to specify which routes to exclude from logging.
+#[derive(Clone)]
+pub struct CustomLoggerBuilder {
+    excluded_routes: Vec<String>,
+    sampled_routes: Vec<(String, f64)>,
+    trust_forwarded_for: bool,
+    root_span_level: Level,
+    error_status_threshold: StatusCode,
+    parent_policy: Option<RequestParentPolicy>,
+    access_log: bool,
+    trace_response_body: bool,
+    discard_on_success: Vec<String>,
+    root_span_attributes: Option<RequestAttributes>,
+    sample_seed: u64,
+}
+
+impl CustomLoggerBuilder {
     /// Creates a new instance of `CustomLoggerBuilder` with no excluded routes.
     pub fn new() -> Self {
         Self {
             excluded_routes: Vec::new(),
+            sampled_routes: Vec::new(),
+            trust_forwarded_for: false,
+            root_span_level: Level::INFO,
+            error_status_threshold: StatusCode::INTERNAL_SERVER_ERROR,
+            parent_policy: None,
+            access_log: false,
+            trace_response_body: false,
+            discard_on_success: Vec::new(),
+            root_span_attributes: None,
+            sample_seed: 0,
         }
     }
 
@@ -217,15 +1699,211 @@ impl CustomLoggerBuilder {
         self
     }
 
+    /// Specifies a route whose requests only create a root span with the given probability.
+    ///
+    /// This is useful for noisy endpoints such as health checks, where a small fraction of
+    /// traces is enough to confirm the endpoint is alive. Excluded routes are never sampled.
+    ///
+    /// # Arguments
+    ///
+    /// * `route` - The path of the route to sample.
+    /// * `ratio` - The ratio of requests to sample, clamped between `0.0` and `1.0`.
+    pub fn sample_route(mut self, route: &str, ratio: f64) -> Self {
+        self.sampled_routes
+            .push((route.to_string(), ratio.clamp(0.0, 1.0)));
+        self
+    }
+
+    /// Sets the seed of the decisions made for routes added with
+    /// [`sample_route`](Self::sample_route).
+    ///
+    /// Each actix worker draws its decisions from its own sequence, starting at `seed` offset by
+    /// the number of workers instantiated before it, so workers do not replay each other's
+    /// decisions. The sequence is reproducible for a given seed and request order, e.g. to
+    /// assert which requests of a test are sampled. Defaults to `0`.
+    ///
+    /// # Arguments
+    ///
+    /// * `seed` - The seed of the first worker's sequence.
+    pub fn sample_seed(mut self, seed: u64) -> Self {
+        self.sample_seed = seed;
+        self
+    }
+
+    /// Specifies a route whose traces are only exported when the request fails.
+    ///
+    /// For chatty but healthy endpoints, where only failures are worth keeping. The status of
+    /// a request is only known once its response is ready, after the child spans have ended,
+    /// so the spans of sampled requests to this route are held back until the root span ends.
+    /// They are then dropped if the response has a 2xx status below the error threshold, and
+    /// exported otherwise. Spans ending after the root span are exported as usual.
+    ///
+    /// # Arguments
+    ///
+    /// * `route` - The path of the route whose successful requests are not exported.
+    pub fn discard_on_success(mut self, route: &str) -> Self {
+        self.discard_on_success.push(route.to_string());
+        self
+    }
+
+    /// Specifies whether `X-Forwarded-For` is honored when recording `client.address`.
+    ///
+    /// Defaults to `false`, in which case the peer address of the connection is used.
+    ///
+    /// # Arguments
+    ///
+    /// * `trust` - Whether the service runs behind a trusted proxy.
+    pub fn trust_forwarded_for(mut self, trust: bool) -> Self {
+        self.trust_forwarded_for = trust;
+        self
+    }
+
+    /// Specifies the level at which root spans are created.
+    ///
+    /// Defaults to `INFO`. Root spans are skipped entirely when the configured `EnvFilter`
+    /// disables this level, so turning logging down also removes the root span overhead.
+    ///
+    /// # Arguments
+    ///
+    /// * `level` - The root span level.
+    pub fn root_span_level(mut self, level: Level) -> Self {
+        self.root_span_level = level;
+        self
+    }
+
+    /// Specifies the lowest response status code that marks the root span as an error.
+    ///
+    /// Defaults to `500`, so only server errors are reported as errors. Use `400` to also
+    /// treat client errors such as `404 Not Found` as errors.
+    ///
+    /// # Arguments
+    ///
+    /// * `status` - Responses with this status code or above set the span status to error.
+    pub fn error_status_threshold(mut self, status: StatusCode) -> Self {
+        self.error_status_threshold = status;
+        self
+    }
+
+    /// Only continues the incoming trace context of requests matching `predicate`.
+    ///
+    /// By default every request continues the `traceparent` it carries, including its sampled
+    /// flag, which lets public clients force traces to be recorded. Requests rejected by the
+    /// predicate start a new root trace instead, sampled by the configured sampler, and link
+    /// to the remote span for correlation.
+    ///
+    /// # Arguments
+    ///
+    /// * `predicate` - Returns `true` for requests from trusted callers, e.g. based on a
+    ///   header set by an internal gateway.
+    pub fn trust_parent_if<F>(mut self, predicate: F) -> Self
+    where
+        F: Fn(&ServiceRequest) -> bool + Send + Sync + 'static,
+    {
+        self.parent_policy = Some(trusted_parent_policy(Arc::new(predicate)));
+        self
+    }
+
+    /// Decides per request whether its root span continues the incoming trace context.
+    ///
+    /// Generalizes [`trust_parent_if`](Self::trust_parent_if). Requests for which `policy`
+    /// returns [`ParentPolicy::NewRoot`] start a new root trace, sampled by the configured
+    /// sampler, and link to the remote span for correlation. Defaults to
+    /// [`ParentPolicy::Continue`] for every request.
+    ///
+    /// # Arguments
+    ///
+    /// * `policy` - Decides the policy of a request, e.g. from the network it arrived on or
+    ///   whether it was authenticated by an internal gateway.
+    pub fn parent_policy<F>(mut self, policy: F) -> Self
+    where
+        F: Fn(&ServiceRequest) -> ParentPolicy + Send + Sync + 'static,
+    {
+        self.parent_policy = Some(Arc::new(policy));
+        self
+    }
+
+    /// Records attributes derived from each request on its root span, before it is sampled.
+    ///
+    /// Root spans are sampled as soon as they are created, before the handler runs, so this is
+    /// the only way for attributes such as `tenant.tier` to be seen by a custom sampler, see
+    /// `TelemetryConfig::custom_sampler`. The attributes are also exported with the span. When
+    /// they are not empty, the sampler runs a second time, so `Sampler::RateLimited` counts
+    /// these root spans twice.
+    ///
+    /// # Arguments
+    ///
+    /// * `attributes` - Derives the attributes from the request, e.g. from a header set by an
+    ///   authenticating gateway.
+    pub fn root_span_attributes<F>(mut self, attributes: F) -> Self
+    where
+        F: Fn(&ServiceRequest) -> Vec<KeyValue> + Send + Sync + 'static,
+    {
+        self.root_span_attributes = Some(Arc::new(attributes));
+        self
+    }
+
+    /// Specifies whether an access log event is emitted for each completed request.
+    ///
+    /// Defaults to `false`. The `INFO` event is emitted once the response is ready, with the
+    /// [`ACCESS_LOG_TARGET`](middleware::ACCESS_LOG_TARGET) target and a fixed set of fields:
+    /// `method`, `path`, `status`, `duration_ms`, `bytes`, `client_ip` and `correlation_id`.
+    /// Excluded routes are not logged.
+    ///
+    /// # Arguments
+    ///
+    /// * `enabled` - Whether to emit access log events.
+    pub fn access_log(mut self, enabled: bool) -> Self {
+        self.access_log = enabled;
+        self
+    }
+
+    /// Specifies whether requests are completed once their response body has been written.
+    ///
+    /// Defaults to `false`, completing requests when the handler returns their response: the
+    /// access log event is emitted before a streamed body is sent, without `bytes`, and the
+    /// body size is not recorded. When enabled, the root span stays open until the last chunk
+    /// is written and records the bytes written as `http.response.body.size`, and the access
+    /// log event is emitted at the same time, with the `duration_ms` and `bytes` of the whole
+    /// transfer. Use it for streaming and server-sent events responses. A body dropped early,
+    /// e.g. when the client disconnects, completes the request with the bytes written so far.
+    ///
+    /// # Arguments
+    ///
+    /// * `enabled` - Whether to trace the response body.
+    pub fn trace_response_body(mut self, enabled: bool) -> Self {
+        self.trace_response_body = enabled;
+        self
+    }
+
     /// Builds and returns a custom logging middleware.
     ///
     /// This middleware will use `CustomFilterRootSpanBuilder` to filter out the specified routes.
-    pub fn build(self) -> TracingLogger<CustomFilterRootSpanBuilder> {
-        // Set the excluded routes for our custom builder
-        CustomFilterRootSpanBuilder::set_excluded_routes(self.excluded_routes);
+    /// Handlers of excluded routes run with tracing disabled, see [`CustomLogger`].
+    pub fn build(self) -> CustomLogger {
+        self.apply(0);
+
+        // Return a TracingLogger with our custom builder, bypassed for excluded routes
+        CustomLogger::new(Arc::new(self))
+    }
 
-        // Return a TracingLogger with our custom builder
-        TracingLogger::<CustomFilterRootSpanBuilder>::new()
+    /// Configures `CustomFilterRootSpanBuilder` on the current thread with these settings.
+    ///
+    /// The settings are thread-local, so [`CustomLogger`] applies them again on every worker
+    /// thread it is instantiated on. Applying them twice replaces the previous settings.
+    /// `worker` numbers the workers, each of which gets its own sequence of sampling seeds.
+    pub(crate) fn apply(&self, worker: u64) {
+        CustomFilterRootSpanBuilder::set_excluded_routes(self.excluded_routes.clone());
+        CustomFilterRootSpanBuilder::set_sampled_routes(self.sampled_routes.clone());
+        CustomFilterRootSpanBuilder::set_trust_forwarded_for(self.trust_forwarded_for);
+        CustomFilterRootSpanBuilder::set_root_span_level(self.root_span_level);
+        CustomFilterRootSpanBuilder::set_error_status_threshold(self.error_status_threshold);
+        CustomFilterRootSpanBuilder::set_parent_policy(self.parent_policy.clone());
+        CustomFilterRootSpanBuilder::set_access_log(self.access_log);
+        CustomFilterRootSpanBuilder::set_trace_response_body(self.trace_response_body);
+        CustomFilterRootSpanBuilder::set_discard_on_success(self.discard_on_success.clone());
+        CustomFilterRootSpanBuilder::set_root_span_attributes(self.root_span_attributes.clone());
+        // Workers far enough apart for their sequences to never overlap in practice.
+        CustomFilterRootSpanBuilder::set_sample_seed(self.sample_seed.wrapping_add(worker << 32));
     }
 }
 
@@ -242,6 +1920,198 @@ pub fn get_tracing_logger() -> CustomLoggerBuilder {
     CustomLoggerBuilder::new()
 }
 
+/// Returns a tracer with its own instrumentation scope from the global tracer provider.
+///
+/// Call it after `init` so the tracer is backed by the installed provider. Every tracer shares
+/// the same sampler, span processors and OTLP exporter, only the reported scope name and
+/// version differ, which lets backends filter spans by the library that created them.
+///
+/// # Arguments
+///
+/// * `name` - The instrumentation scope name, usually the library's crate name.
+/// * `version` - The instrumentation scope version, usually the library's crate version.
+pub fn get_tracer(
+    name: impl Into<Cow<'static, str>>,
+    version: impl Into<Cow<'static, str>>,
+) -> global::BoxedTracer {
+    scoped_tracer(&global::tracer_provider(), name, version)
+}
+
+/// Creates and enters an `INFO` span named `name` with the given attributes.
+///
+/// The span is a child of the current span, so it joins the trace of the request being
+/// handled. `name` is exported as the OpenTelemetry span name and `attrs` as span attributes;
+/// stdout logs show the span as `scope` with an `otel.name` field. The span ends when the
+/// returned guard is dropped.
+///
+/// # Arguments
+///
+/// * `name` - The span name, e.g. `"load_config"`.
+/// * `attrs` - Attribute keys and values to record on the span.
+pub fn span_scope(name: &str, attrs: &[(&str, &str)]) -> tracing::span::EnteredSpan {
+    let span = tracing::info_span!("scope", otel.name = %name);
+    for (key, value) in attrs {
+        span.set_attribute(key.to_string(), value.to_string());
+    }
+    span.entered()
+}
+
+/// Sets string attributes on the current span.
+///
+/// Unlike `Span::record`, the keys do not need to be declared when the span is created: the
+/// attributes are set on the OpenTelemetry span directly, so they are exported but not shown
+/// in stdout logs, which only print declared fields. An attribute set twice keeps the last
+/// value. Does nothing outside a span or without the OpenTelemetry layer installed by `init`.
+///
+/// # Arguments
+///
+/// * `attrs` - Attribute keys and values to set, e.g. `&[("tenant.id", "acme")]`.
+pub fn record_attrs(attrs: &[(&str, &str)]) {
+    record_key_values(
+        attrs
+            .iter()
+            .map(|(key, value)| KeyValue::new(key.to_string(), value.to_string())),
+    );
+}
+
+/// Sets typed attributes on the current span, like [`record_attrs`].
+///
+/// Keeps numbers and booleans typed in the exported span, e.g.
+/// `record_key_values([KeyValue::new("retry.count", 3)])`.
+///
+/// # Arguments
+///
+/// * `attrs` - Attributes to set.
+pub fn record_key_values(attrs: impl IntoIterator<Item = KeyValue>) {
+    sampling::with_otel_data(&Span::current(), |data| {
+        let attributes = data.builder.attributes.get_or_insert_with(Vec::new);
+        for attribute in attrs {
+            match attributes.iter_mut().find(|kv| kv.key == attribute.key) {
+                Some(existing) => existing.value = attribute.value,
+                None => attributes.push(attribute),
+            }
+        }
+    });
+}
+
+/// Creates an `INFO` span named `name` linked to each of the given contexts.
+///
+/// Use it for fan-in work, such as processing a batch of messages that each originate from a
+/// different trace: the span stays a child of the current span and links to the originating
+/// spans instead of picking one of them as its parent. Contexts without a valid span are
+/// skipped. Enter the returned span or instrument a future with it.
+///
+/// Contexts sent along with messages can be read with [`propagation::extract_context`], e.g.
+/// from a `HashMap<String, String>` of message headers.
+///
+/// # Arguments
+///
+/// * `name` - The span name, e.g. `"process_batch"`.
+/// * `links` - The contexts of the spans to link to.
+pub fn span_with_links(
+    name: &str,
+    links: impl IntoIterator<Item = opentelemetry::Context>,
+) -> Span {
+    let span = tracing::info_span!("scope", otel.name = %name);
+    for cx in links {
+        let span_context = cx.span().span_context().clone();
+        if span_context.is_valid() {
+            span.add_link(span_context);
+        }
+    }
+    span
+}
+
+/// Exports a span named `name` that ended now and lasted `duration`.
+///
+/// For integration points where the tracing macros cannot be used, such as FFI callbacks
+/// reporting an operation after the fact. The span is created and ended on the global tracer
+/// provider installed by `init`, bypassing the tracing subscriber: it is not written to the
+/// logs and ignores `RUST_LOG` and `trace_filter`, but it is still sampled and goes through
+/// the span processors, such as `span_transform`. It is a child of the current tracing span,
+/// or of the current OpenTelemetry context outside tracing spans, and starts a new trace when
+/// neither exists. Returns the context of the span, e.g. to link other spans to it.
+///
+/// # Arguments
+///
+/// * `name` - The span name.
+/// * `attrs` - Attributes recorded on the span.
+/// * `duration` - How long the operation took, used to derive the start time.
+pub fn emit_span(
+    name: impl Into<Cow<'static, str>>,
+    attrs: impl IntoIterator<Item = KeyValue>,
+    duration: Duration,
+) -> SpanContext {
+    let tracer = get_tracer(env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+    emit_span_on(&tracer, name.into(), attrs.into_iter().collect(), duration)
+}
+
+/// Creates and ends the span of [`emit_span`] on `tracer`.
+fn emit_span_on<T: opentelemetry::trace::Tracer>(
+    tracer: &T,
+    name: Cow<'static, str>,
+    attrs: Vec<KeyValue>,
+    duration: Duration,
+) -> SpanContext {
+    use opentelemetry::trace::Span as _;
+
+    let current = Span::current();
+    let parent = if current.is_none() {
+        opentelemetry::Context::current()
+    } else {
+        current.context()
+    };
+    let end = SystemTime::now();
+    let mut span = tracer
+        .span_builder(name)
+        .with_start_time(end.checked_sub(duration).unwrap_or(end))
+        .with_attributes(attrs)
+        .start_with_context(tracer, &parent);
+    let span_context = span.span_context().clone();
+    span.end_with_timestamp(end);
+    span_context
+}
+
+/// Awaits `future` and records its error, if any, on the current span.
+///
+/// An `Err` is passed to [`record_error`], which emits an `exception` event and sets an error
+/// status carrying the error message. `Ok` results leave the span status untouched. The result
+/// is returned unchanged, so handlers can wrap their body without further changes:
+///
+/// ```rust,ignore
+/// async fn handler() -> Result<HttpResponse, MyError> {
+///     tembo_telemetry::instrument_result(async {
+///         let bucket = load_bucket().await?;
+///         Ok(HttpResponse::Ok().json(bucket))
+///     })
+///     .await
+/// }
+/// ```
+///
+/// # Arguments
+///
+/// * `future` - The future producing the result to record.
+pub async fn instrument_result<F, T, E>(future: F) -> Result<T, E>
+where
+    F: std::future::Future<Output = Result<T, E>>,
+    E: std::fmt::Display,
+{
+    let result = future.await;
+    if let Err(error) = &result {
+        record_error(&Span::current(), error);
+    }
+    result
+}
+
+/// Builds a tracer for the `name` and `version` scope from `provider`.
+fn scoped_tracer<P: opentelemetry::trace::TracerProvider>(
+    provider: &P,
+    name: impl Into<Cow<'static, str>>,
+    version: impl Into<Cow<'static, str>>,
+) -> P::Tracer {
+    provider.tracer_builder(name).with_version(version).build()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -253,7 +2123,100 @@ mod tests {
         assert_eq!(config.app_name, "");
         assert_eq!(config.env, "");
         assert!(config.endpoint_url.is_none());
+        assert!(config.endpoint_template.is_none());
+        assert_eq!(config.max_attribute_length, Some(4096));
+        assert_eq!(config.max_status_message_length, Some(1024));
+        assert_eq!(
+            config.resource_schema_url.as_deref(),
+            Some(DEFAULT_SCHEMA_URL)
+        );
+        assert!(config.additional_endpoint_urls.is_empty());
+        assert!(config.headers.is_empty());
+        assert!(config.trace_headers.is_empty());
         assert!(config.tracer_id.is_none());
+        assert!(config.stdout_logging);
+        assert!(config.environment.is_none());
+        assert!(config.ansi.is_none());
+        assert_eq!(config.timestamp_format, TimestampFormat::Rfc3339);
+        assert!(config.log_format.is_none());
+        assert!(config.log_source_location.is_none());
+        assert_eq!(config.log_trace_ids, LogTraceIds::OpenTelemetry);
+        assert!(!config.log_span_durations);
+        assert!(!config.sample_logs);
+        assert_eq!(
+            config.sampler,
+            Sampler::ParentBased(Box::new(Sampler::AlwaysOn))
+        );
+        assert!(config.span_transform.is_none());
+        assert!(config.min_span_duration.is_none());
+        assert!(config.tail_sampling.is_none());
+        assert!(config.baggage_attributes.is_empty());
+        assert!(config.excluded_span_names.is_empty());
+        assert!(config.custom_sampler.is_none());
+        assert!(config.id_generator.is_none());
+        assert!(config.trace_filter.is_none());
+        assert!(config.flush_watermark.is_none());
+        assert!(config.max_concurrent_exports.is_none());
+        assert!(!config.detect_k8s);
+        assert!(!config.record_panics);
+        assert_eq!(config.max_queue_size, None);
+        assert!(!config.strict);
+        assert_eq!(config.service_name, None);
+        assert_eq!(config.shutdown_timeout, Duration::from_secs(5));
+        assert_eq!(config.runtime, ExportRuntime::TokioCurrentThread);
+        #[cfg(feature = "metrics")]
+        assert_eq!(config.metric_export_interval, Duration::from_secs(60));
+        #[cfg(feature = "metrics")]
+        assert_eq!(config.http_duration_buckets, None);
+        #[cfg(feature = "metrics")]
+        assert_eq!(config.metric_export_retry, None);
+        #[cfg(feature = "syslog")]
+        assert_eq!(config.syslog, None);
+        assert_eq!(config.environment(), Environment::Production);
+    }
+
+    #[test]
+    fn test_environment_from_str() {
+        for alias in ["development", "dev", "local", "test", " DEV "] {
+            assert_eq!(alias.parse(), Ok(Environment::Development), "{alias}");
+        }
+        for alias in ["production", "prod", "staging", "stage", "Prod"] {
+            assert_eq!(alias.parse(), Ok(Environment::Production), "{alias}");
+        }
+        assert!("qa".parse::<Environment>().is_err());
+    }
+
+    #[test]
+    fn test_environment_resolution() {
+        let config = TelemetryConfig {
+            env: "local".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(config.environment(), Environment::Development);
+
+        let config = TelemetryConfig {
+            env: "local".to_string(),
+            environment: Some(Environment::Production),
+            ..Default::default()
+        };
+        assert_eq!(config.environment(), Environment::Production);
+
+        let config = TelemetryConfig {
+            env: "qa".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(config.environment(), Environment::Production);
+    }
+
+    #[test]
+    fn test_log_source_location() {
+        assert!(TelemetryConfig::development("billing").log_source_location());
+        assert!(!TelemetryConfig::default().log_source_location());
+        let config = TelemetryConfig {
+            log_source_location: Some(true),
+            ..Default::default()
+        };
+        assert!(config.log_source_location());
     }
 
     #[tokio::test]
@@ -263,6 +2226,557 @@ mod tests {
         assert!(result.is_ok());
     }
 
+    #[test]
+    fn test_stdout_layer_disabled_with_exporter() {
+        use opentelemetry::trace::TracerProvider as _;
+
+        let config = TelemetryConfig {
+            stdout_logging: false,
+            ..Default::default()
+        };
+        assert!(config.stdout_layer::<Registry>(false).is_some());
+
+        let provider = trace::TracerProvider::builder().build();
+        let telemetry = tracing_opentelemetry::layer().with_tracer(provider.tracer("test"));
+        let logger = config.stdout_layer(true);
+        assert!(logger.is_none());
+
+        let subscriber = Registry::default().with(Some(telemetry)).with(logger);
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info_span!("only_telemetry").in_scope(|| tracing::info!("exported"));
+        });
+    }
+
+    #[test]
+    fn test_deployment_constructors() {
+        let config = TelemetryConfig::development("billing");
+        assert_eq!(config.app_name, "billing");
+        assert_eq!(config.log_format(), LogFormat::Compact);
+        assert!(config.resolved_endpoint_url().is_none());
+        assert!(config.service_name_problem().is_none());
+
+        let config = TelemetryConfig::production("billing", "http://otel-collector:4317");
+        assert_eq!(config.env, "production");
+        assert_eq!(config.log_format(), LogFormat::Json);
+        assert_eq!(
+            config.resolved_endpoint_url().as_deref(),
+            Some("http://otel-collector:4317")
+        );
+        assert_eq!(config.tracer_id.as_deref(), Some("billing"));
+        assert!(matches!(config.sampler, Sampler::ParentBased(_)));
+        assert!(config.service_name_problem().is_none());
+
+        let config = TelemetryConfig::local_otlp("billing", "http://localhost:4317");
+        assert_eq!(config.log_format(), LogFormat::Compact);
+        assert_eq!(config.sampler, Sampler::AlwaysOn);
+        assert_eq!(config.tracer_id.as_deref(), Some("billing"));
+    }
+
+    #[test]
+    fn test_resolved_endpoint_url() {
+        let config = TelemetryConfig {
+            env: "staging".to_string(),
+            endpoint_template: Some("http://otel-collector.{env}.svc:4317".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            config.resolved_endpoint_url().as_deref(),
+            Some("http://otel-collector.staging.svc:4317")
+        );
+
+        let explicit = TelemetryConfig {
+            endpoint_url: Some("http://localhost:4317".to_string()),
+            ..config.clone()
+        };
+        assert_eq!(
+            explicit.resolved_endpoint_url().as_deref(),
+            Some("http://localhost:4317")
+        );
+
+        let no_env = TelemetryConfig {
+            env: String::new(),
+            ..config
+        };
+        assert_eq!(no_env.resolved_endpoint_url(), None);
+        assert_eq!(TelemetryConfig::default().resolved_endpoint_url(), None);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_telemetry_config_serde() {
+        let config: TelemetryConfig = serde_json::from_value(serde_json::json!({
+            "app_name": "billing",
+            "endpoint_url": "http://otel-collector:4317",
+            "environment": "development",
+            "sampler": { "parent_based": { "trace_id_ratio": 0.25 } },
+            "timestamp_format": "unix_millis",
+            "runtime": "tokio",
+            "min_span_duration": "250ms",
+            "shutdown_timeout": "10s",
+        }))
+        .unwrap();
+        assert_eq!(config.app_name, "billing");
+        assert_eq!(config.environment, Some(Environment::Development));
+        assert_eq!(
+            config.sampler,
+            Sampler::ParentBased(Box::new(Sampler::TraceIdRatio(0.25)))
+        );
+        assert_eq!(config.timestamp_format, TimestampFormat::UnixMillis);
+        assert_eq!(config.runtime, ExportRuntime::Tokio);
+        assert_eq!(config.min_span_duration, Some(Duration::from_millis(250)));
+        assert_eq!(config.shutdown_timeout, Duration::from_secs(10));
+        // Missing fields keep their defaults.
+        assert!(config.stdout_logging);
+        assert_eq!(config.max_queue_size, None);
+
+        let value = serde_json::to_value(&config).unwrap();
+        assert_eq!(value["shutdown_timeout"], "10s");
+        assert_eq!(value["min_span_duration"], "250ms");
+        assert_eq!(value["sampler"]["parent_based"]["trace_id_ratio"], 0.25);
+        let round_trip: TelemetryConfig = serde_json::from_value(value.clone()).unwrap();
+        assert_eq!(serde_json::to_value(&round_trip).unwrap(), value);
+
+        let invalid = serde_json::from_value::<TelemetryConfig>(serde_json::json!({
+            "shutdown_timeout": 10,
+        }));
+        assert!(invalid.is_err());
+    }
+
+    #[cfg(feature = "otlp")]
+    #[test]
+    fn test_init_blocking_requires_runtime_to_export() {
+        let config = TelemetryConfig {
+            endpoint_url: Some("http://localhost:4317".to_string()),
+            ..Default::default()
+        };
+        let err = config.init_blocking().unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<TelemetryError>(),
+            Some(TelemetryError::MissingRuntime)
+        ));
+    }
+
+    #[test]
+    fn test_service_name() {
+        let service_name = |config: &TelemetryConfig| {
+            config
+                .resource()
+                .get(opentelemetry::Key::from_static_str("service.name"))
+                .map(|value| value.to_string())
+        };
+        let config = TelemetryConfig {
+            app_name: "TemboCloud Control Plane".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(config.service_name(), "TemboCloud Control Plane");
+        assert_eq!(
+            service_name(&config).as_deref(),
+            Some("TemboCloud Control Plane")
+        );
+
+        let config = TelemetryConfig {
+            service_name: Some("tembo-cp".to_string()),
+            ..config
+        };
+        assert_eq!(config.service_name(), "tembo-cp");
+        assert_eq!(service_name(&config).as_deref(), Some("tembo-cp"));
+        assert_eq!(config.app_name, "TemboCloud Control Plane");
+        assert_eq!(config.effective().service_name, "tembo-cp");
+
+        // The name checked by strict mode is the one recorded.
+        let config = TelemetryConfig {
+            app_name: "production".to_string(),
+            ..config
+        };
+        assert_eq!(config.service_name_problem(), None);
+        let config = TelemetryConfig {
+            service_name: Some(String::new()),
+            ..config
+        };
+        assert_eq!(config.service_name_problem(), Some("it is empty"));
+    }
+
+    #[test]
+    fn test_resource_schema_url() {
+        let config = TelemetryConfig {
+            app_name: "billing".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(config.resource().schema_url(), Some(DEFAULT_SCHEMA_URL));
+
+        let custom = TelemetryConfig {
+            resource_schema_url: Some("https://opentelemetry.io/schemas/1.26.0".to_string()),
+            ..config.clone()
+        };
+        assert_eq!(
+            custom.resource().schema_url(),
+            Some("https://opentelemetry.io/schemas/1.26.0")
+        );
+        // Attributes merged after `init` keep the schema URL.
+        let shared = resource::SharedResource::new(custom.resource());
+        shared.merge([KeyValue::new("cloud.region", "us-east-1")]);
+        assert_eq!(
+            shared.get().schema_url(),
+            Some("https://opentelemetry.io/schemas/1.26.0")
+        );
+
+        let none = TelemetryConfig {
+            resource_schema_url: None,
+            ..config
+        };
+        assert_eq!(none.resource().schema_url(), None);
+    }
+
+    #[test]
+    fn test_trace_filter() {
+        use crate::format::tests::CapturedWriter;
+        use opentelemetry::trace::TracerProvider as _;
+        use processors::tests::CollectingProcessor;
+
+        let config = TelemetryConfig {
+            trace_filter: Some("debug".to_string()),
+            ..Default::default()
+        };
+        let trace_filter = effective::parse_filter(config.trace_filter.as_deref().unwrap());
+        let collector = CollectingProcessor::default();
+        let provider = trace::TracerProvider::builder()
+            .with_span_processor(collector.clone())
+            .build();
+        let output = CapturedWriter::default();
+        let writer = output.clone();
+        let subscriber = Registry::default()
+            .with(
+                fmt::layer()
+                    .with_writer(move || writer.clone())
+                    .with_filter(tracing_subscriber::EnvFilter::new("info")),
+            )
+            .with(
+                tracing_opentelemetry::layer()
+                    .with_tracer(provider.tracer("test"))
+                    .with_filter(trace_filter.unwrap()),
+            );
+        tracing::subscriber::with_default(subscriber, || {
+            let _request = tracing::info_span!("request").entered();
+            tracing::debug!("cache miss");
+            tracing::info!("request handled");
+        });
+
+        let lines = output.lines();
+        assert_eq!(lines.len(), 1, "{lines:?}");
+        assert!(lines[0].contains("request handled"));
+        let spans = collector.0.lock().unwrap();
+        let events: Vec<_> = spans[0].events.iter().map(|event| &event.name).collect();
+        assert_eq!(events, ["cache miss", "request handled"]);
+
+        let err = effective::parse_filter("billing=loud").unwrap_err();
+        assert!(matches!(err, TelemetryError::InvalidFilter { .. }));
+    }
+
+    #[test]
+    fn test_id_generator() {
+        use opentelemetry::trace::{SpanId, TraceId};
+
+        const TRACE_ID: TraceId = TraceId::from_bytes(42u128.to_be_bytes());
+        const SPAN_ID: SpanId = SpanId::from_bytes(7u64.to_be_bytes());
+
+        #[derive(Debug)]
+        struct FixedIds;
+
+        impl trace::IdGenerator for FixedIds {
+            fn new_trace_id(&self) -> TraceId {
+                TRACE_ID
+            }
+
+            fn new_span_id(&self) -> SpanId {
+                SPAN_ID
+            }
+        }
+
+        let config = TelemetryConfig {
+            id_generator: Some(Arc::new(FixedIds)),
+            ..Default::default()
+        };
+        let trace_config = config.clone().trace_config(Resource::empty());
+        assert_eq!(trace_config.id_generator.new_trace_id(), TRACE_ID);
+        assert_eq!(trace_config.id_generator.new_span_id(), SPAN_ID);
+
+        let random = TelemetryConfig::default().trace_config(Resource::empty());
+        assert_ne!(random.id_generator.new_trace_id(), TRACE_ID);
+    }
+
+    #[test]
+    fn test_service_name_problem() {
+        let config = |app_name: &str| TelemetryConfig {
+            app_name: app_name.to_string(),
+            env: "qa-eu-1".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(config("billing").service_name_problem(), None);
+        assert_eq!(config(" ").service_name_problem(), Some("it is empty"));
+        assert_eq!(
+            config("QA-EU-1").service_name_problem(),
+            Some("it equals `env`")
+        );
+        assert_eq!(
+            config("development").service_name_problem(),
+            Some("it is an environment name")
+        );
+
+        let strict = TelemetryConfig {
+            strict: true,
+            ..config("")
+        };
+        let err = strict.init_blocking().unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<TelemetryError>(),
+            Some(TelemetryError::InvalidAppName { .. })
+        ));
+    }
+
+    #[test]
+    fn test_ansi_enabled() {
+        assert!(ansi_enabled(None, None, true));
+        assert!(!ansi_enabled(None, None, false));
+        assert!(!ansi_enabled(None, Some("1".into()), true));
+        assert!(ansi_enabled(None, Some("".into()), true));
+        assert!(ansi_enabled(Some(true), Some("1".into()), false));
+        assert!(!ansi_enabled(Some(false), None, true));
+    }
+
+    #[test]
+    fn test_extra_layers_compose() {
+        use std::sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        };
+
+        struct CountingLayer(Arc<AtomicUsize>);
+
+        impl<S: Subscriber> Layer<S> for CountingLayer {
+            fn on_event(
+                &self,
+                _event: &tracing::Event<'_>,
+                _ctx: tracing_subscriber::layer::Context<'_, S>,
+            ) {
+                self.0.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+
+        let events = Arc::new(AtomicUsize::new(0));
+        let layers: Vec<BoxedLayer> = vec![Box::new(CountingLayer(events.clone()))];
+        let config = TelemetryConfig::default();
+        let subscriber = Registry::default()
+            .with(layers)
+            .with(config.stdout_layer(false))
+            .with(tracing_subscriber::EnvFilter::new("info"));
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!("counted");
+            tracing::debug!("filtered");
+        });
+        assert_eq!(events.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_scoped_tracer() {
+        use opentelemetry::trace::Tracer as _;
+        use processors::tests::CollectingProcessor;
+
+        let collector = CollectingProcessor::default();
+        let provider = trace::TracerProvider::builder()
+            .with_span_processor(collector.clone())
+            .build();
+        scoped_tracer(&provider, "billing", "1.2.0").in_span("charge", |_| {});
+        scoped_tracer(&provider, "storage", "0.3.0").in_span("put", |_| {});
+
+        let spans = collector.0.lock().unwrap();
+        let scopes: Vec<_> = spans
+            .iter()
+            .map(|span| {
+                let library = &span.instrumentation_lib;
+                (library.name.as_ref(), library.version.as_deref())
+            })
+            .collect();
+        assert_eq!(
+            scopes,
+            vec![("billing", Some("1.2.0")), ("storage", Some("0.3.0"))]
+        );
+        let _ = get_tracer("billing", "1.2.0");
+    }
+
+    #[test]
+    fn test_span_scope() {
+        use opentelemetry::Value;
+        use processors::tests::collecting_subscriber;
+
+        let (collector, subscriber) = collecting_subscriber();
+        tracing::subscriber::with_default(subscriber, || {
+            let _request = tracing::info_span!("request").entered();
+            let _scope = span_scope("load_config", &[("config.source", "s3")]);
+        });
+
+        let spans = collector.0.lock().unwrap();
+        assert_eq!(spans.len(), 2);
+        let (scope, request) = (&spans[0], &spans[1]);
+        assert_eq!(scope.name, "load_config");
+        assert_eq!(scope.parent_span_id, request.span_context.span_id());
+        assert_eq!(
+            scope.span_context.trace_id(),
+            request.span_context.trace_id()
+        );
+        assert!(scope
+            .attributes
+            .iter()
+            .any(|kv| kv.key.as_str() == "config.source" && kv.value == Value::from("s3")));
+    }
+
+    #[test]
+    fn test_record_attrs() {
+        use opentelemetry::Value;
+        use processors::tests::{attribute, collecting_subscriber};
+
+        let (collector, subscriber) = collecting_subscriber();
+        tracing::subscriber::with_default(subscriber, || {
+            let _request = tracing::info_span!("request").entered();
+            record_attrs(&[("tenant.id", "acme"), ("plan", "free")]);
+            record_key_values([
+                KeyValue::new("retry.count", 3),
+                KeyValue::new("cached", true),
+            ]);
+            record_attrs(&[("plan", "enterprise")]);
+        });
+
+        let spans = collector.0.lock().unwrap();
+        assert_eq!(
+            attribute(&spans[0].attributes, "tenant.id"),
+            Some(Value::from("acme"))
+        );
+        assert_eq!(
+            attribute(&spans[0].attributes, "plan"),
+            Some(Value::from("enterprise"))
+        );
+        assert_eq!(
+            attribute(&spans[0].attributes, "retry.count"),
+            Some(Value::I64(3))
+        );
+        assert_eq!(
+            attribute(&spans[0].attributes, "cached"),
+            Some(Value::Bool(true))
+        );
+    }
+
+    #[test]
+    fn test_emit_span() {
+        use opentelemetry::{trace::TracerProvider as _, Value};
+        use processors::tests::collecting_subscriber;
+
+        let (collector, subscriber) = collecting_subscriber();
+        // A second provider feeding the same collector, for spans emitted without `tracing`.
+        let provider = trace::TracerProvider::builder()
+            .with_span_processor(collector.clone())
+            .build();
+        let tracer = provider.tracer("test");
+        let (emitted, orphan) = tracing::subscriber::with_default(subscriber, || {
+            let emitted = tracing::info_span!("request").in_scope(|| {
+                emit_span_on(
+                    &tracer,
+                    "ffi_callback".into(),
+                    vec![KeyValue::new("callback.kind", "decode")],
+                    Duration::from_millis(250),
+                )
+            });
+            let orphan = emit_span_on(&tracer, "orphan".into(), Vec::new(), Duration::ZERO);
+            (emitted, orphan)
+        });
+
+        let spans = collector.0.lock().unwrap();
+        assert_eq!(spans.len(), 3);
+        let (callback, request, orphan_span) = (&spans[0], &spans[1], &spans[2]);
+        assert_eq!(callback.name, "ffi_callback");
+        assert_eq!(callback.span_context, emitted);
+        assert_eq!(callback.parent_span_id, request.span_context.span_id());
+        assert_eq!(emitted.trace_id(), request.span_context.trace_id());
+        assert_eq!(
+            callback
+                .end_time
+                .duration_since(callback.start_time)
+                .unwrap(),
+            Duration::from_millis(250)
+        );
+        assert_eq!(callback.attributes[0].value, Value::from("decode"));
+        assert_eq!(
+            orphan_span.parent_span_id,
+            opentelemetry::trace::SpanId::INVALID
+        );
+        assert_ne!(orphan.trace_id(), emitted.trace_id());
+    }
+
+    #[test]
+    fn test_span_with_links() {
+        use processors::tests::collecting_subscriber;
+        use std::collections::HashMap;
+
+        let (collector, subscriber) = collecting_subscriber();
+        let traceparents = [
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01",
+            "invalid",
+        ];
+        global::set_text_map_propagator(propagation::propagator());
+        let links: Vec<_> = traceparents
+            .iter()
+            .map(|traceparent| {
+                let headers = HashMap::from([("traceparent".to_string(), traceparent.to_string())]);
+                propagation::extract_context(&headers)
+            })
+            .collect();
+        tracing::subscriber::with_default(subscriber, || {
+            span_with_links("process_batch", links).in_scope(|| {});
+        });
+
+        let spans = collector.0.lock().unwrap();
+        assert_eq!(spans.len(), 1);
+        assert_eq!(spans[0].name, "process_batch");
+        let linked: Vec<_> = spans[0]
+            .links
+            .iter()
+            .map(|link| link.span_context.trace_id().to_string())
+            .collect();
+        assert_eq!(
+            linked,
+            vec![
+                "4bf92f3577b34da6a3ce929d0e0e4736",
+                "0af7651916cd43dd8448eb211c80319c"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_instrument_result_records_errors() {
+        use opentelemetry::trace::Status;
+        use processors::tests::collecting_subscriber;
+        use tracing::Instrument;
+
+        let (collector, subscriber) = collecting_subscriber();
+        let _default = tracing::subscriber::set_default(subscriber);
+
+        let ok: Result<u32, String> = instrument_result(async { Ok(1) })
+            .instrument(tracing::info_span!("ok"))
+            .await;
+        assert_eq!(ok, Ok(1));
+        let err: Result<u32, String> =
+            instrument_result(async { Err("bucket not found".to_string()) })
+                .instrument(tracing::info_span!("err"))
+                .await;
+        assert!(err.is_err());
+
+        let spans = collector.0.lock().unwrap();
+        assert_eq!(spans[0].status, Status::Unset);
+        assert!(spans[0].events.is_empty());
+        assert_eq!(spans[1].status, Status::error("bucket not found"));
+        assert_eq!(spans[1].events.len(), 1);
+        assert_eq!(spans[1].events.iter().next().unwrap().name, "exception");
+    }
+
     #[test]
     fn test_excluded_route() {
         CustomFilterRootSpanBuilder::set_excluded_routes(vec!["/health/liveness".to_string()]);
@@ -271,6 +2785,472 @@ mod tests {
         assert!(span.is_none());
     }
 
+    #[test]
+    fn test_sample_with_seed() {
+        assert!((0..1000).all(|seed| !sample_with_seed(0.0, seed)));
+        assert!((0..1000).all(|seed| sample_with_seed(1.0, seed)));
+        assert_eq!(sample_with_seed(0.5, 42), sample_with_seed(0.5, 42));
+
+        let sampled = (0..10_000)
+            .filter(|seed| sample_with_seed(0.1, *seed))
+            .count();
+        assert!((800..1200).contains(&sampled), "sampled {sampled} of 10000");
+    }
+
+    #[test]
+    fn test_sampled_route() {
+        CustomFilterRootSpanBuilder::set_excluded_routes(Vec::new());
+        CustomFilterRootSpanBuilder::set_sampled_routes(vec![(
+            "/health/liveness".to_string(),
+            0.0,
+        )]);
+        let req = TestRequest::get().uri("/health/liveness").to_srv_request();
+        assert!(!CustomFilterRootSpanBuilder::route_sampled(&req));
+        assert!(CustomFilterRootSpanBuilder::on_request_start(&req).is_none());
+
+        let req = TestRequest::get().uri("/some/other/route").to_srv_request();
+        assert!(CustomFilterRootSpanBuilder::route_sampled(&req));
+
+        CustomFilterRootSpanBuilder::set_sampled_routes(vec![(
+            "/health/liveness".to_string(),
+            1.0,
+        )]);
+        let req = TestRequest::get().uri("/health/liveness").to_srv_request();
+        assert!(CustomFilterRootSpanBuilder::route_sampled(&req));
+    }
+
+    #[test]
+    fn test_sample_seed() {
+        let decisions = |builder: &CustomLoggerBuilder, worker| {
+            builder.apply(worker);
+            let req = TestRequest::get().uri("/health/liveness").to_srv_request();
+            (0..64)
+                .map(|_| CustomFilterRootSpanBuilder::route_sampled(&req))
+                .collect::<Vec<_>>()
+        };
+        let builder = CustomLoggerBuilder::new()
+            .sample_route("/health/liveness", 0.5)
+            .sample_seed(7);
+        assert_eq!(decisions(&builder, 0), decisions(&builder, 0));
+        assert_ne!(decisions(&builder, 0), decisions(&builder, 1));
+        assert_ne!(
+            decisions(&builder, 0),
+            decisions(&builder.clone().sample_seed(8), 0)
+        );
+        CustomFilterRootSpanBuilder::set_sampled_routes(Vec::new());
+    }
+
+    #[test]
+    fn test_error_status_threshold() {
+        CustomFilterRootSpanBuilder::set_error_status_threshold(StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(!CustomFilterRootSpanBuilder::is_error_status(
+            StatusCode::BAD_REQUEST
+        ));
+        assert!(!CustomFilterRootSpanBuilder::is_error_status(
+            StatusCode::NOT_FOUND
+        ));
+        assert!(CustomFilterRootSpanBuilder::is_error_status(
+            StatusCode::INTERNAL_SERVER_ERROR
+        ));
+
+        CustomFilterRootSpanBuilder::set_error_status_threshold(StatusCode::BAD_REQUEST);
+        assert!(!CustomFilterRootSpanBuilder::is_error_status(
+            StatusCode::OK
+        ));
+        assert!(CustomFilterRootSpanBuilder::is_error_status(
+            StatusCode::BAD_REQUEST
+        ));
+        assert!(CustomFilterRootSpanBuilder::is_error_status(
+            StatusCode::NOT_FOUND
+        ));
+        assert!(CustomFilterRootSpanBuilder::is_error_status(
+            StatusCode::INTERNAL_SERVER_ERROR
+        ));
+    }
+
+    #[actix_web::test]
+    async fn test_error_status_recorded_on_root_span() {
+        use actix_web::{web, App, HttpResponse};
+        use opentelemetry::trace::Status;
+        use processors::tests::collecting_subscriber;
+
+        let (collector, subscriber) = collecting_subscriber();
+        let _default = tracing::subscriber::set_default(subscriber);
+
+        CustomFilterRootSpanBuilder::set_excluded_routes(Vec::new());
+        CustomFilterRootSpanBuilder::set_sampled_routes(Vec::new());
+        let app = actix_web::test::init_service(
+            App::new()
+                .wrap(
+                    CustomLoggerBuilder::new()
+                        .error_status_threshold(StatusCode::BAD_REQUEST)
+                        .build(),
+                )
+                .route("/ok", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+        for uri in ["/ok", "/missing"] {
+            let request = TestRequest::get().uri(uri).to_request();
+            actix_web::test::call_service(&app, request).await;
+        }
+
+        let spans = collector.0.lock().unwrap();
+        assert_eq!(spans.len(), 2);
+        assert_eq!(spans[0].status, Status::Ok);
+        assert_eq!(spans[1].status, Status::error("Not Found"));
+    }
+
+    #[actix_web::test]
+    async fn test_excluded_route_disables_handler_instrumentation() {
+        use actix_web::{web, App};
+
+        use processors::tests::collecting_subscriber;
+
+        async fn handler() -> &'static str {
+            tracing::info_span!("check_database").in_scope(|| tracing::info!("checked"));
+            "ok"
+        }
+
+        let (collector, subscriber) = collecting_subscriber();
+        let _default = tracing::subscriber::set_default(subscriber);
+
+        let app = actix_web::test::init_service(
+            App::new()
+                .wrap(TraceIdHeader::new())
+                .wrap(get_tracing_logger().exclude("/health/liveness").build())
+                .route("/health/liveness", web::get().to(handler))
+                .route("/health/readiness", web::get().to(handler)),
+        )
+        .await;
+        let request = TestRequest::get().uri("/health/liveness").to_request();
+        let response = actix_web::test::call_service(&app, request).await;
+        assert!(response.status().is_success());
+        assert!(response
+            .headers()
+            .get(middleware::DEFAULT_TRACE_ID_HEADER)
+            .is_none());
+        assert_eq!(actix_web::test::read_body(response).await, "ok");
+        assert!(collector.0.lock().unwrap().is_empty());
+
+        let request = TestRequest::get().uri("/health/readiness").to_request();
+        actix_web::test::call_service(&app, request).await;
+        let spans = collector.0.lock().unwrap();
+        let names: Vec<_> = spans.iter().map(|span| span.name.to_string()).collect();
+        assert_eq!(names, ["check_database", "GET /health/readiness"]);
+    }
+
+    #[actix_web::test]
+    async fn test_discard_on_success() {
+        use actix_web::{web, App, HttpResponse};
+        use opentelemetry::trace::TracerProvider as _;
+        use processors::{tests::CollectingProcessor, DeferredTraceProcessor};
+
+        async fn handler(query: web::Query<Vec<(String, String)>>) -> HttpResponse {
+            tracing::info_span!("sync_batch").in_scope(|| {});
+            if query.is_empty() {
+                HttpResponse::Ok().finish()
+            } else {
+                HttpResponse::ServiceUnavailable().finish()
+            }
+        }
+
+        let collector = CollectingProcessor::default();
+        let provider = trace::TracerProvider::builder()
+            .with_span_processor(DeferredTraceProcessor::new(vec![Box::new(
+                collector.clone(),
+            )]))
+            .build();
+        let subscriber = Registry::default()
+            .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")));
+        let _default = tracing::subscriber::set_default(subscriber);
+
+        let app = actix_web::test::init_service(
+            App::new()
+                .wrap(get_tracing_logger().discard_on_success("/sync").build())
+                .route("/sync", web::get().to(handler))
+                .route("/other", web::get().to(handler)),
+        )
+        .await;
+        let request = TestRequest::get().uri("/sync").to_request();
+        actix_web::test::call_service(&app, request).await;
+        assert!(collector.0.lock().unwrap().is_empty());
+
+        let request = TestRequest::get().uri("/sync?fail=1").to_request();
+        actix_web::test::call_service(&app, request).await;
+        let request = TestRequest::get().uri("/other").to_request();
+        actix_web::test::call_service(&app, request).await;
+        let spans = collector.0.lock().unwrap();
+        let names: Vec<_> = spans.iter().map(|span| span.name.to_string()).collect();
+        assert_eq!(
+            names,
+            ["sync_batch", "GET /sync", "sync_batch", "GET /other"]
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_root_span_attributes_drive_sampling() {
+        use actix_web::{web, App, HttpResponse};
+        use opentelemetry::{
+            trace::{Link, SamplingDecision, SamplingResult, SpanKind},
+            Value,
+        };
+        use processors::tests::collecting_subscriber_with;
+
+        /// Samples root spans of enterprise tenants only.
+        #[derive(Clone, Debug)]
+        struct TierSampler;
+
+        impl trace::ShouldSample for TierSampler {
+            fn should_sample(
+                &self,
+                _parent_context: Option<&opentelemetry::Context>,
+                _trace_id: TraceId,
+                _name: &str,
+                _span_kind: &SpanKind,
+                attributes: &[KeyValue],
+                _links: &[Link],
+            ) -> SamplingResult {
+                let enterprise = attributes.iter().any(|kv| {
+                    kv.key.as_str() == "tenant.tier" && kv.value.as_str() == "enterprise"
+                });
+                SamplingResult {
+                    decision: if enterprise {
+                        SamplingDecision::RecordAndSample
+                    } else {
+                        SamplingDecision::Drop
+                    },
+                    attributes: Vec::new(),
+                    trace_state: Default::default(),
+                }
+            }
+        }
+
+        let (collector, subscriber) =
+            collecting_subscriber_with(trace::config().with_sampler(TierSampler));
+        let _default = tracing::subscriber::set_default(subscriber);
+
+        let app = actix_web::test::init_service(
+            App::new()
+                .wrap(
+                    CustomLoggerBuilder::new()
+                        .root_span_attributes(|request| {
+                            let tier = request
+                                .headers()
+                                .get("x-tenant-tier")
+                                .and_then(|value| value.to_str().ok())
+                                .unwrap_or("free");
+                            vec![KeyValue::new("tenant.tier", tier.to_string())]
+                        })
+                        .build(),
+                )
+                .route("/", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+        for tier in ["free", "enterprise"] {
+            let request = TestRequest::get()
+                .insert_header(("x-tenant-tier", tier))
+                .to_request();
+            actix_web::test::call_service(&app, request).await;
+        }
+
+        let spans = collector.0.lock().unwrap();
+        assert_eq!(spans.len(), 1);
+        assert!(spans[0]
+            .attributes
+            .iter()
+            .any(|kv| kv.key.as_str() == "tenant.tier" && kv.value == Value::from("enterprise")));
+    }
+
+    #[actix_web::test]
+    async fn test_unmatched_route_uses_sentinel() {
+        use actix_web::{web, App, HttpResponse};
+        use opentelemetry::Value;
+        use processors::tests::collecting_subscriber;
+
+        let (collector, subscriber) = collecting_subscriber();
+        let _default = tracing::subscriber::set_default(subscriber);
+
+        let app = actix_web::test::init_service(
+            App::new()
+                .wrap(CustomLoggerBuilder::new().build())
+                .route("/users/{id}", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+        for uri in ["/users/42", "/wp-admin/setup.php"] {
+            let request = TestRequest::get().uri(uri).to_request();
+            actix_web::test::call_service(&app, request).await;
+        }
+
+        let spans = collector.0.lock().unwrap();
+        let routes: Vec<_> = spans
+            .iter()
+            .map(|span| {
+                let route = span
+                    .attributes
+                    .iter()
+                    .filter(|kv| kv.key.as_str() == "http.route")
+                    .map(|kv| kv.value.clone())
+                    .collect::<Vec<_>>();
+                (span.name.to_string(), route)
+            })
+            .collect();
+        assert_eq!(
+            routes,
+            vec![
+                (
+                    "GET /users/{id}".to_string(),
+                    vec![Value::from("/users/{id}")]
+                ),
+                (
+                    "GET NOT_FOUND".to_string(),
+                    vec![Value::from(NOT_FOUND_ROUTE)]
+                ),
+            ]
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_untrusted_parent_starts_new_trace() {
+        use actix_web::{web, App, HttpResponse};
+
+        use processors::tests::collecting_subscriber;
+
+        const TRACEPARENT: &str = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+        global::set_text_map_propagator(propagation::propagator());
+        let (collector, subscriber) = collecting_subscriber();
+        let _default = tracing::subscriber::set_default(subscriber);
+
+        let app = actix_web::test::init_service(
+            App::new()
+                .wrap(
+                    CustomLoggerBuilder::new()
+                        .trust_parent_if(|request| request.headers().contains_key("x-internal"))
+                        .build(),
+                )
+                .route("/", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+        let trusted = TestRequest::get()
+            .insert_header(("traceparent", TRACEPARENT))
+            .insert_header(("x-internal", "1"))
+            .to_request();
+        actix_web::test::call_service(&app, trusted).await;
+        let untrusted = TestRequest::get()
+            .insert_header(("traceparent", TRACEPARENT))
+            .to_request();
+        actix_web::test::call_service(&app, untrusted).await;
+
+        let spans = collector.0.lock().unwrap();
+        assert_eq!(spans.len(), 2);
+        let remote_trace_id = TraceId::from_hex("4bf92f3577b34da6a3ce929d0e0e4736").unwrap();
+        assert_eq!(spans[0].span_context.trace_id(), remote_trace_id);
+        assert!(spans[0].links.is_empty());
+
+        assert_ne!(spans[1].span_context.trace_id(), remote_trace_id);
+        assert_eq!(
+            spans[1].parent_span_id,
+            opentelemetry::trace::SpanId::INVALID
+        );
+        assert_eq!(spans[1].links.len(), 1);
+        assert_eq!(
+            spans[1]
+                .links
+                .iter()
+                .next()
+                .unwrap()
+                .span_context
+                .trace_id(),
+            remote_trace_id
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_parent_policy() {
+        use actix_web::{web, App, HttpResponse};
+
+        use processors::tests::collecting_subscriber;
+
+        assert_eq!(ParentPolicy::default(), ParentPolicy::Continue);
+        global::set_text_map_propagator(propagation::propagator());
+        let (collector, subscriber) = collecting_subscriber();
+        let _default = tracing::subscriber::set_default(subscriber);
+
+        let app = actix_web::test::init_service(
+            App::new()
+                .wrap(
+                    CustomLoggerBuilder::new()
+                        .parent_policy(|request| match request.path() {
+                            "/public" => ParentPolicy::NewRoot,
+                            _ => ParentPolicy::Continue,
+                        })
+                        .build(),
+                )
+                .route("/internal", web::get().to(HttpResponse::Ok))
+                .route("/public", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+        for path in ["/internal", "/public"] {
+            let request = TestRequest::get()
+                .uri(path)
+                .insert_header((
+                    "traceparent",
+                    "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+                ))
+                .to_request();
+            actix_web::test::call_service(&app, request).await;
+        }
+
+        let spans = collector.0.lock().unwrap();
+        assert_eq!(spans.len(), 2);
+        let remote_trace_id = TraceId::from_hex("4bf92f3577b34da6a3ce929d0e0e4736").unwrap();
+        assert_eq!(spans[0].span_context.trace_id(), remote_trace_id);
+        assert_ne!(spans[1].span_context.trace_id(), remote_trace_id);
+        assert_eq!(spans[1].links.len(), 1);
+    }
+
+    #[test]
+    fn test_root_span_enabled() {
+        assert!(root_span_enabled(Level::INFO, LevelFilter::INFO));
+        assert!(root_span_enabled(Level::WARN, LevelFilter::INFO));
+        assert!(!root_span_enabled(Level::INFO, LevelFilter::WARN));
+        assert!(!root_span_enabled(Level::DEBUG, LevelFilter::INFO));
+        assert!(!root_span_enabled(Level::ERROR, LevelFilter::OFF));
+    }
+
+    #[test]
+    fn test_client_address_direct() {
+        CustomFilterRootSpanBuilder::set_trust_forwarded_for(false);
+        let req = TestRequest::get()
+            .peer_addr("198.51.100.4:5000".parse().unwrap())
+            .insert_header(("X-Forwarded-For", "203.0.113.7"))
+            .to_srv_request();
+        assert_eq!(
+            CustomFilterRootSpanBuilder::client_address(&req),
+            "198.51.100.4"
+        );
+    }
+
+    #[test]
+    fn test_client_address_proxied() {
+        CustomFilterRootSpanBuilder::set_trust_forwarded_for(true);
+        let req = TestRequest::get()
+            .peer_addr("10.0.0.1:5000".parse().unwrap())
+            .insert_header(("X-Forwarded-For", "203.0.113.7, 10.0.0.1"))
+            .to_srv_request();
+        assert_eq!(
+            CustomFilterRootSpanBuilder::client_address(&req),
+            "203.0.113.7"
+        );
+
+        let req = TestRequest::get()
+            .peer_addr("10.0.0.1:5000".parse().unwrap())
+            .to_srv_request();
+        assert_eq!(
+            CustomFilterRootSpanBuilder::client_address(&req),
+            "10.0.0.1"
+        );
+    }
+
     #[tokio::test]
     async fn test_non_excluded_route() {
         fn mock_on_request_start(request: &ServiceRequest) -> bool {
@@ -0,0 +1,842 @@
+//! OTLP metrics export and built-in process metrics.
+//!
+//! When the `metrics` feature is enabled and an OTLP endpoint is configured, `init` installs a
+//! global meter provider exporting through the same collector as traces. Process metrics (and
+//! tokio runtime metrics with the `tokio-metrics` feature) can be registered on top of it.
+
+use crate::ExportRuntime;
+use async_trait::async_trait;
+use opentelemetry::{
+    global,
+    metrics::{Histogram, MeterProvider as _, MetricsError, Unit},
+};
+use opentelemetry_otlp::TonicExporterBuilder;
+use opentelemetry_sdk::{
+    metrics::{
+        data::{ResourceMetrics, Temporality},
+        exporter::PushMetricsExporter,
+        new_view,
+        reader::{
+            AggregationSelector, DefaultAggregationSelector, DefaultTemporalitySelector,
+            TemporalitySelector,
+        },
+        Aggregation, Instrument, InstrumentKind, PeriodicReader, SdkMeterProvider, Stream, View,
+    },
+    runtime::{Tokio, TokioCurrentThread},
+    Resource,
+};
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc, Arc,
+    },
+    thread,
+    time::Duration,
+};
+use tracing::{span, Event, Subscriber};
+use tracing_subscriber::{layer::Context, Layer};
+
+/// Name of the HTTP server request duration histogram.
+pub(crate) const HTTP_SERVER_DURATION: &str = "http.server.duration";
+
+/// Creates the `http.server.duration` histogram, in seconds, from the global meter provider.
+///
+/// Instruments created before `init` installs the meter provider stay no-ops, so this is called
+/// when actix workers instantiate [`CustomLogger`](crate::CustomLogger).
+pub(crate) fn http_server_duration() -> Histogram<f64> {
+    global::meter(env!("CARGO_PKG_NAME"))
+        .f64_histogram(HTTP_SERVER_DURATION)
+        .with_unit(Unit::new("s"))
+        .with_description("Duration of HTTP server requests.")
+        .init()
+}
+
+/// Variable overriding the export timeout of the periodic reader, in milliseconds.
+const METRIC_EXPORT_TIMEOUT_VAR: &str = "OTEL_METRIC_EXPORT_TIMEOUT";
+/// Export timeout of the periodic reader when the variable is unset, as in the SDK.
+const DEFAULT_READER_TIMEOUT: Duration = Duration::from_secs(30);
+/// Time left before the reader timeout when an export gives up retrying.
+const READER_TIMEOUT_MARGIN: Duration = Duration::from_secs(1);
+
+/// Builds the OTLP meter provider exporting through `exporter` on `runtime` every `interval`.
+///
+/// When `duration_buckets` is set, the `http.server.duration` histogram uses these bucket
+/// boundaries instead of the SDK defaults. Failed exports are retried according to `retry`.
+pub(crate) fn meter_provider(
+    exporter: TonicExporterBuilder,
+    resource: Resource,
+    runtime: ExportRuntime,
+    interval: Duration,
+    duration_buckets: Option<&[f64]>,
+    retry: Option<MetricExportRetry>,
+) -> Result<SdkMeterProvider, MetricsError> {
+    let exporter = exporter.build_metrics_exporter(
+        Box::new(DefaultAggregationSelector::new()),
+        Box::new(DefaultTemporalitySelector::new()),
+    )?;
+    let exporter = RetryingExporter::new(exporter, retry, reader_timeout());
+    let reader = match runtime {
+        ExportRuntime::TokioCurrentThread => PeriodicReader::builder(exporter, TokioCurrentThread)
+            .with_interval(interval)
+            .build(),
+        ExportRuntime::Tokio => PeriodicReader::builder(exporter, Tokio)
+            .with_interval(interval)
+            .build(),
+    };
+    let mut builder = SdkMeterProvider::builder()
+        .with_reader(reader)
+        .with_resource(resource);
+    if let Some(boundaries) = duration_buckets {
+        builder = builder.with_view(duration_view(boundaries)?);
+    }
+    Ok(builder.build())
+}
+
+/// Retries of failed metric exports, enabled by
+/// [`TelemetryConfig::metric_export_retry`](crate::TelemetryConfig::metric_export_retry).
+///
+/// With the `serde` feature, missing fields take their default values and durations are written
+/// as strings such as `"1s"`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
+pub struct MetricExportRetry {
+    /// Number of retries after the first attempt failed. Defaults to 3.
+    pub max_retries: u32,
+    /// Wait before the first retry, doubled before each further retry. Defaults to 1 second.
+    #[cfg_attr(feature = "serde", serde(with = "crate::duration_serde"))]
+    pub initial_backoff: Duration,
+    /// Maximum time spent on an export, including its attempts and backoff. Defaults to 20
+    /// seconds.
+    ///
+    /// The attempt running when it elapses is abandoned and no further retry starts. It is
+    /// capped to one second less than the export timeout of the periodic reader, 30 seconds
+    /// unless `OTEL_METRIC_EXPORT_TIMEOUT` is set, so the export gives up before the reader
+    /// cancels it.
+    #[cfg_attr(feature = "serde", serde(with = "crate::duration_serde"))]
+    pub max_elapsed: Duration,
+}
+
+impl Default for MetricExportRetry {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            initial_backoff: Duration::from_secs(1),
+            max_elapsed: Duration::from_secs(20),
+        }
+    }
+}
+
+/// Returns the export timeout of the periodic reader, read from the environment like the SDK.
+fn reader_timeout() -> Duration {
+    std::env::var(METRIC_EXPORT_TIMEOUT_VAR)
+        .ok()
+        .and_then(|millis| millis.parse().ok())
+        .map_or(DEFAULT_READER_TIMEOUT, Duration::from_millis)
+}
+
+/// Metrics exporter retrying failed exports and counting the exports that still failed.
+///
+/// Only the interval being exported is retried, so at most one interval of data is held while
+/// waiting. Every export, retried or not, ends before the reader timeout minus
+/// [`READER_TIMEOUT_MARGIN`].
+struct RetryingExporter<E> {
+    inner: E,
+    retry: Option<MetricExportRetry>,
+    /// Maximum time spent on one export.
+    budget: Duration,
+}
+
+impl<E> RetryingExporter<E> {
+    /// Wraps `inner`, fitting the exports in `reader_timeout`.
+    fn new(inner: E, retry: Option<MetricExportRetry>, reader_timeout: Duration) -> Self {
+        let budget = reader_timeout.saturating_sub(READER_TIMEOUT_MARGIN);
+        let budget = retry.map_or(budget, |retry| retry.max_elapsed.min(budget));
+        Self {
+            inner,
+            retry,
+            budget,
+        }
+    }
+}
+
+/// Counts an export as failed when dropped before it succeeded.
+///
+/// The periodic reader drops exports outliving its timeout, so counting on drop covers
+/// cancelled exports too.
+struct FailedExportGuard {
+    succeeded: bool,
+}
+
+impl Drop for FailedExportGuard {
+    fn drop(&mut self) {
+        if !self.succeeded {
+            METRIC_EXPORTS_FAILED.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+impl<E: AggregationSelector> AggregationSelector for RetryingExporter<E> {
+    fn aggregation(&self, kind: InstrumentKind) -> Aggregation {
+        self.inner.aggregation(kind)
+    }
+}
+
+impl<E: TemporalitySelector> TemporalitySelector for RetryingExporter<E> {
+    fn temporality(&self, kind: InstrumentKind) -> Temporality {
+        self.inner.temporality(kind)
+    }
+}
+
+#[async_trait]
+impl<E: PushMetricsExporter> PushMetricsExporter for RetryingExporter<E> {
+    async fn export(&self, metrics: &mut ResourceMetrics) -> opentelemetry::metrics::Result<()> {
+        let (max_retries, mut backoff) = self.retry.map_or((0, Duration::ZERO), |retry| {
+            (retry.max_retries, retry.initial_backoff)
+        });
+        let mut guard = FailedExportGuard { succeeded: false };
+        let deadline = tokio::time::Instant::now() + self.budget;
+        let mut retries = 0;
+        loop {
+            let result = tokio::time::timeout_at(deadline, self.inner.export(metrics))
+                .await
+                .unwrap_or_else(|_| Err(MetricsError::Other("export timed out".to_string())));
+            if result.is_ok() {
+                guard.succeeded = true;
+                return result;
+            }
+            if retries == max_retries || tokio::time::Instant::now() + backoff >= deadline {
+                return result;
+            }
+            tokio::time::sleep(backoff).await;
+            backoff = backoff.saturating_mul(2);
+            retries += 1;
+        }
+    }
+
+    async fn force_flush(&self) -> opentelemetry::metrics::Result<()> {
+        self.inner.force_flush().await
+    }
+
+    fn shutdown(&self) -> opentelemetry::metrics::Result<()> {
+        self.inner.shutdown()
+    }
+}
+
+/// Builds the view applying explicit bucket `boundaries` to `http.server.duration`.
+///
+/// The SDK ignores views with invalid boundaries, so they are validated here to fail `init`
+/// instead of silently falling back to the default buckets.
+fn duration_view(boundaries: &[f64]) -> Result<Box<dyn View>, MetricsError> {
+    let aggregation = Aggregation::ExplicitBucketHistogram {
+        boundaries: boundaries.to_vec(),
+        record_min_max: true,
+    };
+    aggregation.validate()?;
+    new_view(
+        Instrument::new().name(HTTP_SERVER_DURATION),
+        Stream::new().aggregation(aggregation),
+    )
+}
+
+/// Spans created since the process started, counted by [`CountingLayer`].
+static SPANS_CREATED: AtomicU64 = AtomicU64::new(0);
+/// Events emitted since the process started, counted by [`CountingLayer`].
+static EVENTS_EMITTED: AtomicU64 = AtomicU64::new(0);
+/// Spans accepted by an OTLP endpoint since the process started.
+static SPANS_EXPORTED: AtomicU64 = AtomicU64::new(0);
+/// Metric exports that failed after their retries since the process started.
+static METRIC_EXPORTS_FAILED: AtomicU64 = AtomicU64::new(0);
+
+/// Snapshot of the counters describing the telemetry pipeline itself.
+///
+/// Returned by [`TelemetryGuard::counters`](crate::TelemetryGuard::counters) and exported as
+/// the `telemetry.*` counters. A service whose spans and events stay at zero is usually
+/// filtering everything out with `RUST_LOG`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TelemetryCounters {
+    /// Spans enabled by the log filter, whether or not they are sampled.
+    pub spans_created: u64,
+    /// Events enabled by the log filter, including records bridged from the `log` crate.
+    pub events_emitted: u64,
+    /// Spans accepted by an OTLP endpoint, counted once per endpoint.
+    pub spans_exported: u64,
+    /// Spans dropped because an export queue was full.
+    pub spans_dropped: u64,
+    /// Metric exports that failed after their retries, each losing one interval of data.
+    pub metric_exports_failed: u64,
+}
+
+impl TelemetryCounters {
+    /// Reads the current counter values, with `dropped` counting the spans dropped by `init`.
+    pub(crate) fn read(dropped: &AtomicU64) -> Self {
+        Self {
+            spans_created: SPANS_CREATED.load(Ordering::Relaxed),
+            events_emitted: EVENTS_EMITTED.load(Ordering::Relaxed),
+            spans_exported: SPANS_EXPORTED.load(Ordering::Relaxed),
+            spans_dropped: dropped.load(Ordering::Relaxed),
+            metric_exports_failed: METRIC_EXPORTS_FAILED.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Layer counting the spans and events enabled by the log filter.
+///
+/// Only increments a relaxed atomic per span or event.
+pub(crate) struct CountingLayer;
+
+impl<S: Subscriber> Layer<S> for CountingLayer {
+    fn on_new_span(&self, _attrs: &span::Attributes<'_>, _id: &span::Id, _ctx: Context<'_, S>) {
+        SPANS_CREATED.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn on_event(&self, _event: &Event<'_>, _ctx: Context<'_, S>) {
+        EVENTS_EMITTED.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Counts `spans` accepted by an OTLP endpoint.
+pub(crate) fn record_exported(spans: usize) {
+    SPANS_EXPORTED.fetch_add(spans as u64, Ordering::Relaxed);
+}
+
+/// Registers the `telemetry.*` counters describing the pipeline, see [`TelemetryCounters`].
+///
+/// `dropped` counts the spans dropped because an export queue was full.
+pub(crate) fn register_telemetry_counters(provider: &SdkMeterProvider, dropped: &Arc<AtomicU64>) {
+    type Read = fn(&TelemetryCounters) -> u64;
+    let counters: [(&'static str, &'static str, &'static str, Read); 5] = [
+        (
+            "telemetry.spans.created",
+            "The number of spans enabled by the log filter.",
+            "{span}",
+            |counters| counters.spans_created,
+        ),
+        (
+            "telemetry.events.emitted",
+            "The number of events enabled by the log filter.",
+            "{event}",
+            |counters| counters.events_emitted,
+        ),
+        (
+            "telemetry.spans.exported",
+            "The number of spans accepted by an OTLP endpoint.",
+            "{span}",
+            |counters| counters.spans_exported,
+        ),
+        (
+            "telemetry.spans.dropped",
+            "The number of spans dropped because the export queue was full.",
+            "{span}",
+            |counters| counters.spans_dropped,
+        ),
+        (
+            "telemetry.metric_exports.failed",
+            "The number of metric exports that failed after their retries.",
+            "{export}",
+            |counters| counters.metric_exports_failed,
+        ),
+    ];
+    let meter = provider.meter(env!("CARGO_PKG_NAME"));
+    for (name, description, unit, read) in counters {
+        let dropped = dropped.clone();
+        meter
+            .u64_observable_counter(name)
+            .with_description(description)
+            .with_unit(Unit::new(unit))
+            .with_callback(move |counter| {
+                counter.observe(read(&TelemetryCounters::read(&dropped)), &[]);
+            })
+            .init();
+    }
+}
+
+/// Latest values sampled by the [`ProcessMetrics`] collector thread.
+#[derive(Debug, Default)]
+struct Snapshot {
+    memory_usage: AtomicU64,
+    open_fds: AtomicU64,
+    #[cfg(feature = "tokio-metrics")]
+    runtime: Option<RuntimeSnapshot>,
+}
+
+/// Latest tokio runtime values, present when a runtime was running at registration.
+#[cfg(feature = "tokio-metrics")]
+#[derive(Debug)]
+struct RuntimeSnapshot {
+    handle: tokio::runtime::Handle,
+    workers: AtomicU64,
+    alive_tasks: AtomicU64,
+    global_queue_depth: AtomicU64,
+}
+
+impl Snapshot {
+    /// Refreshes all values. Values that cannot be read on this platform are left untouched.
+    fn sample(&self) {
+        if let Some(memory_usage) = read_memory_usage() {
+            self.memory_usage.store(memory_usage, Ordering::Relaxed);
+        }
+        if let Some(open_fds) = read_open_fds() {
+            self.open_fds.store(open_fds, Ordering::Relaxed);
+        }
+        #[cfg(feature = "tokio-metrics")]
+        if let Some(runtime) = &self.runtime {
+            let metrics = runtime.handle.metrics();
+            runtime
+                .workers
+                .store(metrics.num_workers() as u64, Ordering::Relaxed);
+            runtime
+                .alive_tasks
+                .store(metrics.num_alive_tasks() as u64, Ordering::Relaxed);
+            runtime
+                .global_queue_depth
+                .store(metrics.global_queue_depth() as u64, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Background collector backing the process and runtime gauges.
+///
+/// Values are sampled on a dedicated thread at a fixed interval and reported by observable
+/// gauges whenever the meter provider collects. Dropping the collector stops the thread.
+#[derive(Debug)]
+pub(crate) struct ProcessMetrics {
+    stop: Option<mpsc::Sender<()>>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl ProcessMetrics {
+    /// Registers the gauges on `provider` and starts sampling every `interval`.
+    ///
+    /// # Arguments
+    ///
+    /// * `provider` - The meter provider the gauges are registered on.
+    /// * `interval` - How often the values are refreshed.
+    pub(crate) fn start(
+        provider: &SdkMeterProvider,
+        interval: Duration,
+    ) -> std::io::Result<ProcessMetrics> {
+        let snapshot = Arc::new(Snapshot {
+            #[cfg(feature = "tokio-metrics")]
+            runtime: tokio::runtime::Handle::try_current()
+                .ok()
+                .map(|handle| RuntimeSnapshot {
+                    handle,
+                    workers: AtomicU64::default(),
+                    alive_tasks: AtomicU64::default(),
+                    global_queue_depth: AtomicU64::default(),
+                }),
+            ..Default::default()
+        });
+        snapshot.sample();
+        register_gauges(provider, &snapshot);
+
+        let (stop, stopped) = mpsc::channel::<()>();
+        let thread = thread::Builder::new()
+            .name("telemetry-process-metrics".to_string())
+            .spawn(move || {
+                while let Err(mpsc::RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                    snapshot.sample();
+                }
+            })?;
+        Ok(ProcessMetrics {
+            stop: Some(stop),
+            thread: Some(thread),
+        })
+    }
+}
+
+impl Drop for ProcessMetrics {
+    fn drop(&mut self) {
+        // Disconnecting the channel wakes the collector thread and ends its loop.
+        drop(self.stop.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Registers the observable gauges reading from `snapshot`.
+fn register_gauges(provider: &SdkMeterProvider, snapshot: &Arc<Snapshot>) {
+    let meter = provider.meter(env!("CARGO_PKG_NAME"));
+
+    let values = snapshot.clone();
+    meter
+        .u64_observable_gauge("process.memory.usage")
+        .with_description("The resident set size of the process.")
+        .with_unit(Unit::new("By"))
+        .with_callback(move |gauge| gauge.observe(values.memory_usage.load(Ordering::Relaxed), &[]))
+        .init();
+
+    let values = snapshot.clone();
+    meter
+        .u64_observable_gauge("process.open_file_descriptor.count")
+        .with_description("The number of file descriptors in use by the process.")
+        .with_unit(Unit::new("{count}"))
+        .with_callback(move |gauge| gauge.observe(values.open_fds.load(Ordering::Relaxed), &[]))
+        .init();
+
+    #[cfg(feature = "tokio-metrics")]
+    if snapshot.runtime.is_some() {
+        register_runtime_gauge(
+            &meter,
+            snapshot,
+            "tokio.runtime.workers",
+            "The number of worker threads used by the runtime.",
+            |runtime| &runtime.workers,
+        );
+        register_runtime_gauge(
+            &meter,
+            snapshot,
+            "tokio.runtime.alive_tasks",
+            "The number of tasks currently alive in the runtime.",
+            |runtime| &runtime.alive_tasks,
+        );
+        register_runtime_gauge(
+            &meter,
+            snapshot,
+            "tokio.runtime.global_queue_depth",
+            "The number of tasks in the runtime's global queue.",
+            |runtime| &runtime.global_queue_depth,
+        );
+    }
+}
+
+/// Registers a tokio runtime gauge reporting the value selected by `value`.
+#[cfg(feature = "tokio-metrics")]
+fn register_runtime_gauge(
+    meter: &opentelemetry::metrics::Meter,
+    snapshot: &Arc<Snapshot>,
+    name: &'static str,
+    description: &'static str,
+    value: fn(&RuntimeSnapshot) -> &AtomicU64,
+) {
+    let values = snapshot.clone();
+    meter
+        .u64_observable_gauge(name)
+        .with_description(description)
+        .with_unit(Unit::new("{count}"))
+        .with_callback(move |gauge| {
+            if let Some(runtime) = &values.runtime {
+                gauge.observe(value(runtime).load(Ordering::Relaxed), &[]);
+            }
+        })
+        .init();
+}
+
+/// Reads the resident set size of the process in bytes.
+#[cfg(target_os = "linux")]
+fn read_memory_usage() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+    let kilobytes: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kilobytes * 1024)
+}
+
+/// Resident set size is only collected on Linux.
+#[cfg(not(target_os = "linux"))]
+fn read_memory_usage() -> Option<u64> {
+    None
+}
+
+/// Counts the file descriptors open in the process.
+#[cfg(target_os = "linux")]
+fn read_open_fds() -> Option<u64> {
+    let entries = std::fs::read_dir("/proc/self/fd").ok()?;
+    // The directory handle used for listing is itself one of the entries.
+    Some((entries.count() as u64).saturating_sub(1))
+}
+
+/// Open file descriptors are only collected on Linux.
+#[cfg(not(target_os = "linux"))]
+fn read_open_fds() -> Option<u64> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry_sdk::metrics::{data, reader::MetricReader, ManualReader, Pipeline};
+    use std::sync::Weak;
+
+    #[test]
+    fn test_telemetry_counters() {
+        use tracing_subscriber::{layer::SubscriberExt, Registry};
+
+        let dropped = AtomicU64::new(3);
+        let before = TelemetryCounters::read(&dropped);
+        let subscriber = Registry::default().with(CountingLayer);
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info_span!("request").in_scope(|| {
+                tracing::info!("started");
+                tracing::info!("finished");
+            });
+        });
+        record_exported(5);
+
+        // Other tests share the counters, so only check that they grew.
+        let after = TelemetryCounters::read(&dropped);
+        assert!(after.spans_created > before.spans_created);
+        assert!(after.events_emitted >= before.events_emitted + 2);
+        assert!(after.spans_exported >= before.spans_exported + 5);
+        assert_eq!(after.spans_dropped, 3);
+    }
+
+    /// Exporter failing its first `failures` exports, or never completing when `hangs`.
+    struct FlakyExporter {
+        failures: AtomicU64,
+        attempts: AtomicU64,
+        hangs: bool,
+    }
+
+    impl AggregationSelector for FlakyExporter {
+        fn aggregation(&self, kind: InstrumentKind) -> Aggregation {
+            DefaultAggregationSelector::new().aggregation(kind)
+        }
+    }
+
+    impl TemporalitySelector for FlakyExporter {
+        fn temporality(&self, kind: InstrumentKind) -> Temporality {
+            DefaultTemporalitySelector::new().temporality(kind)
+        }
+    }
+
+    #[async_trait]
+    impl PushMetricsExporter for FlakyExporter {
+        async fn export(&self, _: &mut ResourceMetrics) -> opentelemetry::metrics::Result<()> {
+            self.attempts.fetch_add(1, Ordering::Relaxed);
+            if self.hangs {
+                std::future::pending::<()>().await;
+            }
+            let failed = self
+                .failures
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| n.checked_sub(1));
+            match failed {
+                Ok(_) => Err(MetricsError::Other("collector unavailable".to_string())),
+                Err(_) => Ok(()),
+            }
+        }
+
+        async fn force_flush(&self) -> opentelemetry::metrics::Result<()> {
+            Ok(())
+        }
+
+        fn shutdown(&self) -> opentelemetry::metrics::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_metric_export_retry() {
+        let retry = MetricExportRetry {
+            max_retries: 2,
+            initial_backoff: Duration::from_millis(1),
+            max_elapsed: Duration::from_secs(5),
+        };
+        let exporter = |failures, hangs, retry| {
+            let inner = FlakyExporter {
+                failures: AtomicU64::new(failures),
+                attempts: AtomicU64::new(0),
+                hangs,
+            };
+            RetryingExporter::new(inner, Some(retry), DEFAULT_READER_TIMEOUT)
+        };
+        let mut metrics = ResourceMetrics {
+            resource: Resource::empty(),
+            scope_metrics: Vec::new(),
+        };
+        let failed = || METRIC_EXPORTS_FAILED.load(Ordering::Relaxed);
+
+        let before = failed();
+        let recovering = exporter(2, false, retry);
+        recovering.export(&mut metrics).await.unwrap();
+        assert_eq!(recovering.inner.attempts.load(Ordering::Relaxed), 3);
+
+        let down = exporter(u64::MAX, false, retry);
+        assert!(down.export(&mut metrics).await.is_err());
+        assert_eq!(down.inner.attempts.load(Ordering::Relaxed), 3);
+        assert!(failed() > before);
+
+        // A black-holed collector uses up `max_elapsed` instead of the reader timeout.
+        let before = failed();
+        let hanging = exporter(
+            0,
+            true,
+            MetricExportRetry {
+                max_elapsed: Duration::from_millis(20),
+                ..retry
+            },
+        );
+        assert!(hanging.export(&mut metrics).await.is_err());
+        assert_eq!(hanging.inner.attempts.load(Ordering::Relaxed), 1);
+        assert!(failed() > before);
+
+        // Exports cancelled like the reader does on its timeout still count.
+        let before = failed();
+        let hanging = exporter(0, true, retry);
+        let export = tokio::time::timeout(Duration::from_millis(20), hanging.export(&mut metrics));
+        assert!(export.await.is_err());
+        assert!(failed() > before);
+
+        // The budget is capped below the reader timeout.
+        let capped = exporter(0, false, retry);
+        assert_eq!(capped.budget, Duration::from_secs(5));
+        let capped = RetryingExporter::new(capped.inner, Some(retry), Duration::from_secs(3));
+        assert_eq!(capped.budget, Duration::from_secs(2));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_snapshot_sample() {
+        let snapshot = Snapshot::default();
+        snapshot.sample();
+        assert!(snapshot.memory_usage.load(Ordering::Relaxed) > 0);
+        assert!(snapshot.open_fds.load(Ordering::Relaxed) > 0);
+    }
+
+    /// Manual reader that can be collected after being handed to a meter provider.
+    #[derive(Clone, Debug)]
+    struct SharedReader(Arc<ManualReader>);
+
+    impl TemporalitySelector for SharedReader {
+        fn temporality(&self, kind: InstrumentKind) -> Temporality {
+            self.0.temporality(kind)
+        }
+    }
+
+    impl AggregationSelector for SharedReader {
+        fn aggregation(&self, kind: InstrumentKind) -> Aggregation {
+            self.0.aggregation(kind)
+        }
+    }
+
+    impl MetricReader for SharedReader {
+        fn register_pipeline(&self, pipeline: Weak<Pipeline>) {
+            self.0.register_pipeline(pipeline)
+        }
+
+        fn collect(&self, metrics: &mut ResourceMetrics) -> Result<(), MetricsError> {
+            self.0.collect(metrics)
+        }
+
+        fn force_flush(&self) -> Result<(), MetricsError> {
+            self.0.force_flush()
+        }
+
+        fn shutdown(&self) -> Result<(), MetricsError> {
+            self.0.shutdown()
+        }
+    }
+
+    #[test]
+    fn test_duration_view_buckets() {
+        let boundaries = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5];
+        let reader = SharedReader(Arc::new(ManualReader::builder().build()));
+        let provider = SdkMeterProvider::builder()
+            .with_reader(reader.clone())
+            .with_view(duration_view(&boundaries).unwrap())
+            .build();
+        let meter = provider.meter("test");
+        meter
+            .f64_histogram(HTTP_SERVER_DURATION)
+            .init()
+            .record(0.02, &[]);
+        meter
+            .f64_histogram("other.duration")
+            .init()
+            .record(0.02, &[]);
+
+        let mut metrics = ResourceMetrics {
+            resource: Resource::empty(),
+            scope_metrics: Vec::new(),
+        };
+        reader.collect(&mut metrics).unwrap();
+        let bounds = |name: &str| {
+            let metric = metrics.scope_metrics[0]
+                .metrics
+                .iter()
+                .find(|metric| metric.name == name)
+                .unwrap();
+            let histogram = metric
+                .data
+                .as_any()
+                .downcast_ref::<data::Histogram<f64>>()
+                .unwrap();
+            histogram.data_points[0].bounds.clone()
+        };
+        assert_eq!(bounds(HTTP_SERVER_DURATION), boundaries);
+        assert_ne!(bounds("other.duration"), boundaries);
+
+        assert!(duration_view(&[0.5, 0.1]).is_err());
+    }
+
+    #[actix_web::test]
+    async fn test_http_server_duration() {
+        use crate::CustomLoggerBuilder;
+        use actix_web::{test, web, App, HttpResponse};
+        use opentelemetry::Value;
+
+        let reader = SharedReader(Arc::new(ManualReader::builder().build()));
+        global::set_meter_provider(
+            SdkMeterProvider::builder()
+                .with_reader(reader.clone())
+                .build(),
+        );
+        let app = test::init_service(
+            App::new()
+                .wrap(CustomLoggerBuilder::new().build())
+                .route("/invoices/{id}", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+        test::call_service(
+            &app,
+            test::TestRequest::get().uri("/invoices/7").to_request(),
+        )
+        .await;
+
+        let mut metrics = ResourceMetrics {
+            resource: Resource::empty(),
+            scope_metrics: Vec::new(),
+        };
+        reader.collect(&mut metrics).unwrap();
+        let metric = metrics
+            .scope_metrics
+            .iter()
+            .flat_map(|scope| &scope.metrics)
+            .find(|metric| metric.name == HTTP_SERVER_DURATION)
+            .unwrap();
+        assert_eq!(metric.unit.as_str(), "s");
+        let histogram = metric
+            .data
+            .as_any()
+            .downcast_ref::<data::Histogram<f64>>()
+            .unwrap();
+        let attribute = |point: &data::HistogramDataPoint<f64>, key: &str| {
+            point
+                .attributes
+                .iter()
+                .find(|(name, _)| name.as_str() == key)
+                .map(|(_, value)| value.clone())
+        };
+        // Other tests may record requests through the global meter provider as well.
+        let point = histogram
+            .data_points
+            .iter()
+            .find(|point| attribute(point, "http.route") == Some(Value::from("/invoices/{id}")))
+            .unwrap();
+        assert_eq!(point.count, 1);
+        assert_eq!(attribute(point, "http.method"), Some(Value::from("GET")));
+        assert_eq!(attribute(point, "http.status_code"), Some(Value::I64(200)));
+    }
+
+    #[tokio::test]
+    async fn test_process_metrics_stop_on_drop() {
+        let provider = SdkMeterProvider::builder().build();
+        let metrics = ProcessMetrics::start(&provider, Duration::from_millis(10)).unwrap();
+        thread::sleep(Duration::from_millis(30));
+        drop(metrics);
+    }
+}
@@ -0,0 +1,794 @@
+//! Actix middleware built on top of the root span created by `TracingLogger`.
+
+use crate::{CustomFilterRootSpanBuilder, CustomLoggerBuilder};
+use actix_web::{
+    body::{BodySize, EitherBody, MessageBody},
+    dev::{forward_ready, Payload, Service, ServiceRequest, ServiceResponse, Transform},
+    error::ErrorInternalServerError,
+    http::{
+        header::{HeaderName, HeaderValue},
+        StatusCode,
+    },
+    web::Bytes,
+    Error, FromRequest, HttpMessage, HttpRequest,
+};
+use opentelemetry::trace::{TraceContextExt, TraceId};
+use std::{
+    fmt,
+    future::{ready, Future, Ready},
+    pin::Pin,
+    rc::Rc,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, OnceLock,
+    },
+    task::{Context, Poll},
+    time::Instant,
+};
+use tracing::{dispatcher, instrument::WithSubscriber, subscriber::NoSubscriber, Dispatch, Span};
+use tracing_actix_web::{RequestId, RootSpan, StreamSpan, TracingLogger};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+#[cfg(feature = "metrics")]
+use crate::NOT_FOUND_ROUTE;
+#[cfg(feature = "metrics")]
+use actix_web::http::Method;
+#[cfg(feature = "metrics")]
+use opentelemetry::{metrics::Histogram, KeyValue};
+
+/// Request logging middleware returned by [`CustomLoggerBuilder::build`].
+///
+/// Requests are handled by `TracingLogger` with [`CustomFilterRootSpanBuilder`], except for
+/// excluded routes, which bypass it entirely. Their handler runs with tracing disabled, so
+/// neither a root span nor the spans and events created inside the handler are recorded, and
+/// no request id is generated. On a liveness probe this removes the per-request cost of the
+/// request id, the root span extensions and any instrumented code in the handler.
+///
+/// `CustomFilterRootSpanBuilder` reads its settings from thread-local storage, so the
+/// middleware keeps the settings it was built with and applies them on each actix worker
+/// thread when the worker instantiates it. The middleware can therefore be built once, outside
+/// the `HttpServer::new` factory, and cloned into it: workers started later, including workers
+/// that replace one that panicked, get the same excluded routes and settings. Only the seed of
+/// sampled routes differs, offset by the number of workers instantiated before, see
+/// [`CustomLoggerBuilder::sample_seed`]. Nothing is registered per worker, so restarts neither
+/// leak state nor install anything twice. Calling
+/// `CustomFilterRootSpanBuilder::set_*` directly only affects the current thread.
+///
+/// [`CustomLoggerBuilder::build`]: crate::CustomLoggerBuilder::build
+#[derive(Clone)]
+pub struct CustomLogger {
+    tracing_logger: TracingLogger<CustomFilterRootSpanBuilder>,
+    settings: Arc<CustomLoggerBuilder>,
+    /// Number of workers instantiated so far, shared by the clones given to each worker.
+    workers: Arc<AtomicU64>,
+}
+
+impl CustomLogger {
+    /// Creates the middleware applying `settings` on every thread it is instantiated on.
+    pub(crate) fn new(settings: Arc<CustomLoggerBuilder>) -> Self {
+        Self {
+            tracing_logger: TracingLogger::new(),
+            settings,
+            workers: Arc::default(),
+        }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for CustomLogger
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<TracedBody<B>, B>>;
+    type Error = Error;
+    type Transform = CustomLoggerMiddleware<
+        S,
+        <TracingLogger<CustomFilterRootSpanBuilder> as Transform<
+            SharedService<S>,
+            ServiceRequest,
+        >>::Transform,
+    >;
+    type InitError = ();
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Transform, Self::InitError>>>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        // Called on the worker thread, which may not be the one the middleware was built on.
+        self.settings
+            .apply(self.workers.fetch_add(1, Ordering::Relaxed));
+        let service = Rc::new(service);
+        let traced = self
+            .tracing_logger
+            .new_transform(SharedService(Rc::clone(&service)));
+        Box::pin(async move {
+            Ok(CustomLoggerMiddleware {
+                traced: traced.await?,
+                service,
+                #[cfg(feature = "metrics")]
+                http_server_duration: crate::metrics::http_server_duration(),
+            })
+        })
+    }
+}
+
+/// Service created by [`CustomLogger`].
+#[doc(hidden)]
+pub struct CustomLoggerMiddleware<S, T> {
+    traced: T,
+    service: Rc<S>,
+    #[cfg(feature = "metrics")]
+    http_server_duration: Histogram<f64>,
+}
+
+impl<S, T, B> Service<ServiceRequest> for CustomLoggerMiddleware<S, T>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    T: Service<ServiceRequest, Response = ServiceResponse<StreamSpan<B>>, Error = Error>,
+    T::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<TracedBody<B>, B>>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, request: ServiceRequest) -> Self::Future {
+        if CustomFilterRootSpanBuilder::is_excluded(request.path()) {
+            // Run the handler, including the synchronous part of the call, without a
+            // subscriber so instrumentation inside it is skipped at the callsite.
+            let none = no_subscriber();
+            let future = dispatcher::with_default(&none, || self.service.call(request));
+            let future = future.with_subscriber(none);
+            return Box::pin(async move { Ok(future.await?.map_into_right_body()) });
+        }
+        let access_log = CustomFilterRootSpanBuilder::access_log_enabled()
+            .then(|| AccessLogEntry::start(&request));
+        let trace_response_body = CustomFilterRootSpanBuilder::trace_response_body_enabled();
+        #[cfg(feature = "metrics")]
+        let duration = RequestDuration::start(&self.http_server_duration, &request);
+        let future = self.traced.call(request);
+        Box::pin(async move {
+            let outcome = future.await;
+            #[cfg(feature = "metrics")]
+            duration.finish(&outcome);
+            let response = match outcome {
+                Ok(response) if trace_response_body => response,
+                outcome => {
+                    if let Some(entry) = access_log {
+                        entry.finish(&outcome);
+                    }
+                    return Ok(outcome?
+                        .map_body(|_, body| TracedBody::new(body, None))
+                        .map_into_left_body());
+                }
+            };
+            let completion = BodyCompletion {
+                span: response
+                    .request()
+                    .extensions()
+                    .get::<RootSpan>()
+                    .map_or_else(Span::none, |span| Span::clone(span)),
+                bytes: 0,
+                access_log: access_log.map(|entry| {
+                    let correlation_id = CorrelationId::from_request(response.request());
+                    (entry, response.status(), correlation_id)
+                }),
+            };
+            Ok(response
+                .map_body(|_, body| TracedBody::new(body, Some(completion)))
+                .map_into_left_body())
+        })
+    }
+}
+
+/// Target of the access log events emitted by [`CustomLogger`].
+///
+/// Use it in `RUST_LOG` to toggle the access log independently of other logs, e.g.
+/// `RUST_LOG=info,tembo_telemetry::access_log=off`.
+pub const ACCESS_LOG_TARGET: &str = "tembo_telemetry::access_log";
+
+/// Request details captured for the access log before the handler runs.
+struct AccessLogEntry {
+    started_at: Instant,
+    method: String,
+    path: String,
+    client_ip: String,
+}
+
+impl AccessLogEntry {
+    fn start(request: &ServiceRequest) -> Self {
+        Self {
+            started_at: Instant::now(),
+            method: request.method().to_string(),
+            path: request.path().to_string(),
+            client_ip: CustomFilterRootSpanBuilder::client_address(request),
+        }
+    }
+
+    /// Emits the access log event for the completed request.
+    ///
+    /// `bytes` is omitted for streamed responses, whose size is unknown when the headers are
+    /// sent.
+    fn finish<B: MessageBody>(self, outcome: &Result<ServiceResponse<B>, Error>) {
+        let (status, bytes, correlation_id) = match outcome {
+            Ok(response) => {
+                let bytes = match response.response().body().size() {
+                    BodySize::None => Some(0),
+                    BodySize::Sized(bytes) => Some(bytes),
+                    BodySize::Stream => None,
+                };
+                let correlation_id = CorrelationId::from_request(response.request());
+                (response.status(), bytes, correlation_id)
+            }
+            Err(error) => (error.as_response_error().status_code(), None, None),
+        };
+        self.emit(status, bytes, correlation_id.as_ref());
+    }
+
+    /// Emits the access log event, with the duration measured until now.
+    fn emit(self, status: StatusCode, bytes: Option<u64>, correlation_id: Option<&CorrelationId>) {
+        tracing::info!(
+            target: ACCESS_LOG_TARGET,
+            method = %self.method,
+            path = %self.path,
+            status = status.as_u16(),
+            duration_ms = self.started_at.elapsed().as_secs_f64() * 1000.0,
+            bytes,
+            client_ip = %self.client_ip,
+            correlation_id = correlation_id.map(CorrelationId::as_str),
+            "access"
+        );
+    }
+}
+
+/// Measurement of a request for the `http.server.duration` histogram.
+///
+/// The duration runs until the handler returns the response head, so streaming the body is not
+/// included.
+#[cfg(feature = "metrics")]
+struct RequestDuration {
+    histogram: Histogram<f64>,
+    started_at: Instant,
+    method: Method,
+}
+
+#[cfg(feature = "metrics")]
+impl RequestDuration {
+    fn start(histogram: &Histogram<f64>, request: &ServiceRequest) -> Self {
+        Self {
+            histogram: histogram.clone(),
+            started_at: Instant::now(),
+            method: request.method().clone(),
+        }
+    }
+
+    /// Records the duration with the method, route and status of the completed request.
+    ///
+    /// The route is unknown when the request failed with an error instead of a response.
+    fn finish<B>(self, outcome: &Result<ServiceResponse<B>, Error>) {
+        let (status, route) = match outcome {
+            Ok(response) => (
+                response.status(),
+                Some(
+                    response
+                        .request()
+                        .match_pattern()
+                        .unwrap_or_else(|| NOT_FOUND_ROUTE.to_string()),
+                ),
+            ),
+            Err(error) => (error.as_response_error().status_code(), None),
+        };
+        let mut attributes = vec![
+            KeyValue::new("http.method", self.method.as_str().to_string()),
+            KeyValue::new("http.status_code", i64::from(status.as_u16())),
+        ];
+        attributes.extend(route.map(|route| KeyValue::new("http.route", route)));
+        self.histogram
+            .record(self.started_at.elapsed().as_secs_f64(), &attributes);
+    }
+}
+
+pin_project_lite::pin_project! {
+    /// Response body of requests traced by [`CustomLogger`].
+    ///
+    /// When the response body is traced, see [`CustomLoggerBuilder::trace_response_body`],
+    /// counts the bytes written and completes the request once the body ends or is dropped.
+    ///
+    /// [`CustomLoggerBuilder::trace_response_body`]:
+    ///     crate::CustomLoggerBuilder::trace_response_body
+    pub struct TracedBody<B> {
+        #[pin]
+        body: StreamSpan<B>,
+        completion: Option<BodyCompletion>,
+    }
+
+    impl<B> PinnedDrop for TracedBody<B> {
+        fn drop(this: Pin<&mut Self>) {
+            // A body dropped before its end, e.g. when the client disconnects, still completes
+            // the request with the bytes written so far.
+            if let Some(completion) = this.project().completion.take() {
+                completion.finish();
+            }
+        }
+    }
+}
+
+impl<B> TracedBody<B> {
+    fn new(body: StreamSpan<B>, completion: Option<BodyCompletion>) -> Self {
+        Self { body, completion }
+    }
+}
+
+impl<B: MessageBody> MessageBody for TracedBody<B> {
+    type Error = B::Error;
+
+    fn size(&self) -> BodySize {
+        self.body.size()
+    }
+
+    fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Bytes, Self::Error>>> {
+        let this = self.project();
+        let poll = this.body.poll_next(cx);
+        match &poll {
+            Poll::Ready(Some(Ok(chunk))) => {
+                if let Some(completion) = this.completion {
+                    completion.bytes += chunk.len() as u64;
+                }
+            }
+            Poll::Ready(_) => {
+                if let Some(completion) = this.completion.take() {
+                    completion.finish();
+                }
+            }
+            Poll::Pending => {}
+        }
+        poll
+    }
+}
+
+/// Outcome of a request, recorded once its response body is written.
+struct BodyCompletion {
+    span: Span,
+    bytes: u64,
+    access_log: Option<(AccessLogEntry, StatusCode, Option<CorrelationId>)>,
+}
+
+impl BodyCompletion {
+    /// Records `http.response.body.size` on the root span and emits the access log event.
+    fn finish(self) {
+        self.span
+            .set_attribute("http.response.body.size", self.bytes as i64);
+        if let Some((entry, status, correlation_id)) = self.access_log {
+            entry.emit(status, Some(self.bytes), correlation_id.as_ref());
+        }
+    }
+}
+
+/// Returns the dispatcher excluded routes run under.
+///
+/// `Dispatch::none()` is not registered with the callsite registry, so a callsite first hit
+/// under it while the global subscriber is the only registered one would be cached as never
+/// enabled, also for requests that are traced. A registered `NoSubscriber` makes callsites
+/// ask the current dispatcher instead.
+fn no_subscriber() -> Dispatch {
+    static NO_SUBSCRIBER: OnceLock<Dispatch> = OnceLock::new();
+    NO_SUBSCRIBER
+        .get_or_init(|| Dispatch::new(NoSubscriber::default()))
+        .clone()
+}
+
+/// Service shared between `TracingLogger` and the untraced path of [`CustomLogger`].
+#[doc(hidden)]
+pub struct SharedService<S>(Rc<S>);
+
+impl<S> Service<ServiceRequest> for SharedService<S>
+where
+    S: Service<ServiceRequest>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.0.poll_ready(cx)
+    }
+
+    fn call(&self, request: ServiceRequest) -> Self::Future {
+        self.0.call(request)
+    }
+}
+
+/// Default response header used by [`TraceIdHeader`].
+pub const DEFAULT_TRACE_ID_HEADER: &str = "x-trace-id";
+
+/// Middleware writing the [`CorrelationId`] of each request to a response header.
+///
+/// The trace id is read from the root span created by `TracingLogger` once the handler has
+/// run, so responses can be correlated with their trace without touching every handler. When
+/// the request has no valid trace id, e.g. when no OTLP exporter is configured, the request id
+/// generated by `TracingLogger` is written instead. The header is omitted when neither exists,
+/// such as for requests not handled by `TracingLogger`.
+///
+/// Register it before `TracingLogger` so it runs inside the root span:
+///
+/// ```rust,ignore
+/// App::new()
+///     .wrap(TraceIdHeader::new())
+///     .wrap(get_tracing_logger().build())
+/// ```
+#[derive(Clone, Debug)]
+pub struct TraceIdHeader {
+    header_name: HeaderName,
+}
+
+impl TraceIdHeader {
+    /// Creates the middleware using the `x-trace-id` header.
+    pub fn new() -> Self {
+        Self {
+            header_name: HeaderName::from_static(DEFAULT_TRACE_ID_HEADER),
+        }
+    }
+
+    /// Specifies the response header the trace id is written to.
+    ///
+    /// # Arguments
+    ///
+    /// * `header_name` - The name of the response header.
+    pub fn header_name(mut self, header_name: HeaderName) -> Self {
+        self.header_name = header_name;
+        self
+    }
+}
+
+impl Default for TraceIdHeader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for TraceIdHeader
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = TraceIdHeaderMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(TraceIdHeaderMiddleware {
+            service,
+            header_name: self.header_name.clone(),
+        }))
+    }
+}
+
+/// Service created by [`TraceIdHeader`].
+#[doc(hidden)]
+pub struct TraceIdHeaderMiddleware<S> {
+    service: S,
+    header_name: HeaderName,
+}
+
+impl<S, B> Service<ServiceRequest> for TraceIdHeaderMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, request: ServiceRequest) -> Self::Future {
+        let header_name = self.header_name.clone();
+        let future = self.service.call(request);
+        Box::pin(async move {
+            let mut response = future.await?;
+            if let Some(correlation_id) = CorrelationId::from_request(response.request()) {
+                if let Ok(value) = HeaderValue::from_str(correlation_id.as_str()) {
+                    response.headers_mut().insert(header_name, value);
+                }
+            }
+            Ok(response)
+        })
+    }
+}
+
+/// Identifier correlating the logs and response of a request.
+///
+/// Resolves to the trace id of the root span when there is one, so logs can be matched with
+/// the exported trace, and to the request id generated by `TracingLogger` otherwise. The
+/// request id is generated whether or not OTLP is enabled, which keeps stdout-only deployments
+/// correlated as well. Use it as a handler argument:
+///
+/// ```rust,ignore
+/// async fn index(correlation_id: CorrelationId) -> String {
+///     format!("request {correlation_id}")
+/// }
+/// ```
+///
+/// Extraction fails with an internal server error when `TracingLogger` is not registered.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CorrelationId(String);
+
+impl CorrelationId {
+    /// Returns the identifier as a string.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Resolves the identifier from the request extensions set by `TracingLogger`.
+    fn from_request(request: &HttpRequest) -> Option<Self> {
+        let extensions = request.extensions();
+        let trace_id = extensions
+            .get::<RootSpan>()
+            .map(|root_span| root_span.context().span().span_context().trace_id())
+            .unwrap_or(TraceId::INVALID);
+        if trace_id != TraceId::INVALID {
+            return Some(Self(trace_id.to_string()));
+        }
+        extensions
+            .get::<RequestId>()
+            .map(|request_id| Self(request_id.to_string()))
+    }
+}
+
+impl fmt::Display for CorrelationId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl FromRequest for CorrelationId {
+    type Error = Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(request: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        ready(CorrelationId::from_request(request).ok_or_else(|| {
+            ErrorInternalServerError("no correlation id, is `TracingLogger` registered?")
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, web, App, HttpResponse};
+    use opentelemetry::trace::TracerProvider as _;
+    use opentelemetry_sdk::trace::TracerProvider;
+    use tracing_actix_web::TracingLogger;
+    use tracing_subscriber::{layer::SubscriberExt, Registry};
+
+    #[actix_web::test]
+    async fn test_trace_id_header() {
+        let provider = TracerProvider::builder().build();
+        let subscriber = Registry::default()
+            .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")));
+        let _default = tracing::subscriber::set_default(subscriber);
+
+        let app = test::init_service(
+            App::new()
+                .wrap(TraceIdHeader::new().header_name(HeaderName::from_static("x-request-trace")))
+                .wrap(TracingLogger::default())
+                .route("/", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+        let response = test::call_service(&app, test::TestRequest::get().to_request()).await;
+        let trace_id = response.headers().get("x-request-trace").unwrap();
+        assert_eq!(trace_id.len(), 32);
+        assert_ne!(trace_id, &TraceId::INVALID.to_string());
+    }
+
+    #[actix_web::test]
+    async fn test_header_falls_back_to_request_id() {
+        let app = test::init_service(
+            App::new()
+                .wrap(TraceIdHeader::default())
+                .wrap(TracingLogger::default())
+                .route(
+                    "/",
+                    web::get().to(|id: CorrelationId| async move { id.to_string() }),
+                ),
+        )
+        .await;
+        let response = test::call_service(&app, test::TestRequest::get().to_request()).await;
+        let header = response
+            .headers()
+            .get(DEFAULT_TRACE_ID_HEADER)
+            .cloned()
+            .unwrap();
+        let body = test::read_body(response).await;
+        assert_eq!(header.as_bytes(), &body[..]);
+        // Request ids are hyphenated UUIDs, trace ids are 32 hex digits.
+        assert_eq!(body.len(), 36);
+    }
+
+    #[actix_web::test]
+    async fn test_access_log() {
+        use crate::{format::tests::CapturedWriter, get_tracing_logger};
+        use tracing_subscriber::{fmt, Layer};
+
+        let output = CapturedWriter::default();
+        let writer = output.clone();
+        let subscriber = Registry::default().with(
+            fmt::layer()
+                .json()
+                .with_writer(move || writer.clone())
+                .with_filter(
+                    tracing_subscriber::filter::Targets::new()
+                        .with_target(ACCESS_LOG_TARGET, tracing::Level::INFO),
+                ),
+        );
+        let _default = tracing::subscriber::set_default(subscriber);
+
+        let app = test::init_service(
+            App::new()
+                .wrap(
+                    get_tracing_logger()
+                        .exclude("/health")
+                        .access_log(true)
+                        .build(),
+                )
+                .route("/health", web::get().to(HttpResponse::Ok))
+                .route("/users", web::post().to(|| async { "created" })),
+        )
+        .await;
+        let request = test::TestRequest::get().uri("/health").to_request();
+        test::call_service(&app, request).await;
+        let request = test::TestRequest::post()
+            .uri("/users")
+            .peer_addr("10.0.0.1:5000".parse().unwrap())
+            .to_request();
+        test::call_service(&app, request).await;
+        let request = test::TestRequest::get().uri("/missing").to_request();
+        test::call_service(&app, request).await;
+
+        let lines = output.lines();
+        assert_eq!(lines.len(), 2);
+        let line: serde_json::Value = serde_json::from_str(&lines[0]).unwrap();
+        assert_eq!(line["target"], ACCESS_LOG_TARGET);
+        let fields = &line["fields"];
+        assert_eq!(fields["method"], "POST");
+        assert_eq!(fields["path"], "/users");
+        assert_eq!(fields["status"], 200);
+        assert_eq!(fields["bytes"], 7);
+        assert_eq!(fields["client_ip"], "10.0.0.1");
+        assert!(fields["duration_ms"].as_f64().unwrap() >= 0.0);
+        assert_eq!(fields["correlation_id"].as_str().unwrap().len(), 36);
+        let line: serde_json::Value = serde_json::from_str(&lines[1]).unwrap();
+        assert_eq!(line["fields"]["status"], 404);
+    }
+
+    /// Streamed body writing one chunk per poll.
+    struct Chunks(Vec<&'static str>);
+
+    impl MessageBody for Chunks {
+        type Error = Error;
+
+        fn size(&self) -> BodySize {
+            BodySize::Stream
+        }
+
+        fn poll_next(
+            mut self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+        ) -> Poll<Option<Result<Bytes, Self::Error>>> {
+            let chunk = (!self.0.is_empty()).then(|| Ok(Bytes::from(self.0.remove(0))));
+            Poll::Ready(chunk)
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_trace_response_body() {
+        use crate::{
+            format::tests::CapturedWriter, get_tracing_logger,
+            processors::tests::collecting_subscriber,
+        };
+        use opentelemetry::Value;
+        use tracing_subscriber::{fmt, Layer};
+
+        let (collector, subscriber) = collecting_subscriber();
+        let output = CapturedWriter::default();
+        let writer = output.clone();
+        let subscriber = subscriber.with(
+            fmt::layer()
+                .json()
+                .with_writer(move || writer.clone())
+                .with_filter(
+                    tracing_subscriber::filter::Targets::new()
+                        .with_target(ACCESS_LOG_TARGET, tracing::Level::INFO),
+                ),
+        );
+        let _default = tracing::subscriber::set_default(subscriber);
+
+        let app = test::init_service(
+            App::new()
+                .wrap(
+                    get_tracing_logger()
+                        .access_log(true)
+                        .trace_response_body(true)
+                        .build(),
+                )
+                .route(
+                    "/events",
+                    web::get().to(|| async {
+                        HttpResponse::Ok().body(Chunks(vec!["data: a\n", "data: bc\n", "\n"]))
+                    }),
+                ),
+        )
+        .await;
+        let request = test::TestRequest::get().uri("/events").to_request();
+        let response = test::call_service(&app, request).await;
+        assert!(
+            output.lines().is_empty(),
+            "logged before the body was written"
+        );
+        assert_eq!(test::read_body(response).await.len(), 18);
+
+        let lines = output.lines();
+        assert_eq!(lines.len(), 1);
+        let line: serde_json::Value = serde_json::from_str(&lines[0]).unwrap();
+        assert_eq!(line["fields"]["bytes"], 18);
+        let spans = collector.0.lock().unwrap();
+        assert_eq!(spans.len(), 1);
+        let body_size = spans[0]
+            .attributes
+            .iter()
+            .find(|kv| kv.key.as_str() == "http.response.body.size")
+            .map(|kv| kv.value.clone());
+        assert_eq!(body_size, Some(Value::I64(18)));
+    }
+
+    #[actix_web::test]
+    async fn test_settings_applied_on_worker_threads() {
+        use crate::get_tracing_logger;
+
+        let logger = get_tracing_logger().exclude("/health").build();
+        // Each thread stands for a worker, the second one replacing the first after a panic.
+        for _ in 0..2 {
+            let logger = logger.clone();
+            std::thread::spawn(move || {
+                assert!(!CustomFilterRootSpanBuilder::is_excluded("/health"));
+                actix_web::rt::System::new().block_on(async move {
+                    let app = test::init_service(App::new().wrap(logger).route(
+                        "/health",
+                        web::get().to(|request: HttpRequest| async move {
+                            let traced = request.extensions().get::<RequestId>().is_some();
+                            traced.to_string()
+                        }),
+                    ))
+                    .await;
+                    assert!(CustomFilterRootSpanBuilder::is_excluded("/health"));
+                    let request = test::TestRequest::get().uri("/health").to_request();
+                    let body = test::call_and_read_body(&app, request).await;
+                    assert_eq!(&body[..], b"false");
+                });
+            })
+            .join()
+            .unwrap();
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_header_skipped_without_tracing_logger() {
+        let app = test::init_service(
+            App::new()
+                .wrap(TraceIdHeader::default())
+                .route("/", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+        let response = test::call_service(&app, test::TestRequest::get().to_request()).await;
+        assert!(response.headers().get(DEFAULT_TRACE_ID_HEADER).is_none());
+    }
+}
@@ -0,0 +1,731 @@
+//! OTLP exporter pipeline, available with the `otlp` feature.
+
+use crate::{
+    effective, health,
+    processors::{
+        BaggageAttributesProcessor, DeferredTraceProcessor, ExportQueue, InFlightExporter,
+        MinDurationSpanProcessor, QueueLimitSpanProcessor, SpanNameFilterProcessor,
+        TailSamplingProcessor, TransformSpanProcessor, TruncateAttributesProcessor,
+    },
+    resource::SharedResource,
+    ExportRuntime, TelemetryConfig, TelemetryError,
+};
+use opentelemetry::{global, trace::TracerProvider as _};
+use opentelemetry_otlp::{
+    SpanExporter, TonicExporterBuilder, WithExportConfig, OTEL_EXPORTER_OTLP_TIMEOUT_DEFAULT,
+};
+use opentelemetry_sdk::{
+    export::trace::SpanExporter as SdkSpanExporter,
+    runtime::{RuntimeChannel, Tokio, TokioCurrentThread},
+    trace::{self, BatchConfigBuilder, BatchSpanProcessor, SpanProcessor},
+};
+use std::{
+    collections::HashMap,
+    io,
+    net::{TcpStream, ToSocketAddrs},
+    sync::{atomic::AtomicU64, Arc},
+    time::Duration,
+};
+use tonic::{
+    metadata::{AsciiMetadataKey, AsciiMetadataValue, MetadataMap},
+    transport::Endpoint,
+};
+
+/// Endpoint scheme selecting a unix domain socket transport for the OTLP exporter.
+const UNIX_SOCKET_SCHEME: &str = "unix://";
+
+/// Variable setting the SDK's batch processor queue size.
+const MAX_QUEUE_SIZE_VAR: &str = "OTEL_BSP_MAX_QUEUE_SIZE";
+/// Queue size of the SDK's batch processor when the variable is unset.
+const DEFAULT_MAX_QUEUE_SIZE: usize = 2048;
+
+/// Time strict mode waits for each endpoint to accept a connection at startup.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+
+impl TelemetryConfig {
+    /// Builds a tracer exporting over OTLP, without installing a subscriber.
+    ///
+    /// Applies the same exporter configuration as `init`: the sampler, resource, additional
+    /// endpoints, keep-alive options, `span_transform` and `min_span_duration`. Use it to
+    /// assemble a custom `Registry` while reusing this configuration:
+    ///
+    /// ```rust,ignore
+    /// let tracer = telemetry_config.build_otlp_tracer()?;
+    /// let subscriber = Registry::default()
+    ///     .with(tracing_opentelemetry::layer().with_tracer(tracer))
+    ///     .with(my_layer);
+    /// tracing::subscriber::set_global_default(subscriber)?;
+    /// ```
+    ///
+    /// The tracer provider is installed globally, which keeps it alive. Call
+    /// `opentelemetry::global::shutdown_tracer_provider` before exiting to flush buffered spans.
+    /// The propagator, panic hook and meter provider are only installed by `init`.
+    ///
+    /// Fails with [`TelemetryError::MissingEndpoint`] when neither `endpoint_url` nor
+    /// `endpoint_template` resolves to an endpoint.
+    pub fn build_otlp_tracer(&self) -> Result<trace::Tracer, TelemetryError> {
+        let resource = self.resource();
+        let (_provider, tracer) = self
+            .tracer_pipeline(
+                self.trace_config(resource.clone()),
+                &SharedResource::new(resource),
+                &Arc::default(),
+            )?
+            .ok_or(TelemetryError::MissingEndpoint)?;
+        Ok(tracer)
+    }
+
+    /// Builds and installs the global tracer provider exporting to the resolved endpoint.
+    ///
+    /// Returns `None` when no endpoint is configured. Exported spans carry the latest value of
+    /// `resource`, spans dropped because an export queue is full are counted in `dropped`.
+    pub(crate) fn tracer_pipeline(
+        &self,
+        trace_config: trace::Config,
+        resource: &SharedResource,
+        dropped: &Arc<AtomicU64>,
+    ) -> Result<Option<(trace::TracerProvider, trace::Tracer)>, TelemetryError> {
+        let Some(endpoint_url) = self.resolved_endpoint_url() else {
+            return Ok(None);
+        };
+        let exporters = std::iter::once(&endpoint_url)
+            .chain(&self.additional_endpoint_urls)
+            .map(|url| {
+                Ok(self
+                    .otlp_exporter(url)?
+                    .with_metadata(metadata(&self.headers, &self.trace_headers))
+                    .build_span_exporter()?)
+            })
+            .collect::<Result<Vec<_>, TelemetryError>>()?;
+        let provider = match self.runtime {
+            ExportRuntime::TokioCurrentThread => self.tracer_provider(
+                exporters,
+                trace_config,
+                resource,
+                dropped,
+                TokioCurrentThread,
+            ),
+            ExportRuntime::Tokio => {
+                self.tracer_provider(exporters, trace_config, resource, dropped, Tokio)
+            }
+        };
+        let tracer = provider
+            .tracer_builder(env!("CARGO_PKG_NAME"))
+            .with_version(env!("CARGO_PKG_VERSION"))
+            .build();
+        global::set_tracer_provider(provider.clone());
+        Ok(Some((provider, tracer)))
+    }
+
+    /// Builds a tracer provider exporting batches through each of `exporters` on `runtime`.
+    fn tracer_provider<R: RuntimeChannel>(
+        &self,
+        exporters: Vec<SpanExporter>,
+        trace_config: trace::Config,
+        resource: &SharedResource,
+        dropped: &Arc<AtomicU64>,
+        runtime: R,
+    ) -> trace::TracerProvider {
+        let processors = self.span_processors(exporters, resource, dropped, runtime);
+        // Deferred traces are buffered once and forwarded to every endpoint.
+        let mut processor: Box<dyn SpanProcessor> =
+            Box::new(DeferredTraceProcessor::new(processors));
+        if let Some(tail_sampling) = self.tail_sampling {
+            processor = Box::new(TailSamplingProcessor::new(tail_sampling, processor));
+        }
+        // Without keys, spans are passed through unchanged.
+        let processor = BaggageAttributesProcessor::new(self.baggage_attributes.clone(), processor);
+        trace::TracerProvider::builder()
+            .with_config(trace_config)
+            .with_span_processor(processor)
+            .build()
+    }
+
+    /// Builds the processor chain of each of `exporters`.
+    ///
+    /// Every exporter gets its own batch processor and queue of
+    /// [`resolved_max_queue_size`](Self::resolved_max_queue_size) spans, so a slow or
+    /// unreachable endpoint does not hold back the others.
+    fn span_processors<E, R>(
+        &self,
+        exporters: Vec<E>,
+        resource: &SharedResource,
+        dropped: &Arc<AtomicU64>,
+        runtime: R,
+    ) -> Vec<Box<dyn SpanProcessor>>
+    where
+        E: SdkSpanExporter + 'static,
+        R: RuntimeChannel,
+    {
+        let mut processors: Vec<Box<dyn SpanProcessor>> = Vec::new();
+        let statuses = health::register_exporters(exporters.len());
+        for (exporter, status) in exporters.into_iter().zip(statuses) {
+            // The default builder reads the `OTEL_BSP_*` variables.
+            let mut batch_config = BatchConfigBuilder::default();
+            if let Some(max_queue_size) = self.max_queue_size {
+                batch_config = batch_config.with_max_queue_size(max_queue_size);
+            }
+            if let Some(watermark) = self.flush_watermark {
+                // The batch processor exports as soon as a full batch is queued.
+                batch_config = batch_config.with_max_export_batch_size(watermark);
+            }
+            if let Some(max_concurrent_exports) = self.max_concurrent_exports {
+                // With 0, the SDK would never wait for running exports.
+                batch_config =
+                    batch_config.with_max_concurrent_exports(max_concurrent_exports.max(1));
+            }
+            let batch_config = batch_config.build();
+            let queue = ExportQueue::new(self.resolved_max_queue_size(), dropped.clone());
+            let exporter = InFlightExporter::new(queue.clone(), status, exporter);
+            let batch = BatchSpanProcessor::builder(exporter, runtime.clone())
+                .with_batch_config(batch_config)
+                .build();
+            let mut batch: Box<dyn SpanProcessor> =
+                Box::new(QueueLimitSpanProcessor::new(queue, Box::new(batch)));
+            if let Some(max_length) = self.max_attribute_length {
+                batch = Box::new(TruncateAttributesProcessor::new(max_length, batch));
+            }
+            let processor: Box<dyn SpanProcessor> = match &self.span_transform {
+                Some(transform) => Box::new(TransformSpanProcessor::new(transform.clone(), batch)),
+                None => batch,
+            };
+            let processor = TransformSpanProcessor::new(resource.transform(), processor);
+            let processor: Box<dyn SpanProcessor> = match self.min_span_duration {
+                Some(min_duration) => Box::new(MinDurationSpanProcessor::new(
+                    min_duration,
+                    Box::new(processor),
+                )),
+                None => Box::new(processor),
+            };
+            processors.push(if self.excluded_span_names.is_empty() {
+                processor
+            } else {
+                Box::new(SpanNameFilterProcessor::new(
+                    self.excluded_span_names.clone(),
+                    processor,
+                ))
+            });
+        }
+        processors
+    }
+
+    /// Builds the tonic OTLP exporter for the given endpoint.
+    ///
+    /// Endpoints of the form `unix:///path/to/socket` connect over a unix domain socket, which
+    /// is only supported on unix platforms. Other endpoints use TCP.
+    ///
+    /// When any keep-alive option is set, the exporter uses a channel built here so the options
+    /// can be applied. Such a channel ignores the `OTEL_EXPORTER_OTLP_*` endpoint and timeout
+    /// environment variables in favor of `endpoint_url`.
+    pub(crate) fn otlp_exporter(
+        &self,
+        endpoint_url: &str,
+    ) -> Result<TonicExporterBuilder, TelemetryError> {
+        let exporter = opentelemetry_otlp::new_exporter().tonic();
+        if let Some(path) = endpoint_url.strip_prefix(UNIX_SOCKET_SCHEME) {
+            return self.uds_exporter(exporter, path);
+        }
+
+        let exporter = exporter.with_endpoint(endpoint_url);
+        if self.keep_alive_while_idle.is_none()
+            && self.http2_keep_alive_interval.is_none()
+            && self.keep_alive_timeout.is_none()
+        {
+            return Ok(exporter);
+        }
+        let endpoint = self.channel_endpoint(endpoint_url.to_string())?;
+        Ok(exporter.with_channel(endpoint.connect_lazy()))
+    }
+
+    /// Builds a tonic endpoint for `uri` with the configured timeout and keep-alive options.
+    fn channel_endpoint(&self, uri: String) -> Result<Endpoint, TelemetryError> {
+        let mut endpoint = Endpoint::from_shared(uri.clone())
+            .map_err(|err| TelemetryError::invalid_endpoint(&uri, err))?
+            .timeout(Duration::from_secs(OTEL_EXPORTER_OTLP_TIMEOUT_DEFAULT));
+        if let Some(while_idle) = self.keep_alive_while_idle {
+            endpoint = endpoint.keep_alive_while_idle(while_idle);
+        }
+        if let Some(interval) = self.http2_keep_alive_interval {
+            endpoint = endpoint.http2_keep_alive_interval(interval);
+        }
+        if let Some(timeout) = self.keep_alive_timeout {
+            endpoint = endpoint.keep_alive_timeout(timeout);
+        }
+        Ok(endpoint)
+    }
+
+    /// Returns the configured headers that cannot be sent, with the reason.
+    pub(crate) fn invalid_headers(&self) -> Vec<(String, &'static str)> {
+        let headers = self.headers.iter().chain(&self.trace_headers);
+        #[cfg(feature = "metrics")]
+        let headers = headers.chain(&self.metric_headers);
+        headers
+            .filter_map(|(name, value)| Some((name.clone(), header_problem(name, value)?)))
+            .collect()
+    }
+
+    /// Resolves the queue size of each endpoint, from `max_queue_size` or else like the SDK.
+    pub(crate) fn resolved_max_queue_size(&self) -> usize {
+        max_queue_size(
+            self.max_queue_size,
+            std::env::var(MAX_QUEUE_SIZE_VAR).ok().as_deref(),
+        )
+    }
+
+    /// Fails on the first configured header that cannot be sent, checked by strict mode.
+    pub(crate) fn check_headers(&self) -> Result<(), TelemetryError> {
+        match self.invalid_headers().into_iter().next() {
+            Some((name, reason)) => Err(TelemetryError::InvalidHeader { name, reason }),
+            None => Ok(()),
+        }
+    }
+
+    /// Fails on the first endpoint that does not accept a connection, checked by strict mode.
+    ///
+    /// Covers the trace endpoint, the additional endpoints and the metrics endpoint. Each one
+    /// is connected to and disconnected from again, blocking for up to 3 seconds per address.
+    pub(crate) fn check_endpoints_reachable(&self) -> Result<(), TelemetryError> {
+        let endpoint_urls = self
+            .resolved_endpoint_url()
+            .into_iter()
+            .chain(self.additional_endpoint_urls.iter().cloned());
+        #[cfg(feature = "metrics")]
+        let endpoint_urls = endpoint_urls.chain(self.resolved_metrics_endpoint_url());
+        for endpoint_url in endpoint_urls {
+            connect(&endpoint_url)?;
+        }
+        Ok(())
+    }
+
+    /// Configures `exporter` to connect to the unix domain socket at `path`.
+    #[cfg(unix)]
+    fn uds_exporter(
+        &self,
+        exporter: TonicExporterBuilder,
+        path: &str,
+    ) -> Result<TonicExporterBuilder, TelemetryError> {
+        if path.is_empty() {
+            return Err(TelemetryError::invalid_endpoint(
+                UNIX_SOCKET_SCHEME,
+                "missing socket path",
+            ));
+        }
+        let path = std::path::PathBuf::from(path);
+        // The URI is only used for the HTTP/2 authority, the socket path selects the peer.
+        let endpoint = self.channel_endpoint("http://localhost".to_string())?;
+        let channel = endpoint.connect_with_connector_lazy(tower::service_fn(move |_| {
+            tokio::net::UnixStream::connect(path.clone())
+        }));
+        Ok(exporter.with_channel(channel))
+    }
+
+    /// Unix domain sockets are not supported on this platform.
+    #[cfg(not(unix))]
+    fn uds_exporter(
+        &self,
+        _exporter: TonicExporterBuilder,
+        path: &str,
+    ) -> Result<TonicExporterBuilder, TelemetryError> {
+        Err(TelemetryError::invalid_endpoint(
+            &format!("{UNIX_SOCKET_SCHEME}{path}"),
+            "unix domain sockets are not supported on this platform",
+        ))
+    }
+}
+
+/// Returns the `configured` queue size, or else the `env` value of `OTEL_BSP_MAX_QUEUE_SIZE`
+/// when it parses, or else the SDK default.
+fn max_queue_size(configured: Option<usize>, env: Option<&str>) -> usize {
+    configured
+        .or_else(|| env.and_then(|size| size.parse().ok()))
+        .unwrap_or(DEFAULT_MAX_QUEUE_SIZE)
+}
+
+/// Opens and closes a connection to `endpoint_url`.
+///
+/// Errors report the URL with credentials redacted, since they end up in startup logs.
+fn connect(endpoint_url: &str) -> Result<(), TelemetryError> {
+    let redacted_url = effective::redact_url(endpoint_url);
+    let unreachable = |source| TelemetryError::UnreachableEndpoint {
+        url: redacted_url.clone(),
+        source,
+    };
+    if let Some(path) = endpoint_url.strip_prefix(UNIX_SOCKET_SCHEME) {
+        #[cfg(unix)]
+        return std::os::unix::net::UnixStream::connect(path)
+            .map(drop)
+            .map_err(unreachable);
+        #[cfg(not(unix))]
+        return Err(TelemetryError::invalid_endpoint(
+            path,
+            "unix domain sockets are not supported on this platform",
+        ));
+    }
+
+    let endpoint = Endpoint::from_shared(endpoint_url.to_string())
+        .map_err(|err| TelemetryError::invalid_endpoint(&redacted_url, err))?;
+    let uri = endpoint.uri();
+    let host = uri
+        .host()
+        .ok_or_else(|| TelemetryError::invalid_endpoint(&redacted_url, "missing host"))?;
+    let default_port = if uri.scheme_str() == Some("https") {
+        443
+    } else {
+        80
+    };
+    let port = uri.port_u16().unwrap_or(default_port);
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let mut last_error = io::Error::new(io::ErrorKind::NotFound, "host has no address");
+    for addr in (host, port).to_socket_addrs().map_err(unreachable)? {
+        match TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT) {
+            Ok(_) => return Ok(()),
+            Err(err) => last_error = err,
+        }
+    }
+    Err(unreachable(last_error))
+}
+
+/// Returns why the header `name: value` cannot be sent as gRPC metadata, if it cannot.
+fn header_problem(name: &str, value: &str) -> Option<&'static str> {
+    if AsciiMetadataKey::from_bytes(name.as_bytes()).is_err() {
+        Some("invalid header name")
+    } else if AsciiMetadataValue::try_from(value).is_err() {
+        Some("invalid header value")
+    } else {
+        None
+    }
+}
+
+/// Builds the gRPC metadata sent with exports of one signal.
+///
+/// `signal_headers` replace the entries of `headers` with the same name, compared
+/// case-insensitively. Invalid headers are skipped, see [`TelemetryConfig::invalid_headers`].
+pub(crate) fn metadata(
+    headers: &HashMap<String, String>,
+    signal_headers: &HashMap<String, String>,
+) -> MetadataMap {
+    let mut metadata = MetadataMap::new();
+    for (name, value) in headers.iter().chain(signal_headers) {
+        if let (Ok(name), Ok(value)) = (
+            AsciiMetadataKey::from_bytes(name.as_bytes()),
+            AsciiMetadataValue::try_from(value.as_str()),
+        ) {
+            metadata.insert(name, value);
+        }
+    }
+    metadata
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry::trace::Tracer as _;
+    use opentelemetry_sdk::{
+        export::trace::{ExportResult, SpanData},
+        Resource,
+    };
+    use std::{
+        future::Future,
+        pin::Pin,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Mutex,
+        },
+    };
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_tracer_pipeline_on_multi_thread_runtime() {
+        let config = TelemetryConfig {
+            endpoint_url: Some("http://localhost:4317".to_string()),
+            runtime: ExportRuntime::Tokio,
+            ..Default::default()
+        };
+        let (provider, _tracer) = config
+            .tracer_pipeline(
+                trace::config(),
+                &SharedResource::new(Resource::empty()),
+                &Arc::default(),
+            )
+            .unwrap()
+            .unwrap();
+        assert!(provider.force_flush().iter().all(Result::is_ok));
+
+        let config = TelemetryConfig::default();
+        assert!(config
+            .tracer_pipeline(
+                trace::config(),
+                &SharedResource::new(Resource::empty()),
+                &Arc::default(),
+            )
+            .unwrap()
+            .is_none());
+    }
+
+    /// Exporter collecting exported spans.
+    #[derive(Clone, Debug, Default)]
+    struct CollectingExporter(Arc<Mutex<Vec<SpanData>>>);
+
+    impl SdkSpanExporter for CollectingExporter {
+        fn export(
+            &mut self,
+            batch: Vec<SpanData>,
+        ) -> Pin<Box<dyn Future<Output = ExportResult> + Send + 'static>> {
+            self.0.lock().unwrap().extend(batch);
+            Box::pin(async { Ok(()) })
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_flush_watermark_exports_before_the_delay() {
+        let config = TelemetryConfig {
+            flush_watermark: Some(2),
+            ..Default::default()
+        };
+        let exporter = CollectingExporter::default();
+        let processors = config.span_processors(
+            vec![exporter.clone()],
+            &SharedResource::new(Resource::empty()),
+            &Arc::default(),
+            Tokio,
+        );
+        let provider = trace::TracerProvider::builder()
+            .with_span_processor(DeferredTraceProcessor::new(processors))
+            .build();
+        let tracer = provider.tracer("test");
+        tracer.in_span("first", |_| {});
+        tracer.in_span("second", |_| {});
+
+        // The scheduled export only runs after 5 seconds.
+        for _ in 0..100 {
+            if exporter.0.lock().unwrap().len() == 2 {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("spans were not exported once the watermark was reached");
+    }
+
+    /// Exporter recording the highest number of exports running at once.
+    #[derive(Clone, Debug, Default)]
+    struct SlowExporter {
+        in_flight: Arc<AtomicUsize>,
+        max_in_flight: Arc<AtomicUsize>,
+        exported: Arc<AtomicUsize>,
+    }
+
+    impl SdkSpanExporter for SlowExporter {
+        fn export(
+            &mut self,
+            batch: Vec<SpanData>,
+        ) -> Pin<Box<dyn Future<Output = ExportResult> + Send + 'static>> {
+            let in_flight = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max_in_flight.fetch_max(in_flight, Ordering::SeqCst);
+            let exporter = self.clone();
+            Box::pin(async move {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                exporter.exported.fetch_add(batch.len(), Ordering::SeqCst);
+                exporter.in_flight.fetch_sub(1, Ordering::SeqCst);
+                Ok(())
+            })
+        }
+    }
+
+    #[test]
+    fn test_max_queue_size() {
+        assert_eq!(max_queue_size(None, Some("4096")), 4096);
+        assert_eq!(max_queue_size(Some(16), Some("4096")), 16);
+        assert_eq!(max_queue_size(None, Some("many")), DEFAULT_MAX_QUEUE_SIZE);
+        assert_eq!(max_queue_size(None, None), DEFAULT_MAX_QUEUE_SIZE);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_max_concurrent_exports() {
+        let config = TelemetryConfig {
+            flush_watermark: Some(1),
+            max_concurrent_exports: Some(2),
+            ..Default::default()
+        };
+        let exporter = SlowExporter::default();
+        let processors = config.span_processors(
+            vec![exporter.clone()],
+            &SharedResource::new(Resource::empty()),
+            &Arc::default(),
+            Tokio,
+        );
+        let provider = trace::TracerProvider::builder()
+            .with_span_processor(DeferredTraceProcessor::new(processors))
+            .build();
+        let tracer = provider.tracer("test");
+        for _ in 0..8 {
+            tracer.in_span("burst", |_| {});
+        }
+
+        for _ in 0..100 {
+            if exporter.exported.load(Ordering::SeqCst) == 8 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(exporter.exported.load(Ordering::SeqCst), 8);
+        assert_eq!(exporter.max_in_flight.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_tracer_pipeline_with_additional_endpoints() {
+        let config = TelemetryConfig {
+            endpoint_url: Some("http://localhost:4317".to_string()),
+            additional_endpoint_urls: vec!["http://localhost:14317".to_string()],
+            ..Default::default()
+        };
+        assert!(config
+            .tracer_pipeline(
+                trace::config(),
+                &SharedResource::new(Resource::empty()),
+                &Arc::default(),
+            )
+            .unwrap()
+            .is_some());
+        // One batch processor per endpoint.
+        let exporters = ["http://localhost:4317", "http://localhost:14317"]
+            .iter()
+            .map(|url| {
+                config
+                    .otlp_exporter(url)
+                    .unwrap()
+                    .build_span_exporter()
+                    .unwrap()
+            })
+            .collect();
+        let processors = config.span_processors(
+            exporters,
+            &SharedResource::new(Resource::empty()),
+            &Arc::default(),
+            Tokio,
+        );
+        assert_eq!(processors.len(), 2);
+
+        let config = TelemetryConfig {
+            additional_endpoint_urls: vec!["not a uri".to_string()],
+            ..config
+        };
+        assert!(config
+            .tracer_pipeline(
+                trace::config(),
+                &SharedResource::new(Resource::empty()),
+                &Arc::default(),
+            )
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_build_otlp_tracer() {
+        let config = TelemetryConfig {
+            endpoint_url: Some("http://localhost:4317".to_string()),
+            ..Default::default()
+        };
+        assert!(config.build_otlp_tracer().is_ok());
+        assert!(matches!(
+            TelemetryConfig::default().build_otlp_tracer(),
+            Err(TelemetryError::MissingEndpoint)
+        ));
+        let config = TelemetryConfig {
+            endpoint_url: Some("unix://".to_string()),
+            ..Default::default()
+        };
+        assert!(matches!(
+            config.build_otlp_tracer(),
+            Err(TelemetryError::InvalidEndpoint { .. })
+        ));
+    }
+
+    #[test]
+    fn test_signal_metadata() {
+        let headers = HashMap::from([
+            ("x-api-key".to_string(), "shared".to_string()),
+            ("x-team".to_string(), "billing".to_string()),
+            ("bad header".to_string(), "ignored".to_string()),
+        ]);
+        let trace_headers = HashMap::from([("X-Api-Key".to_string(), "traces".to_string())]);
+        let metadata = metadata(&headers, &trace_headers);
+        assert_eq!(metadata.len(), 2);
+        assert_eq!(metadata.get("x-api-key").unwrap(), "traces");
+        assert_eq!(metadata.get("x-team").unwrap(), "billing");
+
+        let config = TelemetryConfig {
+            headers,
+            trace_headers: HashMap::from([("x-token".to_string(), "line\nbreak".to_string())]),
+            ..Default::default()
+        };
+        let mut invalid = config.invalid_headers();
+        invalid.sort();
+        assert_eq!(
+            invalid,
+            vec![
+                ("bad header".to_string(), "invalid header name"),
+                ("x-token".to_string(), "invalid header value"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_strict_checks() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let reachable = format!("http://{}", listener.local_addr().unwrap());
+        let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let unreachable = format!(
+            "http://user:password@{}/?token=abc",
+            closed.local_addr().unwrap()
+        );
+        drop(closed);
+
+        let config = TelemetryConfig {
+            endpoint_url: Some(reachable),
+            headers: HashMap::from([("x-api-key".to_string(), "secret".to_string())]),
+            ..Default::default()
+        };
+        config.check_headers().unwrap();
+        config.check_endpoints_reachable().unwrap();
+
+        let config = TelemetryConfig {
+            additional_endpoint_urls: vec![unreachable],
+            trace_headers: HashMap::from([("bad header".to_string(), "secret".to_string())]),
+            ..config
+        };
+        assert!(matches!(
+            config.check_headers(),
+            Err(TelemetryError::InvalidHeader { name, .. }) if name == "bad header"
+        ));
+        let err = config.check_endpoints_reachable().unwrap_err();
+        assert!(matches!(err, TelemetryError::UnreachableEndpoint { .. }));
+        // Credentials in the URL are redacted from the error.
+        let message = err.to_string();
+        assert!(!message.contains("password") && !message.contains("abc"));
+        #[cfg(unix)]
+        assert!(matches!(
+            connect("unix:///nonexistent/otel.sock"),
+            Err(TelemetryError::UnreachableEndpoint { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_otlp_exporter_with_keep_alive() {
+        let config = TelemetryConfig {
+            keep_alive_while_idle: Some(true),
+            http2_keep_alive_interval: Some(Duration::from_secs(30)),
+            keep_alive_timeout: Some(Duration::from_secs(10)),
+            ..Default::default()
+        };
+        let exporter = config.otlp_exporter("http://localhost:4317").unwrap();
+        assert!(exporter.build_span_exporter().is_ok());
+        assert!(config.otlp_exporter("not a uri").is_err());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_otlp_exporter_over_unix_socket() {
+        let config = TelemetryConfig::default();
+        let exporter = config.otlp_exporter("unix:///var/run/otel.sock").unwrap();
+        assert!(exporter.build_span_exporter().is_ok());
+        assert!(config.otlp_exporter("unix://").is_err());
+    }
+}
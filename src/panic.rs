@@ -0,0 +1,81 @@
+//! Panic hook recording panics on the current span.
+
+use crate::status;
+use std::{backtrace::Backtrace, panic};
+
+/// Installs a panic hook recording panics as error events on the current span.
+///
+/// The event carries `exception.type`, `exception.message` and `exception.stacktrace`, and
+/// marks the span as failed with the panic message as status description. With the `metrics`
+/// feature, the `process.panics` counter is incremented as well. The previously installed hook
+/// runs afterwards, so the default stderr output and hooks set by the application are kept.
+pub(crate) fn install_panic_hook() {
+    let previous = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        let payload = info.payload();
+        let message = payload
+            .downcast_ref::<&str>()
+            .copied()
+            .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+            .unwrap_or("Box<dyn Any>");
+        let location = info
+            .location()
+            .map(|location| format!("{}:{}", location.file(), location.line()))
+            .unwrap_or_default();
+        tracing::error!(
+            exception.r#type = "panic",
+            exception.message = %message,
+            exception.stacktrace = %Backtrace::force_capture(),
+            code.location = %location,
+            "panic"
+        );
+        status::set_error_status(&tracing::Span::current(), message);
+        #[cfg(feature = "metrics")]
+        opentelemetry::global::meter(env!("CARGO_PKG_NAME"))
+            .u64_counter("process.panics")
+            .with_description("The number of panics in the process.")
+            .init()
+            .add(1, &[]);
+        previous(info);
+    }));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processors::tests::{attribute, collecting_subscriber};
+    use opentelemetry::{trace::Status, Value};
+
+    #[test]
+    fn test_panics_recorded_on_current_span() {
+        let (collector, subscriber) = collecting_subscriber();
+
+        install_panic_hook();
+        tracing::subscriber::with_default(subscriber, || {
+            let result = panic::catch_unwind(|| {
+                tracing::info_span!("handler").in_scope(|| panic!("bucket missing"));
+            });
+            assert!(result.is_err());
+        });
+        drop(panic::take_hook());
+
+        let spans = collector.0.lock().unwrap();
+        assert_eq!(spans.len(), 1);
+        assert!(matches!(spans[0].status, Status::Error { .. }));
+        let event = spans[0].events.iter().next().unwrap();
+        assert_eq!(event.name, "panic");
+        assert_eq!(
+            attribute(&event.attributes, "exception.message"),
+            Some(Value::from("bucket missing"))
+        );
+        assert_eq!(
+            attribute(&event.attributes, "exception.type"),
+            Some(Value::from("panic"))
+        );
+        assert!(attribute(&event.attributes, "exception.stacktrace").is_some());
+        assert!(attribute(&event.attributes, "code.location")
+            .unwrap()
+            .as_str()
+            .starts_with("src/panic.rs"));
+    }
+}
@@ -0,0 +1,1128 @@
+//! Span processors applied before spans reach the exporter.
+//!
+//! Processors in this module wrap the exporting processor (the OTLP batch processor) and
+//! inspect or rewrite each span in `on_end`, before it is queued for export.
+
+use crate::{health::ExportStatus, TailSampling};
+use opentelemetry::{
+    baggage::BaggageExt,
+    trace::{Span as _, SpanContext, SpanId, Status, TraceFlags, TraceId, TraceResult},
+    Array, Context, Key, KeyValue, StringValue, Value,
+};
+use opentelemetry_sdk::{
+    export::trace::{ExportResult, SpanData, SpanExporter},
+    trace::{Span, SpanProcessor},
+};
+use std::{
+    collections::HashMap,
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex, OnceLock,
+    },
+    time::{Duration, Instant},
+};
+
+/// User-provided transform applied to every span before export.
+///
+/// Transforms can redact, rename or drop attributes, for example to strip query parameters
+/// from URLs or to remove high-cardinality values.
+#[derive(Clone)]
+pub struct SpanTransform(Arc<dyn Fn(&mut SpanData) + Send + Sync>);
+
+impl SpanTransform {
+    /// Creates a transform from a closure.
+    ///
+    /// # Arguments
+    ///
+    /// * `transform` - Called with each ended span before it is exported.
+    pub fn new<F>(transform: F) -> Self
+    where
+        F: Fn(&mut SpanData) + Send + Sync + 'static,
+    {
+        Self(Arc::new(transform))
+    }
+}
+
+impl fmt::Debug for SpanTransform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SpanTransform(..)")
+    }
+}
+
+/// Span processor running a [`SpanTransform`] before handing spans to an inner processor.
+///
+/// The transform runs synchronously in `on_end`, before the span is queued by the inner batch
+/// processor, so changes are reflected in the exported data.
+#[derive(Debug)]
+pub struct TransformSpanProcessor {
+    transform: SpanTransform,
+    inner: Box<dyn SpanProcessor>,
+}
+
+impl TransformSpanProcessor {
+    /// Creates a processor applying `transform` before delegating to `inner`.
+    ///
+    /// # Arguments
+    ///
+    /// * `transform` - The transform to apply to each span.
+    /// * `inner` - The processor that receives the transformed spans, usually the exporter's.
+    pub fn new(transform: SpanTransform, inner: Box<dyn SpanProcessor>) -> Self {
+        Self { transform, inner }
+    }
+}
+
+impl SpanProcessor for TransformSpanProcessor {
+    fn on_start(&self, span: &mut Span, cx: &Context) {
+        self.inner.on_start(span, cx);
+    }
+
+    fn on_end(&self, mut span: SpanData) {
+        (self.transform.0)(&mut span);
+        self.inner.on_end(span);
+    }
+
+    fn force_flush(&self) -> TraceResult<()> {
+        self.inner.force_flush()
+    }
+
+    fn shutdown(&mut self) -> TraceResult<()> {
+        self.inner.shutdown()
+    }
+}
+
+/// Span processor dropping spans shorter than a minimum duration.
+///
+/// Spans with an error status are always kept. The decision is made locally when a span ends,
+/// after sampling: it does not change the sampling decision propagated to child spans or
+/// downstream services, so a kept span can reference a parent that was dropped.
+#[derive(Debug)]
+pub struct MinDurationSpanProcessor {
+    min_duration: Duration,
+    inner: Box<dyn SpanProcessor>,
+}
+
+impl MinDurationSpanProcessor {
+    /// Creates a processor forwarding spans lasting at least `min_duration` to `inner`.
+    ///
+    /// # Arguments
+    ///
+    /// * `min_duration` - Spans ending sooner after they started are dropped.
+    /// * `inner` - The processor that receives the remaining spans, usually the exporter's.
+    pub fn new(min_duration: Duration, inner: Box<dyn SpanProcessor>) -> Self {
+        Self {
+            min_duration,
+            inner,
+        }
+    }
+
+    /// Returns whether `span` is forwarded to the inner processor.
+    fn keep(&self, span: &SpanData) -> bool {
+        if matches!(span.status, Status::Error { .. }) {
+            return true;
+        }
+        span.end_time
+            .duration_since(span.start_time)
+            .map_or(true, |duration| duration >= self.min_duration)
+    }
+}
+
+impl SpanProcessor for MinDurationSpanProcessor {
+    fn on_start(&self, span: &mut Span, cx: &Context) {
+        self.inner.on_start(span, cx);
+    }
+
+    fn on_end(&self, span: SpanData) {
+        if self.keep(&span) {
+            self.inner.on_end(span);
+        }
+    }
+
+    fn force_flush(&self) -> TraceResult<()> {
+        self.inner.force_flush()
+    }
+
+    fn shutdown(&mut self) -> TraceResult<()> {
+        self.inner.shutdown()
+    }
+}
+
+/// Span processor dropping spans whose name matches one of a list of patterns.
+///
+/// Patterns match span names exactly, except that `*` matches any sequence of characters, e.g.
+/// `sqlx::*` or `*.acquire`. Meant for spans of instrumented dependencies that clutter traces:
+/// they are only dropped from export, events recorded in them are still logged. Matching spans
+/// are dropped whatever their status, and their children are exported as usual.
+#[derive(Debug)]
+pub struct SpanNameFilterProcessor {
+    patterns: Vec<String>,
+    inner: Box<dyn SpanProcessor>,
+}
+
+impl SpanNameFilterProcessor {
+    /// Creates a processor forwarding spans matching none of `patterns` to `inner`.
+    ///
+    /// # Arguments
+    ///
+    /// * `patterns` - Span names to drop, where `*` matches any sequence of characters.
+    /// * `inner` - The processor that receives the remaining spans, usually the exporter's.
+    pub fn new(patterns: Vec<String>, inner: Box<dyn SpanProcessor>) -> Self {
+        Self { patterns, inner }
+    }
+
+    /// Returns whether `span` is forwarded to the inner processor.
+    fn keep(&self, span: &SpanData) -> bool {
+        !self
+            .patterns
+            .iter()
+            .any(|pattern| matches_pattern(pattern, &span.name))
+    }
+}
+
+impl SpanProcessor for SpanNameFilterProcessor {
+    fn on_start(&self, span: &mut Span, cx: &Context) {
+        self.inner.on_start(span, cx);
+    }
+
+    fn on_end(&self, span: SpanData) {
+        if self.keep(&span) {
+            self.inner.on_end(span);
+        }
+    }
+
+    fn force_flush(&self) -> TraceResult<()> {
+        self.inner.force_flush()
+    }
+
+    fn shutdown(&mut self) -> TraceResult<()> {
+        self.inner.shutdown()
+    }
+}
+
+/// Span processor copying baggage entries of the parent context onto each starting span.
+///
+/// Entries set upstream, such as `tenant_id`, are propagated with the trace context, so every
+/// span of the service becomes filterable by them without recording them by hand. Each entry
+/// is recorded as an attribute named after its key, on spans whose parent context carries it.
+/// Looking up the keys costs a hash lookup per key and span, plus a string copy for each entry
+/// found.
+#[derive(Debug)]
+pub struct BaggageAttributesProcessor {
+    keys: Vec<Key>,
+    inner: Box<dyn SpanProcessor>,
+}
+
+impl BaggageAttributesProcessor {
+    /// Creates a processor recording the baggage entries named `keys` before calling `inner`.
+    ///
+    /// # Arguments
+    ///
+    /// * `keys` - Baggage keys to record as span attributes.
+    /// * `inner` - The processor that receives the spans, usually the exporter's.
+    pub fn new(keys: Vec<String>, inner: Box<dyn SpanProcessor>) -> Self {
+        // Reference counted keys are cloned for each span without copying the string.
+        let keys = keys.into_iter().map(|key| Key::from(Arc::<str>::from(key)));
+        Self {
+            keys: keys.collect(),
+            inner,
+        }
+    }
+}
+
+impl SpanProcessor for BaggageAttributesProcessor {
+    fn on_start(&self, span: &mut Span, cx: &Context) {
+        if !self.keys.is_empty() {
+            let baggage = cx.baggage();
+            for key in &self.keys {
+                if let Some(value) = baggage.get(key.clone()) {
+                    span.set_attribute(KeyValue::new(key.clone(), value.clone()));
+                }
+            }
+        }
+        self.inner.on_start(span, cx);
+    }
+
+    fn on_end(&self, span: SpanData) {
+        self.inner.on_end(span);
+    }
+
+    fn force_flush(&self) -> TraceResult<()> {
+        self.inner.force_flush()
+    }
+
+    fn shutdown(&mut self) -> TraceResult<()> {
+        self.inner.shutdown()
+    }
+}
+
+/// Returns whether `name` matches `pattern`, in which `*` matches any sequence of characters.
+fn matches_pattern(pattern: &str, name: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = name.strip_prefix(first) else {
+        return false;
+    };
+    let mut parts: Vec<&str> = parts.collect();
+    let Some(last) = parts.pop() else {
+        // No wildcard, the name must match exactly.
+        return rest.is_empty();
+    };
+    for part in parts {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
+
+/// Suffix appended to attribute values cut by [`TruncateAttributesProcessor`].
+pub const TRUNCATION_SUFFIX: &str = "...[truncated]";
+
+/// Span processor truncating over-long string attribute values.
+///
+/// Applies to span and event attributes, including arrays of strings. Values longer than the
+/// limit keep their first `max_length` bytes, cut at a character boundary, followed by
+/// [`TRUNCATION_SUFFIX`]. Guards the collector against values such as serialized request
+/// bodies being recorded by accident.
+#[derive(Debug)]
+pub struct TruncateAttributesProcessor {
+    max_length: usize,
+    inner: Box<dyn SpanProcessor>,
+}
+
+impl TruncateAttributesProcessor {
+    /// Creates a processor truncating attribute values before delegating to `inner`.
+    ///
+    /// # Arguments
+    ///
+    /// * `max_length` - Maximum length in bytes of string values, excluding the suffix.
+    /// * `inner` - The processor that receives the truncated spans, usually the exporter's.
+    pub fn new(max_length: usize, inner: Box<dyn SpanProcessor>) -> Self {
+        Self { max_length, inner }
+    }
+
+    fn truncate_attributes(&self, attributes: &mut [KeyValue]) {
+        for kv in attributes {
+            match &mut kv.value {
+                Value::String(value) => self.truncate(value),
+                Value::Array(Array::String(values)) => {
+                    values.iter_mut().for_each(|value| self.truncate(value))
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn truncate(&self, value: &mut StringValue) {
+        if let Some(truncated) = truncate(value.as_str(), self.max_length) {
+            *value = truncated.into();
+        }
+    }
+}
+
+/// Cuts `text` to at most `max_length` bytes at a character boundary, followed by
+/// [`TRUNCATION_SUFFIX`]. Returns `None` when `text` fits.
+pub(crate) fn truncate(text: &str, max_length: usize) -> Option<String> {
+    if text.len() <= max_length {
+        return None;
+    }
+    let mut end = max_length;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    Some(format!("{}{TRUNCATION_SUFFIX}", &text[..end]))
+}
+
+impl SpanProcessor for TruncateAttributesProcessor {
+    fn on_start(&self, span: &mut Span, cx: &Context) {
+        self.inner.on_start(span, cx);
+    }
+
+    fn on_end(&self, mut span: SpanData) {
+        self.truncate_attributes(&mut span.attributes);
+        for event in span.events.events.iter_mut() {
+            self.truncate_attributes(&mut event.attributes);
+        }
+        self.inner.on_end(span);
+    }
+
+    fn force_flush(&self) -> TraceResult<()> {
+        self.inner.force_flush()
+    }
+
+    fn shutdown(&mut self) -> TraceResult<()> {
+        self.inner.shutdown()
+    }
+}
+
+/// Maximum number of traces held back by [`DeferredTraceProcessor`] at once.
+///
+/// Traces deferred beyond this limit are exported as usual, so a burst of slow requests
+/// cannot grow the buffer without bound.
+const MAX_DEFERRED_TRACES: usize = 4096;
+
+/// Spans of a deferred trace, held until its root span ends.
+#[derive(Debug)]
+struct DeferredTrace {
+    root: SpanId,
+    discard: bool,
+    spans: Vec<SpanData>,
+}
+
+/// Traces whose spans are held back until the outcome of their root span is known.
+fn deferred_traces() -> &'static Mutex<HashMap<TraceId, DeferredTrace>> {
+    static DEFERRED: OnceLock<Mutex<HashMap<TraceId, DeferredTrace>>> = OnceLock::new();
+    DEFERRED.get_or_init(Default::default)
+}
+
+/// Holds back the spans of `trace_id` until its root span `root` ends.
+///
+/// Returns `false` when too many traces are already deferred, in which case the trace is
+/// exported as usual.
+pub(crate) fn defer_trace(trace_id: TraceId, root: SpanId) -> bool {
+    let mut deferred = deferred_traces()
+        .lock()
+        .unwrap_or_else(|err| err.into_inner());
+    if deferred.len() >= MAX_DEFERRED_TRACES {
+        return false;
+    }
+    deferred.insert(
+        trace_id,
+        DeferredTrace {
+            root,
+            discard: false,
+            spans: Vec::new(),
+        },
+    );
+    true
+}
+
+/// Drops the spans of the deferred trace `trace_id` once its root span ends.
+pub(crate) fn discard_deferred_trace(trace_id: TraceId) {
+    let mut deferred = deferred_traces()
+        .lock()
+        .unwrap_or_else(|err| err.into_inner());
+    if let Some(trace) = deferred.get_mut(&trace_id) {
+        trace.discard = true;
+    }
+}
+
+/// Span processor holding back the spans of deferred traces until their root span ends.
+///
+/// Child spans usually end before the root span of a request, which is the first point at
+/// which the response status is known. Spans of traces deferred by the request middleware,
+/// see `CustomLoggerBuilder::discard_on_success`, are buffered until the root span ends, then
+/// either forwarded together or dropped. Spans of other traces, and spans ending after their
+/// root span, are forwarded immediately.
+#[derive(Debug)]
+pub(crate) struct DeferredTraceProcessor {
+    inner: Vec<Box<dyn SpanProcessor>>,
+}
+
+#[cfg_attr(not(feature = "otlp"), allow(dead_code))]
+impl DeferredTraceProcessor {
+    /// Creates a processor forwarding spans to each of the `inner` processors.
+    pub(crate) fn new(inner: Vec<Box<dyn SpanProcessor>>) -> Self {
+        Self { inner }
+    }
+
+    fn forward(&self, span: SpanData) {
+        if let Some((last, rest)) = self.inner.split_last() {
+            for processor in rest {
+                processor.on_end(span.clone());
+            }
+            last.on_end(span);
+        }
+    }
+}
+
+impl SpanProcessor for DeferredTraceProcessor {
+    fn on_start(&self, span: &mut Span, cx: &Context) {
+        for processor in &self.inner {
+            processor.on_start(span, cx);
+        }
+    }
+
+    fn on_end(&self, span: SpanData) {
+        let trace_id = span.span_context.trace_id();
+        let trace = {
+            let mut deferred = deferred_traces()
+                .lock()
+                .unwrap_or_else(|err| err.into_inner());
+            match deferred.get_mut(&trace_id) {
+                Some(trace) if trace.root != span.span_context.span_id() => {
+                    trace.spans.push(span);
+                    return;
+                }
+                Some(_) => deferred.remove(&trace_id),
+                None => None,
+            }
+        };
+        match trace {
+            None => self.forward(span),
+            Some(trace) if trace.discard => {}
+            Some(trace) => {
+                for span in trace.spans {
+                    self.forward(span);
+                }
+                self.forward(span);
+            }
+        }
+    }
+
+    fn force_flush(&self) -> TraceResult<()> {
+        self.inner
+            .iter()
+            .map(|processor| processor.force_flush())
+            .fold(Ok(()), Result::and)
+    }
+
+    fn shutdown(&mut self) -> TraceResult<()> {
+        self.inner
+            .iter_mut()
+            .map(|processor| processor.shutdown())
+            .fold(Ok(()), Result::and)
+    }
+}
+
+/// Spans of a trace that was not sampled, held until it fails or its window expires.
+#[derive(Debug)]
+struct TailTrace {
+    /// When the last span of the trace ended.
+    updated: Instant,
+    /// Whether a span of the trace failed, so its later spans are exported right away.
+    failed: bool,
+    spans: Vec<SpanData>,
+}
+
+/// Traces buffered by a [`TailSamplingProcessor`].
+#[derive(Debug, Default)]
+struct TailTraces {
+    traces: HashMap<TraceId, TailTrace>,
+    buffered_spans: usize,
+    swept: Option<Instant>,
+}
+
+impl TailTraces {
+    /// Drops the traces without a span ending in the last `window`.
+    ///
+    /// Runs at most once per second, so ending spans do not scan every buffered trace.
+    fn sweep(&mut self, now: Instant, window: Duration) {
+        if self
+            .swept
+            .is_some_and(|swept| now.duration_since(swept) < window.min(Duration::from_secs(1)))
+        {
+            return;
+        }
+        self.swept = Some(now);
+        let buffered_spans = &mut self.buffered_spans;
+        self.traces.retain(|_, trace| {
+            let keep = now.duration_since(trace.updated) < window;
+            if !keep {
+                *buffered_spans -= trace.spans.len();
+            }
+            keep
+        });
+    }
+}
+
+/// Span processor exporting traces that were not sampled once one of their spans fails.
+///
+/// Sampled spans are forwarded as usual. Spans that were not sampled, recorded because
+/// `TelemetryConfig::tail_sampling` is set, are buffered per trace for the configured window.
+/// When a span with an error status ends, the buffered spans of its trace, that span and the
+/// spans of the trace ending later are marked as sampled and forwarded. The buffer holds at
+/// most `max_buffered_spans` spans, further spans of traces that did not fail are dropped.
+#[derive(Debug)]
+pub(crate) struct TailSamplingProcessor {
+    config: TailSampling,
+    traces: Mutex<TailTraces>,
+    inner: Box<dyn SpanProcessor>,
+}
+
+#[cfg_attr(not(feature = "otlp"), allow(dead_code))]
+impl TailSamplingProcessor {
+    /// Creates a processor forwarding sampled and failed traces to `inner`.
+    pub(crate) fn new(config: TailSampling, inner: Box<dyn SpanProcessor>) -> Self {
+        Self {
+            config,
+            traces: Mutex::default(),
+            inner,
+        }
+    }
+
+    /// Returns the spans to export now that `span` ended.
+    fn buffer(&self, span: SpanData) -> Vec<SpanData> {
+        let now = Instant::now();
+        let failed = matches!(span.status, Status::Error { .. });
+        let mut traces = self.traces.lock().unwrap_or_else(|err| err.into_inner());
+        traces.sweep(now, self.config.window);
+        let full = traces.buffered_spans >= self.config.max_buffered_spans;
+        let TailTraces {
+            traces,
+            buffered_spans,
+            ..
+        } = &mut *traces;
+        let trace = match traces.get_mut(&span.span_context.trace_id()) {
+            Some(trace) => trace,
+            None if full && !failed => return Vec::new(),
+            None => traces
+                .entry(span.span_context.trace_id())
+                .or_insert(TailTrace {
+                    updated: now,
+                    failed: false,
+                    spans: Vec::new(),
+                }),
+        };
+        trace.updated = now;
+        if trace.failed {
+            vec![span]
+        } else if failed {
+            trace.failed = true;
+            *buffered_spans -= trace.spans.len();
+            let mut spans = std::mem::take(&mut trace.spans);
+            spans.push(span);
+            spans
+        } else {
+            if !full {
+                trace.spans.push(span);
+                *buffered_spans += 1;
+            }
+            Vec::new()
+        }
+    }
+}
+
+impl SpanProcessor for TailSamplingProcessor {
+    fn on_start(&self, span: &mut Span, cx: &Context) {
+        self.inner.on_start(span, cx);
+    }
+
+    fn on_end(&self, span: SpanData) {
+        if span.span_context.is_sampled() {
+            return self.inner.on_end(span);
+        }
+        for mut span in self.buffer(span) {
+            let cx = &span.span_context;
+            span.span_context = SpanContext::new(
+                cx.trace_id(),
+                cx.span_id(),
+                cx.trace_flags() | TraceFlags::SAMPLED,
+                cx.is_remote(),
+                cx.trace_state().clone(),
+            );
+            self.inner.on_end(span);
+        }
+    }
+
+    fn force_flush(&self) -> TraceResult<()> {
+        self.inner.force_flush()
+    }
+
+    fn shutdown(&mut self) -> TraceResult<()> {
+        self.inner.shutdown()
+    }
+}
+
+/// Bounded count of spans queued for export by one exporter.
+///
+/// Shared between the [`QueueLimitSpanProcessor`] in front of the batch processor, which takes
+/// a slot for each span, and the [`InFlightExporter`] behind it, which releases the slots once
+/// a batch is exported. Spans ending while every slot is taken are dropped and counted.
+#[cfg_attr(not(feature = "otlp"), allow(dead_code))]
+#[derive(Clone, Debug)]
+pub(crate) struct ExportQueue {
+    in_flight: Arc<AtomicUsize>,
+    max_in_flight: usize,
+    dropped: Arc<AtomicU64>,
+}
+
+#[cfg_attr(not(feature = "otlp"), allow(dead_code))]
+impl ExportQueue {
+    /// Creates a queue holding up to `max_in_flight` spans, counting drops in `dropped`.
+    ///
+    /// # Arguments
+    ///
+    /// * `max_in_flight` - The number of spans that can be queued or exporting at once.
+    /// * `dropped` - The counter incremented for each dropped span, shared across exporters.
+    pub(crate) fn new(max_in_flight: usize, dropped: Arc<AtomicU64>) -> Self {
+        Self {
+            in_flight: Arc::new(AtomicUsize::new(0)),
+            max_in_flight,
+            dropped,
+        }
+    }
+
+    /// Takes a slot for a span, returning `false` when the queue is full.
+    fn try_reserve(&self) -> bool {
+        self.in_flight
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |in_flight| {
+                (in_flight < self.max_in_flight).then_some(in_flight + 1)
+            })
+            .is_ok()
+    }
+}
+
+/// Slots of an exported batch, released when the export completes or is abandoned.
+#[cfg_attr(not(feature = "otlp"), allow(dead_code))]
+struct Reservation {
+    queue: ExportQueue,
+    spans: usize,
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        self.queue.in_flight.fetch_sub(self.spans, Ordering::AcqRel);
+    }
+}
+
+/// Span processor dropping spans once its [`ExportQueue`] is full.
+#[cfg_attr(not(feature = "otlp"), allow(dead_code))]
+#[derive(Debug)]
+pub(crate) struct QueueLimitSpanProcessor {
+    queue: ExportQueue,
+    inner: Box<dyn SpanProcessor>,
+}
+
+#[cfg_attr(not(feature = "otlp"), allow(dead_code))]
+impl QueueLimitSpanProcessor {
+    /// Creates a processor forwarding spans to `inner` while `queue` has room.
+    pub(crate) fn new(queue: ExportQueue, inner: Box<dyn SpanProcessor>) -> Self {
+        Self { queue, inner }
+    }
+}
+
+impl SpanProcessor for QueueLimitSpanProcessor {
+    fn on_start(&self, span: &mut Span, cx: &Context) {
+        self.inner.on_start(span, cx);
+    }
+
+    fn on_end(&self, span: SpanData) {
+        // Unsampled spans are not exported, so they do not take a slot.
+        if !span.span_context.is_sampled() {
+            return;
+        }
+        if self.queue.try_reserve() {
+            self.inner.on_end(span);
+        } else {
+            self.queue.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn force_flush(&self) -> TraceResult<()> {
+        self.inner.force_flush()
+    }
+
+    fn shutdown(&mut self) -> TraceResult<()> {
+        self.inner.shutdown()
+    }
+}
+
+/// Exporter releasing the [`ExportQueue`] slots of each batch it exports.
+#[cfg_attr(not(feature = "otlp"), allow(dead_code))]
+#[derive(Debug)]
+pub(crate) struct InFlightExporter<E> {
+    queue: ExportQueue,
+    status: ExportStatus,
+    inner: E,
+}
+
+#[cfg_attr(not(feature = "otlp"), allow(dead_code))]
+impl<E> InFlightExporter<E> {
+    /// Creates an exporter delegating to `inner` and releasing slots of `queue`.
+    ///
+    /// The result of each export is recorded in `status`.
+    pub(crate) fn new(queue: ExportQueue, status: ExportStatus, inner: E) -> Self {
+        Self {
+            queue,
+            status,
+            inner,
+        }
+    }
+}
+
+impl<E: SpanExporter> SpanExporter for InFlightExporter<E> {
+    fn export(
+        &mut self,
+        batch: Vec<SpanData>,
+    ) -> Pin<Box<dyn Future<Output = ExportResult> + Send + 'static>> {
+        // The batch processor drops the future when the export times out, which releases the
+        // reservation as well.
+        let reservation = Reservation {
+            queue: self.queue.clone(),
+            spans: batch.len(),
+        };
+        let export = self.inner.export(batch);
+        let status = self.status.clone();
+        Box::pin(async move {
+            let result = export.await;
+            #[cfg(feature = "metrics")]
+            if result.is_ok() {
+                crate::metrics::record_exported(reservation.spans);
+            }
+            drop(reservation);
+            status.record(&result);
+            result
+        })
+    }
+
+    fn shutdown(&mut self) {
+        self.inner.shutdown();
+    }
+
+    fn force_flush(&mut self) -> Pin<Box<dyn Future<Output = ExportResult> + Send + 'static>> {
+        self.inner.force_flush()
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use opentelemetry::{
+        trace::{Tracer, TracerProvider as _},
+        KeyValue,
+    };
+    use opentelemetry_sdk::trace::{self, TracerProvider};
+    use std::sync::Mutex;
+    use tracing_opentelemetry::OpenTelemetryLayer;
+    use tracing_subscriber::{
+        layer::{Layered, SubscriberExt},
+        Layer, Registry,
+    };
+
+    /// Processor collecting ended spans for assertions.
+    #[derive(Debug, Clone, Default)]
+    pub(crate) struct CollectingProcessor(pub(crate) Arc<Mutex<Vec<SpanData>>>);
+
+    /// Layer keeping the tracer provider alive, since tracers only hold a weak reference to it.
+    pub(crate) struct KeepProvider {
+        _provider: TracerProvider,
+    }
+
+    impl<S: tracing::Subscriber> Layer<S> for KeepProvider {}
+
+    /// Subscriber exporting `tracing` spans into a [`CollectingProcessor`].
+    pub(crate) type CollectingSubscriber =
+        Layered<KeepProvider, Layered<OpenTelemetryLayer<Registry, trace::Tracer>, Registry>>;
+
+    /// Subscriber whose spans end up in the returned collector.
+    pub(crate) fn collecting_subscriber() -> (CollectingProcessor, CollectingSubscriber) {
+        collecting_subscriber_with(trace::config())
+    }
+
+    /// Like [`collecting_subscriber`], with a custom tracer config such as a sampler.
+    pub(crate) fn collecting_subscriber_with(
+        config: trace::Config,
+    ) -> (CollectingProcessor, CollectingSubscriber) {
+        let collector = CollectingProcessor::default();
+        let provider = TracerProvider::builder()
+            .with_config(config)
+            .with_span_processor(collector.clone())
+            .build();
+        let subscriber = Registry::default()
+            .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")))
+            .with(KeepProvider {
+                _provider: provider,
+            });
+        (collector, subscriber)
+    }
+
+    /// Value of the attribute named `key`, if present.
+    pub(crate) fn attribute(attributes: &[KeyValue], key: &str) -> Option<Value> {
+        attributes
+            .iter()
+            .find(|kv| kv.key.as_str() == key)
+            .map(|kv| kv.value.clone())
+    }
+
+    impl SpanProcessor for CollectingProcessor {
+        fn on_start(&self, _span: &mut Span, _cx: &Context) {}
+
+        fn on_end(&self, span: SpanData) {
+            self.0.lock().unwrap().push(span);
+        }
+
+        fn force_flush(&self) -> TraceResult<()> {
+            Ok(())
+        }
+
+        fn shutdown(&mut self) -> TraceResult<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_transform_redacts_attributes() {
+        let collector = CollectingProcessor::default();
+        let transform = SpanTransform::new(|span| {
+            span.attributes
+                .retain(|kv| kv.key.as_str() != "db.statement");
+        });
+        let provider = TracerProvider::builder()
+            .with_span_processor(TransformSpanProcessor::new(
+                transform,
+                Box::new(collector.clone()),
+            ))
+            .build();
+
+        provider.tracer("test").in_span("query", |cx| {
+            use opentelemetry::trace::TraceContextExt;
+            cx.span().set_attributes(vec![
+                KeyValue::new("db.statement", "SELECT * FROM secrets"),
+                KeyValue::new("db.system", "postgresql"),
+            ]);
+        });
+
+        let spans = collector.0.lock().unwrap();
+        assert_eq!(spans.len(), 1);
+        let keys: Vec<_> = spans[0]
+            .attributes
+            .iter()
+            .map(|kv| kv.key.as_str())
+            .collect();
+        assert_eq!(keys, vec!["db.system"]);
+    }
+
+    #[test]
+    fn test_matches_pattern() {
+        assert!(matches_pattern("pool.acquire", "pool.acquire"));
+        assert!(!matches_pattern("pool.acquire", "pool.acquire_slow"));
+        assert!(matches_pattern("sqlx::*", "sqlx::query"));
+        assert!(matches_pattern("*.acquire", "deadpool.acquire"));
+        assert!(matches_pattern("a*b*c", "a-b-b-c"));
+        assert!(!matches_pattern("a*b*c", "a-c-b"));
+        assert!(!matches_pattern("ab*ba", "aba"));
+        assert!(matches_pattern("*", ""));
+    }
+
+    #[test]
+    fn test_span_name_filter() {
+        let collector = CollectingProcessor::default();
+        let provider = TracerProvider::builder()
+            .with_span_processor(SpanNameFilterProcessor::new(
+                vec!["pool.acquire".to_string(), "sqlx::*".to_string()],
+                Box::new(collector.clone()),
+            ))
+            .build();
+
+        let tracer = provider.tracer("test");
+        tracer.in_span("request", |_| {
+            tracer.in_span("pool.acquire", |_| {});
+            tracer.in_span("sqlx::query", |_| {});
+            tracer.in_span("render", |_| {});
+        });
+
+        let spans = collector.0.lock().unwrap();
+        let names: Vec<_> = spans.iter().map(|span| span.name.as_ref()).collect();
+        assert_eq!(names, ["render", "request"]);
+    }
+
+    #[test]
+    fn test_truncate_oversized_attributes() {
+        let collector = CollectingProcessor::default();
+        let provider = TracerProvider::builder()
+            .with_span_processor(TruncateAttributesProcessor::new(
+                8,
+                Box::new(collector.clone()),
+            ))
+            .build();
+
+        provider.tracer("test").in_span("request", |cx| {
+            use opentelemetry::trace::TraceContextExt;
+            let span = cx.span();
+            span.set_attributes(vec![
+                KeyValue::new("http.request.body", "x".repeat(2 * 1024 * 1024)),
+                KeyValue::new("user.name", "héllo wörld"),
+                KeyValue::new("http.route", "/short"),
+                KeyValue::new("http.status_code", 200),
+            ]);
+            span.add_event("payload", vec![KeyValue::new("body", "0123456789")]);
+        });
+
+        let spans = collector.0.lock().unwrap();
+        assert_eq!(
+            attribute(&spans[0].attributes, "http.request.body"),
+            Some(Value::from("xxxxxxxx...[truncated]"))
+        );
+        // Cut at the last character boundary within the limit.
+        assert_eq!(
+            attribute(&spans[0].attributes, "user.name"),
+            Some(Value::from("héllo w...[truncated]"))
+        );
+        assert_eq!(
+            attribute(&spans[0].attributes, "http.route"),
+            Some(Value::from("/short"))
+        );
+        assert_eq!(
+            attribute(&spans[0].attributes, "http.status_code"),
+            Some(Value::I64(200))
+        );
+        let event = spans[0].events.iter().next().unwrap();
+        assert_eq!(
+            event.attributes[0].value,
+            Value::from("01234567...[truncated]")
+        );
+    }
+
+    #[test]
+    fn test_min_duration_drops_fast_spans() {
+        use opentelemetry::trace::Span as _;
+        use std::time::SystemTime;
+
+        let collector = CollectingProcessor::default();
+        let provider = TracerProvider::builder()
+            .with_span_processor(MinDurationSpanProcessor::new(
+                Duration::from_millis(10),
+                Box::new(collector.clone()),
+            ))
+            .build();
+        let tracer = provider.tracer("test");
+        let start = SystemTime::now();
+        let end_after = |name: &'static str, duration: Duration, status: Status| {
+            let mut span = tracer
+                .span_builder(name)
+                .with_start_time(start)
+                .start(&tracer);
+            span.set_status(status);
+            span.end_with_timestamp(start + duration);
+        };
+        end_after("fast", Duration::from_millis(2), Status::Unset);
+        end_after("slow", Duration::from_millis(25), Status::Unset);
+        end_after("failed", Duration::from_millis(2), Status::error("boom"));
+
+        let spans = collector.0.lock().unwrap();
+        let names: Vec<_> = spans.iter().map(|span| span.name.as_ref()).collect();
+        assert_eq!(names, vec!["slow", "failed"]);
+    }
+
+    #[test]
+    fn test_tail_sampling_exports_failed_traces() {
+        use crate::sampling::RecordUnsampled;
+        use opentelemetry::trace::TraceContextExt;
+        use opentelemetry_sdk::trace::{config, Sampler};
+
+        let collector = CollectingProcessor::default();
+        let tail_sampling = TailSampling {
+            max_buffered_spans: 3,
+            ..Default::default()
+        };
+        let provider =
+            TracerProvider::builder()
+                .with_config(config().with_sampler(RecordUnsampled(Box::new(
+                    Sampler::ParentBased(Box::new(Sampler::AlwaysOff)),
+                ))))
+                .with_span_processor(TailSamplingProcessor::new(
+                    tail_sampling,
+                    Box::new(collector.clone()),
+                ))
+                .build();
+        let tracer = provider.tracer("test");
+        let request = |fail: bool| {
+            tracer.in_span("request", |_| {
+                tracer.in_span("query", |_| {});
+                tracer.in_span("render", |cx| {
+                    if fail {
+                        cx.span().set_status(Status::error("boom"));
+                    }
+                });
+            });
+        };
+        request(true);
+        request(false);
+        // The buffer is full, so the spans ending before the failure are dropped.
+        request(true);
+
+        let spans = collector.0.lock().unwrap();
+        let names: Vec<_> = spans.iter().map(|span| span.name.as_ref()).collect();
+        assert_eq!(
+            names,
+            vec!["query", "render", "request", "render", "request"]
+        );
+        assert!(spans.iter().all(|span| span.span_context.is_sampled()));
+        assert_eq!(
+            spans[0].span_context.trace_id(),
+            spans[2].span_context.trace_id()
+        );
+    }
+
+    #[test]
+    fn test_baggage_attributes() {
+        use opentelemetry::baggage::BaggageExt;
+
+        let collector = CollectingProcessor::default();
+        let provider = TracerProvider::builder()
+            .with_span_processor(BaggageAttributesProcessor::new(
+                vec!["tenant_id".to_string(), "region".to_string()],
+                Box::new(collector.clone()),
+            ))
+            .build();
+        let tracer = provider.tracer("test");
+
+        let _cx = Context::current()
+            .with_baggage(vec![
+                KeyValue::new("tenant_id", "acme"),
+                KeyValue::new("session", "secret"),
+            ])
+            .attach();
+        tracer.in_span("request", |_| tracer.in_span("query", |_| {}));
+
+        let spans = collector.0.lock().unwrap();
+        assert_eq!(spans.len(), 2);
+        for span in spans.iter() {
+            let attributes: Vec<_> = span
+                .attributes
+                .iter()
+                .map(|kv| (kv.key.as_str(), kv.value.as_str().into_owned()))
+                .collect();
+            assert_eq!(attributes, vec![("tenant_id", "acme".to_string())]);
+        }
+    }
+
+    /// Exporter accepting every batch.
+    #[derive(Debug)]
+    struct NoopExporter;
+
+    impl SpanExporter for NoopExporter {
+        fn export(
+            &mut self,
+            _batch: Vec<SpanData>,
+        ) -> Pin<Box<dyn Future<Output = ExportResult> + Send + 'static>> {
+            Box::pin(async { Ok(()) })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_queue_limit_drops_and_counts_spans() {
+        let dropped = Arc::new(AtomicU64::new(0));
+        let queue = ExportQueue::new(2, dropped.clone());
+        let collector = CollectingProcessor::default();
+        let provider = TracerProvider::builder()
+            .with_span_processor(QueueLimitSpanProcessor::new(
+                queue.clone(),
+                Box::new(collector.clone()),
+            ))
+            .build();
+        let tracer = provider.tracer("test");
+        for _ in 0..3 {
+            tracer.in_span("request", |_| {});
+        }
+        assert_eq!(collector.0.lock().unwrap().len(), 2);
+        assert_eq!(dropped.load(Ordering::Relaxed), 1);
+
+        // Exporting a batch frees its slots, abandoning an export does too.
+        let mut exporter =
+            InFlightExporter::new(queue.clone(), ExportStatus::default(), NoopExporter);
+        let batch = std::mem::take(&mut *collector.0.lock().unwrap());
+        exporter.export(batch).await.unwrap();
+        tracer.in_span("request", |_| {});
+        tracer.in_span("request", |_| {});
+        let batch = std::mem::take(&mut *collector.0.lock().unwrap());
+        drop(exporter.export(batch));
+        tracer.in_span("request", |_| {});
+        assert_eq!(collector.0.lock().unwrap().len(), 1);
+        assert_eq!(dropped.load(Ordering::Relaxed), 1);
+    }
+}
@@ -0,0 +1,495 @@
+//! Helpers for propagating W3C trace context and baggage across service boundaries.
+//!
+//! The propagator installed by `TelemetryInit::init` round-trips the `traceparent`,
+//! `tracestate` and `baggage` headers, so vendor data set upstream is preserved on every hop.
+//! The helpers in this module read and write individual `tracestate` and baggage entries and
+//! inject or extract context using whichever propagator is configured globally.
+//! [`spawn_traced`] carries the current context into work run on other threads.
+#![cfg_attr(
+    feature = "tokio",
+    doc = "[`spawn_blocking_traced`] does the same for the tokio blocking pool."
+)]
+
+use actix_web::http::header::{HeaderMap, HeaderName, HeaderValue};
+use opentelemetry::{
+    baggage::{BaggageExt, KeyValueMetadata},
+    global,
+    propagation::{Extractor, Injector, TextMapCompositePropagator},
+    trace::{SpanContext, SpanId, TraceContextExt, TraceId, TraceResult, TraceState},
+    Context, ContextGuard, KeyValue,
+};
+use opentelemetry_sdk::propagation::{BaggagePropagator, TraceContextPropagator};
+use std::{
+    collections::HashMap,
+    thread::{self, JoinHandle},
+};
+use tracing::{dispatcher, Dispatch, Span};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+/// Maximum length in bytes of a serialized `tracestate` header.
+///
+/// The W3C specification requires propagators to retain at least 512 bytes of `tracestate`.
+/// When writing an entry would grow the header past this limit, the oldest entries (those at
+/// the end of the list) are dropped until it fits, as recommended by the specification.
+pub const MAX_TRACE_STATE_LEN: usize = 512;
+
+/// Builds the propagator installed by `TelemetryInit::init`.
+///
+/// W3C trace context and W3C baggage are composed so both are injected and extracted together.
+pub(crate) fn propagator() -> TextMapCompositePropagator {
+    TextMapCompositePropagator::new(vec![
+        Box::new(TraceContextPropagator::new()),
+        Box::new(BaggagePropagator::new()),
+    ])
+}
+
+/// Returns the OpenTelemetry context of the current `tracing` span.
+///
+/// Baggage attached with [`set_baggage`] is merged into the returned context. Outside of any
+/// span, the currently attached OpenTelemetry context is returned.
+pub fn current_context() -> Context {
+    let attached = Context::current();
+    let cx = tracing::Span::current().context();
+    if !cx.has_active_span() {
+        return attached;
+    }
+    if attached.baggage().is_empty() {
+        return cx;
+    }
+    cx.with_baggage(attached.baggage().iter().map(|(key, (value, metadata))| {
+        KeyValueMetadata::new(key.clone(), value.clone(), metadata.clone())
+    }))
+}
+
+/// Reads a baggage entry from the current context.
+///
+/// Baggage received from upstream is carried by every span of the request, so it can be read
+/// anywhere in the handler.
+///
+/// # Arguments
+///
+/// * `key` - The baggage key to look up.
+pub fn get_baggage(key: &str) -> Option<String> {
+    current_context()
+        .baggage()
+        .get(key.to_string())
+        .map(|value| value.as_str().into_owned())
+}
+
+/// Sets a baggage entry on the current context.
+///
+/// The entry is visible through [`get_baggage`] and propagated by [`inject_current_context`]
+/// until the returned guard is dropped. Spans created afterwards as new roots also inherit it.
+///
+/// # Arguments
+///
+/// * `key` - The baggage key.
+/// * `value` - The value to store under `key`.
+pub fn set_baggage(key: &str, value: &str) -> ContextGuard {
+    current_context()
+        .with_baggage(vec![KeyValue::new(key.to_string(), value.to_string())])
+        .attach()
+}
+
+/// Reads a `tracestate` entry from the current span context.
+///
+/// # Arguments
+///
+/// * `key` - The `tracestate` list-member key to look up.
+pub fn trace_state_value(key: &str) -> Option<String> {
+    current_context()
+        .span()
+        .span_context()
+        .trace_state()
+        .get(key)
+        .map(str::to_string)
+}
+
+/// Returns a copy of `cx` whose span context carries `key=value` in its `tracestate`.
+///
+/// The new entry is placed at the front of the list, replacing any existing value for `key`.
+/// If the serialized header would exceed [`MAX_TRACE_STATE_LEN`], entries are removed from the
+/// end of the list until it fits. An error is returned if the key or value is invalid, or if
+/// the entry alone exceeds the limit.
+///
+/// Inject the returned context with [`inject_context`] to forward it downstream, or use it as
+/// the parent of a new span via `OpenTelemetrySpanExt::set_parent`.
+///
+/// # Arguments
+///
+/// * `cx` - The context to derive from, usually [`current_context`].
+/// * `key` - The `tracestate` list-member key.
+/// * `value` - The value to store under `key`.
+pub fn context_with_trace_state(cx: &Context, key: &str, value: &str) -> TraceResult<Context> {
+    let span = cx.span();
+    let span_context = span.span_context();
+    let trace_state = truncate_trace_state(span_context.trace_state().insert(key, value)?)?;
+    let span_context = SpanContext::new(
+        span_context.trace_id(),
+        span_context.span_id(),
+        span_context.trace_flags(),
+        span_context.is_remote(),
+        trace_state,
+    );
+    Ok(cx.with_remote_span_context(span_context))
+}
+
+/// Drops entries from the end of `trace_state` until it fits in [`MAX_TRACE_STATE_LEN`].
+fn truncate_trace_state(trace_state: TraceState) -> TraceResult<TraceState> {
+    let header = trace_state.header();
+    if header.len() <= MAX_TRACE_STATE_LEN {
+        return Ok(trace_state);
+    }
+
+    let mut entries: Vec<(&str, &str)> = header
+        .split(',')
+        .filter_map(|entry| entry.split_once('='))
+        .collect();
+    // Each entry is `key=value`, joined by commas.
+    let header_len = |entries: &[(&str, &str)]| -> usize {
+        entries
+            .iter()
+            .map(|(k, v)| k.len() + v.len() + 2)
+            .sum::<usize>()
+            - 1
+    };
+    while entries.len() > 1 && header_len(&entries) > MAX_TRACE_STATE_LEN {
+        entries.pop();
+    }
+    let truncated = TraceState::from_key_value(entries)?;
+    if truncated.header().len() > MAX_TRACE_STATE_LEN {
+        return Err(format!("tracestate entry exceeds {MAX_TRACE_STATE_LEN} bytes").into());
+    }
+    Ok(truncated)
+}
+
+/// Converts trace and span ids to the decimal ids Datadog uses to correlate logs with traces.
+///
+/// Datadog expects the lower 64 bits of the trace id and the span id as unsigned integers,
+/// logged as `dd.trace_id` and `dd.span_id`. Use it with the ids of [`current_context`] to
+/// correlate logs written outside of the stdout logger:
+///
+/// ```rust,ignore
+/// let cx = current_context();
+/// let span_context = cx.span().span_context();
+/// let (trace_id, span_id) = datadog_ids(span_context.trace_id(), span_context.span_id());
+/// ```
+///
+/// # Arguments
+///
+/// * `trace_id` - The OpenTelemetry trace id.
+/// * `span_id` - The OpenTelemetry span id.
+pub fn datadog_ids(trace_id: TraceId, span_id: SpanId) -> (u64, u64) {
+    let trace_id = u128::from_be_bytes(trace_id.to_bytes()) as u64;
+    (trace_id, u64::from_be_bytes(span_id.to_bytes()))
+}
+
+/// Injects `cx` into `injector` using the global text map propagator.
+///
+/// # Arguments
+///
+/// * `cx` - The context to propagate.
+/// * `injector` - The carrier to write headers into, e.g. [`HeaderInjector`].
+pub fn inject_context(cx: &Context, injector: &mut dyn Injector) {
+    global::get_text_map_propagator(|propagator| propagator.inject_context(cx, injector));
+}
+
+/// Injects the current span context into `injector` using the global text map propagator.
+///
+/// # Arguments
+///
+/// * `injector` - The carrier to write headers into, e.g. [`HeaderInjector`].
+pub fn inject_current_context(injector: &mut dyn Injector) {
+    inject_context(&current_context(), injector);
+}
+
+/// Extracts a context from `extractor` using the global text map propagator.
+///
+/// # Arguments
+///
+/// * `extractor` - The carrier to read headers from, e.g. [`HeaderExtractor`].
+pub fn extract_context(extractor: &dyn Extractor) -> Context {
+    global::get_text_map_propagator(|propagator| propagator.extract(extractor))
+}
+
+/// Serializes the current context into a string, e.g. to store it in a background job payload.
+///
+/// The context is injected with the global text map propagator, so the string carries the
+/// `traceparent`, `tracestate` and `baggage` entries as `name: value` lines, in the same form
+/// as the headers of a request. Read it back with [`deserialize_context`] when the job runs.
+/// Returns an empty string outside of any span when no baggage is set.
+pub fn serialize_context() -> String {
+    let mut entries = HashMap::new();
+    inject_current_context(&mut entries);
+    let mut lines: Vec<_> = entries
+        .iter()
+        .map(|(name, value)| format!("{name}: {value}"))
+        .collect();
+    lines.sort();
+    lines.join("\n")
+}
+
+/// Restores a context serialized by [`serialize_context`].
+///
+/// Lines that are not `name: value` entries are ignored, and a string without a valid
+/// `traceparent` yields a context without a span. Continue the trace with
+/// `span.set_parent(cx)`, or start a new one linking to it with `span_with_links`, which keeps
+/// the job's trace separate from the request's while still correlating them.
+///
+/// # Arguments
+///
+/// * `serialized` - A string returned by [`serialize_context`].
+pub fn deserialize_context(serialized: &str) -> Context {
+    let entries: HashMap<String, String> = serialized
+        .lines()
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_ascii_lowercase(), value.trim().to_string()))
+        .collect();
+    extract_context(&entries)
+}
+
+/// Spawns an OS thread running `f` in the current span and OpenTelemetry context.
+///
+/// `std::thread::spawn` starts threads without any context, so spans created there would start
+/// new traces. The current span is entered and the context returned by [`current_context`],
+/// including its baggage, is attached on the new thread before `f` runs, so its spans are
+/// children of the caller's span and report the same trace id.
+///
+/// # Arguments
+///
+/// * `f` - The work to run on the new thread.
+pub fn spawn_traced<F, T>(f: F) -> JoinHandle<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    thread::spawn(in_current_context(f))
+}
+
+/// Runs `f` on the tokio blocking pool in the current span and OpenTelemetry context.
+///
+/// The traced equivalent of `tokio::task::spawn_blocking`, see [`spawn_traced`]. Must be
+/// called from within a tokio runtime. Requires the `tokio` feature, enabled by `otlp`.
+///
+/// # Arguments
+///
+/// * `f` - The blocking work to run.
+#[cfg(feature = "tokio")]
+pub fn spawn_blocking_traced<F, T>(f: F) -> tokio::task::JoinHandle<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    tokio::task::spawn_blocking(in_current_context(f))
+}
+
+/// Wraps `f` to run with the caller's subscriber, span and OpenTelemetry context.
+fn in_current_context<F, T>(f: F) -> impl FnOnce() -> T + Send + 'static
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    // Subscribers set with `set_default` are thread-local and would not follow otherwise.
+    let dispatch = dispatcher::get_default(Dispatch::clone);
+    let span = Span::current();
+    let cx = current_context();
+    move || {
+        dispatcher::with_default(&dispatch, || {
+            let _entered = span.enter();
+            let _attached = cx.attach();
+            f()
+        })
+    }
+}
+
+/// Injector writing propagation headers into an actix `HeaderMap`.
+pub struct HeaderInjector<'a>(pub &'a mut HeaderMap);
+
+impl Injector for HeaderInjector<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        if let (Ok(name), Ok(value)) = (
+            HeaderName::from_bytes(key.as_bytes()),
+            HeaderValue::from_str(&value),
+        ) {
+            self.0.insert(name, value);
+        }
+    }
+}
+
+/// Extractor reading propagation headers from an actix `HeaderMap`.
+pub struct HeaderExtractor<'a>(pub &'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(HeaderName::as_str).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processors::tests::collecting_subscriber;
+    use opentelemetry::propagation::TextMapPropagator;
+
+    const TRACEPARENT: &str = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+    fn incoming(tracestate: &str) -> Context {
+        let mut headers = HashMap::new();
+        headers.insert("traceparent".to_string(), TRACEPARENT.to_string());
+        headers.insert("tracestate".to_string(), tracestate.to_string());
+        TraceContextPropagator::new().extract(&headers)
+    }
+
+    #[test]
+    fn test_tracestate_round_trip() {
+        global::set_text_map_propagator(propagator());
+        let cx = incoming("tembo=p:1,other=x");
+
+        let mut headers = HashMap::new();
+        inject_context(&cx, &mut headers);
+        assert_eq!(headers["traceparent"], TRACEPARENT);
+        assert_eq!(headers["tracestate"], "tembo=p:1,other=x");
+    }
+
+    #[test]
+    fn test_context_with_trace_state() {
+        let cx = incoming("other=x");
+        let cx = context_with_trace_state(&cx, "tembo", "p:2").unwrap();
+        let span = cx.span();
+        let trace_state = span.span_context().trace_state();
+        assert_eq!(trace_state.get("tembo"), Some("p:2"));
+        assert_eq!(trace_state.header(), "tembo=p:2,other=x");
+        assert!(context_with_trace_state(&cx, "Invalid Key", "v").is_err());
+    }
+
+    #[test]
+    fn test_trace_state_truncated_to_limit() {
+        let filler = "a".repeat(200);
+        let cx = incoming(&format!("one={filler},two={filler}"));
+        let cx = context_with_trace_state(&cx, "tembo", &"b".repeat(200)).unwrap();
+        let span = cx.span();
+        let trace_state = span.span_context().trace_state();
+        assert!(trace_state.header().len() <= MAX_TRACE_STATE_LEN);
+        assert!(trace_state.get("tembo").is_some());
+        assert!(trace_state.get("one").is_some());
+        assert!(trace_state.get("two").is_none());
+    }
+
+    #[test]
+    fn test_baggage_round_trip() {
+        global::set_text_map_propagator(propagator());
+        let mut headers = HashMap::new();
+        headers.insert("traceparent".to_string(), TRACEPARENT.to_string());
+        headers.insert("baggage".to_string(), "tenant_id=t1".to_string());
+        let _incoming = extract_context(&headers).attach();
+        assert_eq!(get_baggage("tenant_id").as_deref(), Some("t1"));
+
+        let _guard = set_baggage("request_priority", "high");
+        assert_eq!(get_baggage("request_priority").as_deref(), Some("high"));
+
+        let mut outgoing = HashMap::new();
+        inject_current_context(&mut outgoing);
+        assert_eq!(outgoing["traceparent"], TRACEPARENT);
+        let baggage = &outgoing["baggage"];
+        assert!(baggage.contains("tenant_id=t1"));
+        assert!(baggage.contains("request_priority=high"));
+    }
+
+    #[test]
+    fn test_datadog_ids() {
+        let trace_id = TraceId::from_hex("4bf92f3577b34da6a3ce929d0e0e4736").unwrap();
+        let span_id = SpanId::from_hex("00f067aa0ba902b7").unwrap();
+        assert_eq!(
+            datadog_ids(trace_id, span_id),
+            (11803532876627986230, 67667974448284343)
+        );
+    }
+
+    #[test]
+    fn test_header_injector_and_extractor() {
+        let cx = incoming("tembo=p:1");
+        let mut headers = HeaderMap::new();
+        TraceContextPropagator::new().inject_context(&cx, &mut HeaderInjector(&mut headers));
+        let extracted = TraceContextPropagator::new().extract(&HeaderExtractor(&headers));
+        let span = extracted.span();
+        assert_eq!(span.span_context().trace_state().get("tembo"), Some("p:1"));
+    }
+
+    #[test]
+    fn test_spawn_traced_keeps_trace() {
+        let (collector, subscriber) = collecting_subscriber();
+
+        tracing::subscriber::with_default(subscriber, || {
+            let parent = tracing::info_span!("parent");
+            let _entered = parent.enter();
+            let baggage = set_baggage("tenant", "acme");
+            spawn_traced(|| {
+                assert_eq!(get_baggage("tenant").as_deref(), Some("acme"));
+                tracing::info_span!("child").in_scope(|| {});
+            })
+            .join()
+            .unwrap();
+            drop(baggage);
+        });
+
+        let spans = collector.0.lock().unwrap();
+        let parent = spans.iter().find(|span| span.name == "parent").unwrap();
+        let child = spans.iter().find(|span| span.name == "child").unwrap();
+        assert_eq!(
+            child.span_context.trace_id(),
+            parent.span_context.trace_id()
+        );
+        assert_eq!(child.parent_span_id, parent.span_context.span_id());
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn test_spawn_blocking_traced_keeps_trace() {
+        let (_collector, subscriber) = collecting_subscriber();
+        let _default = tracing::subscriber::set_default(subscriber);
+
+        let parent = tracing::info_span!("parent");
+        let trace_id = parent.context().span().span_context().trace_id();
+        let child_trace_id = parent
+            .in_scope(|| {
+                spawn_blocking_traced(|| {
+                    tracing::info_span!("child")
+                        .context()
+                        .span()
+                        .span_context()
+                        .trace_id()
+                })
+            })
+            .await
+            .unwrap();
+        assert_eq!(child_trace_id, trace_id);
+    }
+
+    #[test]
+    fn test_serialize_context_round_trip() {
+        global::set_text_map_propagator(propagator());
+        let cx = incoming("tembo=p:1");
+        let serialized = {
+            let _attached = cx.clone().attach();
+            serialize_context()
+        };
+        assert!(serialized.contains(&format!("traceparent: {TRACEPARENT}")));
+
+        let restored = deserialize_context(&serialized);
+        let restored = restored.span().span_context().clone();
+        let original = cx.span().span_context().clone();
+        assert_eq!(restored.trace_id(), original.trace_id());
+        assert_eq!(restored.span_id(), original.span_id());
+        assert!(restored.is_remote());
+
+        assert!(!deserialize_context("").span().span_context().is_valid());
+        assert!(!deserialize_context("garbage")
+            .span()
+            .span_context()
+            .is_valid());
+    }
+}
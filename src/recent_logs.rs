@@ -0,0 +1,171 @@
+//! In-memory buffer of the most recent log lines.
+
+use crate::{effective, format::Timestamp, TelemetryError, TimestampFormat};
+use std::{
+    collections::VecDeque,
+    io,
+    sync::{Mutex, MutexGuard},
+};
+use tracing::Subscriber;
+use tracing_subscriber::{fmt::MakeWriter, registry::LookupSpan, Layer};
+
+/// Buffer of the lines written by the layer `init` installs.
+static BUFFER: Mutex<RecentLines> = Mutex::new(RecentLines {
+    lines: VecDeque::new(),
+    capacity: 0,
+});
+
+/// Configuration of the buffer enabled by
+/// [`TelemetryConfig::recent_logs`](crate::TelemetryConfig::recent_logs).
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
+pub struct RecentLogs {
+    /// Number of lines kept, older lines are discarded first. Defaults to 256.
+    pub capacity: usize,
+    /// Filter directives selecting the buffered events, in `RUST_LOG` syntax.
+    ///
+    /// Applied instead of `RUST_LOG`, so the buffer can keep events more verbose than the
+    /// logs that are written. Defaults to `debug`.
+    pub filter: String,
+}
+
+impl Default for RecentLogs {
+    fn default() -> Self {
+        Self {
+            capacity: 256,
+            filter: "debug".to_string(),
+        }
+    }
+}
+
+/// Bounded queue of formatted lines.
+struct RecentLines {
+    lines: VecDeque<String>,
+    capacity: usize,
+}
+
+impl RecentLines {
+    /// Appends `line`, discarding the oldest lines beyond the capacity.
+    fn push(&mut self, line: String) {
+        if self.capacity == 0 {
+            return;
+        }
+        while self.lines.len() >= self.capacity {
+            self.lines.pop_front();
+        }
+        self.lines.push_back(line);
+    }
+}
+
+fn buffer() -> MutexGuard<'static, RecentLines> {
+    BUFFER.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+/// Returns the buffered log lines, oldest first, without their trailing newline.
+///
+/// Lines are only buffered when [`TelemetryConfig::recent_logs`] is set, e.g. to attach the
+/// events leading up to an error to a crash report. Returns an empty list otherwise.
+///
+/// [`TelemetryConfig::recent_logs`]: crate::TelemetryConfig::recent_logs
+pub fn recent_logs() -> Vec<String> {
+    buffer().lines.iter().cloned().collect()
+}
+
+/// Returns the buffered log lines like [`recent_logs`], leaving the buffer empty.
+///
+/// Lets each report only carry the events since the previous one.
+pub fn take_recent_logs() -> Vec<String> {
+    buffer().lines.drain(..).collect()
+}
+
+/// Builds the layer writing events selected by `config.filter` to the buffer.
+///
+/// Resizes the buffer to `config.capacity`, keeping the most recent lines. Fails when the
+/// filter directives are invalid.
+pub(crate) fn layer<S>(
+    config: &RecentLogs,
+    timestamp_format: TimestampFormat,
+) -> Result<Box<dyn Layer<S> + Send + Sync>, TelemetryError>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    let filter = effective::parse_filter(&config.filter)?;
+    {
+        let mut buffer = buffer();
+        buffer.capacity = config.capacity;
+        let excess = buffer.lines.len().saturating_sub(config.capacity);
+        buffer.lines.drain(..excess);
+    }
+    Ok(tracing_subscriber::fmt::layer()
+        .compact()
+        .with_ansi(false)
+        .with_timer(Timestamp(timestamp_format))
+        .with_writer(BufferWriter)
+        .with_filter(filter)
+        .boxed())
+}
+
+/// Writer appending each formatted event to the buffer as one line.
+struct BufferWriter;
+
+impl MakeWriter<'_> for BufferWriter {
+    type Writer = BufferWriter;
+
+    fn make_writer(&self) -> Self::Writer {
+        BufferWriter
+    }
+}
+
+impl io::Write for BufferWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let line = buf.strip_suffix(b"\n").unwrap_or(buf);
+        buffer().push(String::from_utf8_lossy(line).into_owned());
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tracing_subscriber::{layer::SubscriberExt, EnvFilter, Registry};
+
+    #[test]
+    fn test_recent_logs() {
+        let config = RecentLogs {
+            capacity: 2,
+            filter: "debug".to_string(),
+        };
+        let subscriber = Registry::default()
+            .with(layer(&config, TimestampFormat::default()).unwrap())
+            .with(tracing_subscriber::fmt::layer().with_filter(EnvFilter::new("warn")));
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::trace!("connection polled");
+            tracing::debug!("cache miss");
+            tracing::debug!(attempt = 2, "retrying query");
+            tracing::info!("query failed");
+        });
+
+        let lines = recent_logs();
+        assert_eq!(lines.len(), 2, "{lines:?}");
+        assert!(lines[0].contains("retrying query attempt=2"), "{lines:?}");
+        assert!(lines[1].contains("INFO"), "{lines:?}");
+        assert!(lines[1].ends_with("query failed"), "{lines:?}");
+        assert_eq!(take_recent_logs(), lines);
+        assert!(recent_logs().is_empty());
+
+        let config = RecentLogs {
+            filter: "billing=loud".to_string(),
+            ..RecentLogs::default()
+        };
+        let err = layer::<Registry>(&config, TimestampFormat::default()).err();
+        assert!(matches!(err, Some(TelemetryError::InvalidFilter { .. })));
+    }
+}
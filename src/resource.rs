@@ -0,0 +1,122 @@
+//! Resource shared with the exporting pipeline so it can be extended after `init`.
+
+use crate::SpanTransform;
+use opentelemetry::KeyValue;
+use opentelemetry_sdk::Resource;
+use std::{
+    borrow::Cow,
+    sync::{Arc, RwLock},
+};
+
+/// Downward API environment variables read by `detect_k8s`, and the attribute each sets.
+const K8S_ENV_VARS: [(&str, &str); 5] = [
+    ("POD_NAME", "k8s.pod.name"),
+    ("POD_UID", "k8s.pod.uid"),
+    ("POD_NAMESPACE", "k8s.namespace.name"),
+    ("NODE_NAME", "k8s.node.name"),
+    ("DEPLOYMENT_NAME", "k8s.deployment.name"),
+];
+
+/// Returns the Kubernetes resource attributes found through `lookup`.
+///
+/// # Arguments
+///
+/// * `lookup` - Resolves an environment variable, skipping unset and empty values.
+pub(crate) fn k8s_attributes(lookup: impl Fn(&str) -> Option<String>) -> Vec<KeyValue> {
+    K8S_ENV_VARS
+        .iter()
+        .filter_map(|(var, key)| {
+            let value = lookup(var).filter(|value| !value.is_empty())?;
+            Some(KeyValue::new(*key, value))
+        })
+        .collect()
+}
+
+/// Resource applied to spans as they end.
+///
+/// The SDK fixes the resource of a tracer provider when it is built. Spans carry their own copy
+/// of it, so the exporting pipeline overwrites that copy with the latest resource held here,
+/// which lets attributes resolved after startup reach the collector.
+#[derive(Clone, Debug)]
+pub(crate) struct SharedResource(Arc<RwLock<Resource>>);
+
+impl SharedResource {
+    /// Creates a shared resource starting from the resource built by `init`.
+    pub(crate) fn new(resource: Resource) -> Self {
+        Self(Arc::new(RwLock::new(resource)))
+    }
+
+    /// Merges `attributes` into the resource, replacing existing values for the same keys.
+    pub(crate) fn merge(&self, attributes: impl IntoIterator<Item = KeyValue>) {
+        let update = Resource::new(attributes);
+        let mut resource = self.0.write().unwrap_or_else(|err| err.into_inner());
+        *resource = resource.merge(&update);
+    }
+
+    /// Returns a copy of the current resource.
+    #[cfg_attr(not(feature = "otlp"), allow(dead_code))]
+    pub(crate) fn get(&self) -> Resource {
+        self.0.read().unwrap_or_else(|err| err.into_inner()).clone()
+    }
+
+    /// Returns a span transform setting the current resource on each span.
+    #[cfg_attr(not(feature = "otlp"), allow(dead_code))]
+    pub(crate) fn transform(&self) -> SpanTransform {
+        let shared = self.clone();
+        SpanTransform::new(move |span| span.resource = Cow::Owned(shared.get()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processors::{tests::CollectingProcessor, TransformSpanProcessor};
+    use opentelemetry::{
+        trace::{Tracer as _, TracerProvider as _},
+        Key, Value,
+    };
+    use opentelemetry_sdk::trace::TracerProvider;
+
+    #[test]
+    fn test_k8s_attributes() {
+        let attributes = k8s_attributes(|var| match var {
+            "POD_NAME" => Some("api-7d9f-x2x".to_string()),
+            "POD_NAMESPACE" => Some("billing".to_string()),
+            "NODE_NAME" => Some(String::new()),
+            _ => None,
+        });
+        assert_eq!(
+            attributes,
+            vec![
+                KeyValue::new("k8s.pod.name", "api-7d9f-x2x"),
+                KeyValue::new("k8s.namespace.name", "billing"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_resource_updates_apply_to_later_spans() {
+        let resource = SharedResource::new(Resource::new([KeyValue::new("service.name", "app")]));
+        let collector = CollectingProcessor::default();
+        let provider = TracerProvider::builder()
+            .with_span_processor(TransformSpanProcessor::new(
+                resource.transform(),
+                Box::new(collector.clone()),
+            ))
+            .build();
+        let tracer = provider.tracer("test");
+
+        tracer.in_span("before", |_| {});
+        resource.merge([KeyValue::new("cloud.availability_zone", "us-east-1a")]);
+        tracer.in_span("after", |_| {});
+
+        let spans = collector.0.lock().unwrap();
+        let zone = Key::from_static_str("cloud.availability_zone");
+        assert_eq!(spans[0].resource.get(zone.clone()), None);
+        assert_eq!(spans[1].resource.get(zone), Some(Value::from("us-east-1a")));
+        assert_eq!(
+            spans[1].resource.get(Key::from_static_str("service.name")),
+            Some(Value::from("app"))
+        );
+    }
+}
@@ -0,0 +1,592 @@
+use opentelemetry::{
+    trace::{Link, SamplingDecision, SamplingResult, SpanKind, TraceContextExt, TraceId},
+    Context, KeyValue,
+};
+use opentelemetry_sdk::trace::{self, ShouldSample};
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tracing::{Event, Level, Metadata, Span, Subscriber};
+use tracing_opentelemetry::{OtelData, PreSampledTracer};
+use tracing_subscriber::{
+    layer::{self, Filter},
+    registry::LookupSpan,
+    Registry,
+};
+
+/// Sampler applied to spans created by the tracer provider.
+///
+/// This mirrors the samplers provided by `opentelemetry_sdk` in a form that can be stored on
+/// [`TelemetryConfig`](crate::TelemetryConfig) and compared in tests. With the `serde` feature,
+/// unit variants are written as snake case strings such as `"always_on"`, and the others as a
+/// single entry map such as `{ trace_id_ratio = 0.1 }`.
+///
+/// Defaults to `ParentBased(AlwaysOn)`: spans continuing a trace keep the sampling decision of
+/// their parent, including a remote parent from the `traceparent` header, and new traces are
+/// always sampled.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum Sampler {
+    /// Sample every span, even when its parent was not sampled.
+    ///
+    /// Ignoring the decision of an upstream service leaves gaps in distributed traces, so this
+    /// is usually wrapped in [`Sampler::ParentBased`].
+    AlwaysOn,
+    /// Never sample traces.
+    AlwaysOff,
+    /// Sample a fraction of traces based on their trace ID.
+    ///
+    /// The decision is a pure function of the trace ID, so a given trace is always sampled the
+    /// same way across services using the same ratio.
+    TraceIdRatio(f64),
+    /// Honor the sampling decision of the parent span, using the inner sampler for root spans.
+    ParentBased(Box<Sampler>),
+    /// Sample at most the given number of root spans per second.
+    ///
+    /// Root spans draw from a token bucket refilled at this rate, holding up to one second
+    /// of tokens, so the decision is made once when the root span is created and caps trace
+    /// volume during traffic spikes. Spans with a parent, including requests continuing a remote
+    /// trace, follow the parent's decision and are not counted against the limit.
+    RateLimited(f64),
+}
+
+impl Default for Sampler {
+    fn default() -> Self {
+        Sampler::ParentBased(Box::new(Sampler::AlwaysOn))
+    }
+}
+
+impl Sampler {
+    /// Converts the sampler into the equivalent `opentelemetry_sdk` sampler.
+    pub(crate) fn to_sdk(&self) -> trace::Sampler {
+        match self {
+            Sampler::AlwaysOn => trace::Sampler::AlwaysOn,
+            Sampler::AlwaysOff => trace::Sampler::AlwaysOff,
+            Sampler::TraceIdRatio(ratio) => trace::Sampler::TraceIdRatioBased(*ratio),
+            Sampler::ParentBased(root) => trace::Sampler::ParentBased(Box::new(root.to_sdk())),
+            Sampler::RateLimited(per_second) => {
+                trace::Sampler::ParentBased(Box::new(RateLimitingSampler::new(*per_second)))
+            }
+        }
+    }
+}
+
+/// Local tail sampling of failed traces, enabled by
+/// [`TelemetryConfig::tail_sampling`](crate::TelemetryConfig::tail_sampling).
+///
+/// With the `serde` feature, missing fields take their default values and `window` is written
+/// as a string such as `"10s"`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
+pub struct TailSampling {
+    /// How long the spans of a trace that was not sampled are kept after one of them ended.
+    ///
+    /// Spans of traces with no new span ending within the window are dropped, so a failure
+    /// reported later only exports the spans that ended since. Defaults to 10 seconds.
+    #[cfg_attr(feature = "serde", serde(with = "crate::duration_serde"))]
+    pub window: Duration,
+    /// Maximum number of spans buffered across all traces.
+    ///
+    /// Further spans of traces that were not sampled are dropped until buffered traces fail or
+    /// expire. Defaults to 10000.
+    pub max_buffered_spans: usize,
+}
+
+impl Default for TailSampling {
+    fn default() -> Self {
+        Self {
+            window: Duration::from_secs(10),
+            max_buffered_spans: 10_000,
+        }
+    }
+}
+
+/// Sampler recording the spans `0` drops, so tail sampling can still export them.
+///
+/// The spans are not marked as sampled, so they are neither exported nor propagated as sampled
+/// unless their trace fails.
+#[derive(Clone, Debug)]
+pub(crate) struct RecordUnsampled(pub(crate) Box<dyn ShouldSample>);
+
+impl ShouldSample for RecordUnsampled {
+    fn should_sample(
+        &self,
+        parent_context: Option<&Context>,
+        trace_id: TraceId,
+        name: &str,
+        span_kind: &SpanKind,
+        attributes: &[KeyValue],
+        links: &[Link],
+    ) -> SamplingResult {
+        let mut result =
+            self.0
+                .should_sample(parent_context, trace_id, name, span_kind, attributes, links);
+        if result.decision == SamplingDecision::Drop {
+            result.decision = SamplingDecision::RecordOnly;
+        }
+        result
+    }
+}
+
+/// Marker stored in the parent context of spans created under [`force_sample`].
+#[derive(Clone, Copy, Debug)]
+struct ForcedSampling;
+
+/// Sampler installed by `init`, sampling spans created under [`force_sample`].
+///
+/// Other spans are sampled by the configured sampler.
+#[derive(Clone, Debug)]
+pub(crate) struct ForceableSampler(pub(crate) Box<dyn ShouldSample>);
+
+impl ShouldSample for ForceableSampler {
+    fn should_sample(
+        &self,
+        parent_context: Option<&Context>,
+        trace_id: TraceId,
+        name: &str,
+        span_kind: &SpanKind,
+        attributes: &[KeyValue],
+        links: &[Link],
+    ) -> SamplingResult {
+        match parent_context {
+            Some(cx) if cx.get::<ForcedSampling>().is_some() => SamplingResult {
+                decision: SamplingDecision::RecordAndSample,
+                attributes: Vec::new(),
+                trace_state: cx.span().span_context().trace_state().clone(),
+            },
+            _ => self
+                .0
+                .should_sample(parent_context, trace_id, name, span_kind, attributes, links),
+        }
+    }
+}
+
+/// Guard returned by [`force_sample`]. Sampling returns to normal when it is dropped.
+#[must_use = "sampling is only forced while the guard is alive"]
+#[derive(Debug)]
+pub struct ForceSampleGuard {
+    span: Span,
+    previous: Option<Context>,
+}
+
+/// Samples the current span and every span created under it until the guard is dropped.
+///
+/// Meant for operations, such as an admin action during an incident, whose traces must be
+/// recorded whatever the configured sampler decides. Only spans that are children of the
+/// current span are affected, so work handed to other tasks without the current span is
+/// sampled as usual. The current span itself stays sampled after the guard is dropped, so
+/// with a parent-based sampler its later children are sampled as well.
+///
+/// Does nothing outside of a span or without the OpenTelemetry layer installed by `init`.
+pub fn force_sample() -> ForceSampleGuard {
+    let span = Span::current();
+    let previous = with_otel_data(&span, sample_with_children);
+    ForceSampleGuard { span, previous }
+}
+
+/// Creates a sampled root span covering the application's startup.
+///
+/// The span is named `startup` and, like spans under [`force_sample`], it and every span
+/// created under it are sampled whatever the configured sampler decides, so slow migrations or
+/// cache warmups are always traced. Create it after `init`, enter it while initializing and
+/// drop it once the application is ready to serve:
+///
+/// ```rust,ignore
+/// let _telemetry = telemetry_config.init().await?;
+/// let startup = tembo_telemetry::startup_span().entered();
+/// run_migrations(&pool).await?;
+/// warm_cache(&pool).await?;
+/// drop(startup);
+///
+/// HttpServer::new(move || App::new()).bind(("0.0.0.0", 8080))?.run().await
+/// ```
+///
+/// Holding the entered span across `.await` points is fine in `main` before the server
+/// starts, since nothing else runs on that thread yet. Work spawned onto other tasks does not
+/// inherit it and should be instrumented with a clone of the span instead.
+pub fn startup_span() -> Span {
+    let span = tracing::info_span!(parent: None, "startup");
+    with_otel_data(&span, sample_with_children);
+    span
+}
+
+/// Samples the span owning `data` and marks its children as sampled.
+///
+/// Returns the previous parent context of the span, which restores normal sampling of later
+/// children when set back.
+fn sample_with_children(data: &mut OtelData) -> Context {
+    let parent_cx = data.parent_cx.clone();
+    let trace_state = match &data.builder.sampling_result {
+        Some(result) => result.trace_state.clone(),
+        None => parent_cx.span().span_context().trace_state().clone(),
+    };
+    data.builder.sampling_result = Some(SamplingResult {
+        decision: SamplingDecision::RecordAndSample,
+        attributes: Vec::new(),
+        trace_state,
+    });
+    // Children derive their parent context from this one when they are created.
+    data.parent_cx = parent_cx.with_value(ForcedSampling);
+    parent_cx
+}
+
+impl Drop for ForceSampleGuard {
+    fn drop(&mut self) {
+        if let Some(previous) = self.previous.take() {
+            with_otel_data(&self.span, |data| data.parent_cx = previous);
+        }
+    }
+}
+
+/// Per-layer filter dropping events below `WARN` in traces that are not sampled.
+///
+/// The trace of an event is the one of its span. When the span was not sampled yet, the
+/// sampler runs with `0` as the OpenTelemetry layer would, so the decision is the one the
+/// trace gets. Events outside any span, or in spans without OpenTelemetry data, are kept.
+pub(crate) struct SampledLogsFilter(pub(crate) trace::Tracer);
+
+impl<S> Filter<S> for SampledLogsFilter
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn enabled(&self, _meta: &Metadata<'_>, _cx: &layer::Context<'_, S>) -> bool {
+        true
+    }
+
+    fn event_enabled(&self, event: &Event<'_>, cx: &layer::Context<'_, S>) -> bool {
+        if *event.metadata().level() <= Level::WARN {
+            return true;
+        }
+        let Some(span) = cx.event_span(event) else {
+            return true;
+        };
+        let mut extensions = span.extensions_mut();
+        match extensions.get_mut::<OtelData>() {
+            Some(data) => self
+                .0
+                .sampled_context(data)
+                .span()
+                .span_context()
+                .is_sampled(),
+            None => true,
+        }
+    }
+}
+
+/// Runs `f` on the OpenTelemetry data of `span`, if it has any.
+pub(crate) fn with_otel_data<T>(span: &Span, f: impl FnOnce(&mut OtelData) -> T) -> Option<T> {
+    span.with_subscriber(|(id, dispatch)| {
+        let registry = dispatch.downcast_ref::<Registry>()?;
+        let span = registry.span(id)?;
+        let mut extensions = span.extensions_mut();
+        extensions.get_mut::<OtelData>().map(f)
+    })
+    .flatten()
+}
+
+/// Token bucket sampler backing [`Sampler::RateLimited`].
+///
+/// Clones share the same bucket, since the SDK clones samplers for each tracer.
+#[derive(Clone, Debug)]
+struct RateLimitingSampler {
+    per_second: f64,
+    bucket: Arc<Mutex<Bucket>>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl RateLimitingSampler {
+    fn new(per_second: f64) -> Self {
+        let per_second = per_second.max(0.0);
+        // A zero rate never samples, so its bucket starts empty.
+        let tokens = if per_second > 0.0 {
+            Self::capacity(per_second)
+        } else {
+            0.0
+        };
+        Self {
+            per_second,
+            bucket: Arc::new(Mutex::new(Bucket {
+                tokens,
+                refilled_at: Instant::now(),
+            })),
+        }
+    }
+
+    /// Returns the bucket size: one second of tokens, and at least one.
+    fn capacity(per_second: f64) -> f64 {
+        per_second.max(1.0)
+    }
+
+    /// Takes a token if one is available at `now`.
+    fn try_acquire(&self, now: Instant) -> bool {
+        let mut bucket = self.bucket.lock().unwrap_or_else(|err| err.into_inner());
+        let elapsed = now
+            .saturating_duration_since(bucket.refilled_at)
+            .as_secs_f64();
+        bucket.tokens =
+            (bucket.tokens + elapsed * self.per_second).min(Self::capacity(self.per_second));
+        bucket.refilled_at = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+impl ShouldSample for RateLimitingSampler {
+    fn should_sample(
+        &self,
+        parent_context: Option<&Context>,
+        _trace_id: TraceId,
+        _name: &str,
+        _span_kind: &SpanKind,
+        _attributes: &[KeyValue],
+        _links: &[Link],
+    ) -> SamplingResult {
+        let decision = if self.try_acquire(Instant::now()) {
+            SamplingDecision::RecordAndSample
+        } else {
+            SamplingDecision::Drop
+        };
+        SamplingResult {
+            decision,
+            attributes: Vec::new(),
+            trace_state: parent_context
+                .map(|cx| cx.span().span_context().trace_state().clone())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sampler_to_sdk() {
+        assert!(matches!(
+            Sampler::default().to_sdk(),
+            trace::Sampler::ParentBased(_)
+        ));
+        assert!(matches!(
+            Sampler::AlwaysOn.to_sdk(),
+            trace::Sampler::AlwaysOn
+        ));
+        assert!(matches!(
+            Sampler::AlwaysOff.to_sdk(),
+            trace::Sampler::AlwaysOff
+        ));
+        assert!(matches!(
+            Sampler::TraceIdRatio(0.5).to_sdk(),
+            trace::Sampler::TraceIdRatioBased(ratio) if ratio == 0.5
+        ));
+        assert!(matches!(
+            Sampler::ParentBased(Box::new(Sampler::AlwaysOn)).to_sdk(),
+            trace::Sampler::ParentBased(_)
+        ));
+        assert!(matches!(
+            Sampler::RateLimited(10.0).to_sdk(),
+            trace::Sampler::ParentBased(_)
+        ));
+    }
+
+    #[test]
+    fn test_default_sampler_honors_remote_parent() {
+        use opentelemetry::trace::{SpanContext, TraceFlags};
+
+        let sample = |flags| {
+            let parent = SpanContext::new(
+                TraceId::from_bytes(1u128.to_be_bytes()),
+                opentelemetry::trace::SpanId::from_bytes(1u64.to_be_bytes()),
+                flags,
+                true,
+                Default::default(),
+            );
+            let cx = Context::new().with_remote_span_context(parent);
+            Sampler::default()
+                .to_sdk()
+                .should_sample(
+                    Some(&cx),
+                    TraceId::from_bytes(1u128.to_be_bytes()),
+                    "request",
+                    &SpanKind::Server,
+                    &[],
+                    &[],
+                )
+                .decision
+        };
+        assert_eq!(
+            sample(TraceFlags::SAMPLED),
+            SamplingDecision::RecordAndSample
+        );
+        assert_eq!(sample(TraceFlags::default()), SamplingDecision::Drop);
+
+        let root = Sampler::default().to_sdk().should_sample(
+            None,
+            TraceId::from_bytes(2u128.to_be_bytes()),
+            "request",
+            &SpanKind::Server,
+            &[],
+            &[],
+        );
+        assert_eq!(root.decision, SamplingDecision::RecordAndSample);
+    }
+
+    #[test]
+    fn test_force_sample() {
+        use crate::processors::tests::collecting_subscriber_with;
+
+        let (collector, subscriber) = collecting_subscriber_with(
+            trace::config().with_sampler(ForceableSampler(Box::new(Sampler::AlwaysOff.to_sdk()))),
+        );
+
+        tracing::subscriber::with_default(subscriber, || {
+            drop(force_sample());
+            tracing::info_span!("request").in_scope(|| {
+                tracing::info_span!("before").in_scope(|| {});
+                {
+                    let _forced = force_sample();
+                    tracing::info_span!("forced")
+                        .in_scope(|| tracing::info_span!("nested").in_scope(|| {}));
+                }
+                tracing::info_span!("after").in_scope(|| {});
+            });
+        });
+
+        let spans = collector.0.lock().unwrap();
+        let names: Vec<_> = spans.iter().map(|span| span.name.to_string()).collect();
+        assert_eq!(names, ["nested", "forced", "request"]);
+        assert!(spans
+            .iter()
+            .all(|span| span.span_context.trace_id() == spans[2].span_context.trace_id()));
+    }
+
+    #[test]
+    fn test_sampled_logs_filter() {
+        use crate::format::tests::CapturedWriter;
+        use opentelemetry::trace::TracerProvider as _;
+        use tracing_subscriber::{fmt, layer::SubscriberExt, Layer as _};
+
+        let provider = trace::TracerProvider::builder()
+            .with_config(
+                trace::config()
+                    .with_sampler(ForceableSampler(Box::new(Sampler::AlwaysOff.to_sdk()))),
+            )
+            .build();
+        let tracer = provider.tracer("test");
+        let writer = CapturedWriter::default();
+        let output = writer.clone();
+        let subscriber = Registry::default()
+            .with(tracing_opentelemetry::layer().with_tracer(tracer.clone()))
+            .with(
+                fmt::layer()
+                    .without_time()
+                    .with_ansi(false)
+                    .with_writer(move || writer.clone())
+                    .with_filter(SampledLogsFilter(tracer)),
+            );
+
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!("outside any span");
+            tracing::info_span!("unsampled").in_scope(|| {
+                tracing::info!("dropped");
+                tracing::info_span!("child").in_scope(|| tracing::debug!("dropped too"));
+                tracing::warn!("unsampled warning");
+                let _forced = force_sample();
+                tracing::info_span!("sampled").in_scope(|| tracing::info!("sampled info"));
+            });
+        });
+
+        let lines = output.lines();
+        assert_eq!(lines.len(), 3, "{lines:?}");
+        assert!(lines[0].ends_with("outside any span"), "{lines:?}");
+        assert!(lines[1].ends_with("unsampled warning"), "{lines:?}");
+        assert!(lines[2].ends_with("sampled info"), "{lines:?}");
+    }
+
+    #[test]
+    fn test_startup_span() {
+        use crate::processors::tests::collecting_subscriber_with;
+
+        let (collector, subscriber) = collecting_subscriber_with(
+            trace::config().with_sampler(ForceableSampler(Box::new(Sampler::AlwaysOff.to_sdk()))),
+        );
+
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info_span!("request").in_scope(|| {
+                let startup = startup_span().entered();
+                tracing::info_span!("migrations").in_scope(|| {});
+                drop(startup);
+            });
+        });
+
+        let spans = collector.0.lock().unwrap();
+        let names: Vec<_> = spans.iter().map(|span| span.name.to_string()).collect();
+        assert_eq!(names, ["migrations", "startup"]);
+        // The startup span is a root span even when created inside another span.
+        assert_eq!(
+            spans[1].parent_span_id,
+            opentelemetry::trace::SpanId::INVALID
+        );
+        assert_eq!(
+            spans[0].span_context.trace_id(),
+            spans[1].span_context.trace_id()
+        );
+    }
+
+    #[test]
+    fn test_rate_limiting_sampler() {
+        let sampler = RateLimitingSampler::new(2.0);
+        let start = sampler.bucket.lock().unwrap().refilled_at;
+        assert!(sampler.try_acquire(start));
+        assert!(sampler.try_acquire(start));
+        assert!(!sampler.try_acquire(start));
+
+        // Tokens refill at the configured rate, up to one second worth.
+        let later = start + std::time::Duration::from_millis(500);
+        assert!(sampler.try_acquire(later));
+        assert!(!sampler.try_acquire(later));
+        let much_later = start + std::time::Duration::from_secs(10);
+        assert!(sampler.clone().try_acquire(much_later));
+        assert!(sampler.try_acquire(much_later));
+        assert!(!sampler.try_acquire(much_later));
+
+        let disabled = RateLimitingSampler::new(0.0);
+        assert!(!disabled.try_acquire(start + std::time::Duration::from_secs(10)));
+    }
+
+    #[test]
+    fn test_rate_limited_root_spans() {
+        use opentelemetry::trace::{Tracer as _, TracerProvider as _};
+
+        let provider = trace::TracerProvider::builder()
+            .with_config(trace::config().with_sampler(Sampler::RateLimited(1.0).to_sdk()))
+            .build();
+        let tracer = provider.tracer("test");
+        let mut sampled = Vec::new();
+        for _ in 0..3 {
+            tracer.in_span("root", |cx| {
+                let root = cx.span().span_context().is_sampled();
+                let child = tracer.in_span("child", |cx| cx.span().span_context().is_sampled());
+                sampled.push((root, child));
+            });
+        }
+        assert_eq!(sampled, vec![(true, true), (false, false), (false, false)]);
+    }
+}
@@ -0,0 +1,105 @@
+//! Log level changes triggered by `SIGUSR1` and `SIGUSR2`.
+
+use std::{fmt, io, sync::Arc};
+use tokio::signal::unix::{signal, SignalKind};
+use tracing_subscriber::{reload, EnvFilter};
+
+/// Directives installed by `SIGUSR1`.
+pub(crate) const DEBUG_DIRECTIVES: &str = "debug";
+
+/// Replaces the log filter installed by `init`.
+#[derive(Clone)]
+pub(crate) struct LogFilterHandle {
+    /// Directives the filter was built from, restored by `SIGUSR2`.
+    initial: String,
+    reload: Arc<dyn Fn(EnvFilter) -> Result<(), reload::Error> + Send + Sync>,
+}
+
+impl fmt::Debug for LogFilterHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LogFilterHandle")
+            .field("initial", &self.initial)
+            .finish_non_exhaustive()
+    }
+}
+
+impl LogFilterHandle {
+    /// Wraps the reload `handle` of a filter built from the `initial` directives.
+    pub(crate) fn new<S: 'static>(initial: String, handle: reload::Handle<EnvFilter, S>) -> Self {
+        Self {
+            initial,
+            reload: Arc::new(move |filter| handle.reload(filter)),
+        }
+    }
+
+    /// Installs a filter built from `directives`, reporting invalid directives and reload
+    /// failures as a warning.
+    fn set(&self, directives: &str, signal: &str) {
+        let result = EnvFilter::try_new(directives)
+            .map_err(|err| err.to_string())
+            .and_then(|filter| (self.reload)(filter).map_err(|err| err.to_string()));
+        match result {
+            Ok(()) => tracing::info!(filter = directives, signal, "log filter changed"),
+            Err(err) => tracing::warn!(signal, "failed to change the log filter: {err}"),
+        }
+    }
+
+    /// Spawns tasks switching the filter to `debug` on `SIGUSR1` and back on `SIGUSR2`.
+    pub(crate) fn listen(&self) -> io::Result<()> {
+        if tokio::runtime::Handle::try_current().is_err() {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                "handling log level signals requires a Tokio runtime",
+            ));
+        }
+        let mut raise = signal(SignalKind::user_defined1())?;
+        let mut reset = signal(SignalKind::user_defined2())?;
+        let handle = self.clone();
+        tokio::spawn(async move {
+            while raise.recv().await.is_some() {
+                handle.set(DEBUG_DIRECTIVES, "SIGUSR1");
+            }
+        });
+        let handle = self.clone();
+        tokio::spawn(async move {
+            while reset.recv().await.is_some() {
+                handle.set(&handle.initial, "SIGUSR2");
+            }
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tracing_subscriber::{layer::SubscriberExt, Registry};
+
+    #[tokio::test]
+    async fn test_signals_change_the_filter() {
+        let (filter, handle) = reload::Layer::new(EnvFilter::new("info"));
+        let subscriber = Registry::default().with(filter);
+        let _default = tracing::subscriber::set_default(subscriber);
+        LogFilterHandle::new("info".to_string(), handle.clone())
+            .listen()
+            .unwrap();
+
+        let current = || handle.with_current(|filter| filter.to_string()).unwrap();
+        let wait_for = |expected: &'static str| async move {
+            for _ in 0..100 {
+                if current() == expected {
+                    return;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            panic!("filter is `{}`, expected `{expected}`", current());
+        };
+
+        // SAFETY: `raise` has no preconditions, the signals are handled by the spawned tasks.
+        unsafe { libc::raise(libc::SIGUSR1) };
+        wait_for("debug").await;
+        unsafe { libc::raise(libc::SIGUSR2) };
+        wait_for("info").await;
+    }
+}
@@ -0,0 +1,115 @@
+//! Log events reporting the duration of each span when it closes.
+
+use crate::format::write_pair;
+use std::{fmt, time::Instant};
+use tracing::{
+    field::{Field, Visit},
+    span, Subscriber,
+};
+use tracing_subscriber::{layer::Context, registry::LookupSpan, Layer};
+
+/// Start time and formatted fields of a span, stored in its extensions.
+struct SpanTiming {
+    start: Instant,
+    fields: String,
+}
+
+/// Formats span fields as space-separated `key=value` pairs.
+struct FieldsVisitor<'a>(&'a mut String);
+
+impl Visit for FieldsVisitor<'_> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        let _ = write_pair(self.0, field.name(), value);
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.record_str(field, &format!("{value:?}"));
+    }
+}
+
+/// Layer emitting a `span closed` event with the duration of each span it sees close.
+///
+/// The event is a root event, so it is not attached to exported traces. Its schema is fixed:
+/// `span.name`, `span.target`, `span.fields` with the span's fields as `key=value` pairs and
+/// `duration_ms`, the milliseconds between the creation and the close of the span.
+pub(crate) struct SpanDurationLayer;
+
+impl<S> Layer<S> for SpanDurationLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let mut fields = String::new();
+        attrs.record(&mut FieldsVisitor(&mut fields));
+        span.extensions_mut().insert(SpanTiming {
+            start: Instant::now(),
+            fields,
+        });
+    }
+
+    fn on_record(&self, id: &span::Id, values: &span::Record<'_>, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let mut extensions = span.extensions_mut();
+        if let Some(timing) = extensions.get_mut::<SpanTiming>() {
+            values.record(&mut FieldsVisitor(&mut timing.fields));
+        }
+    }
+
+    fn on_close(&self, id: span::Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(&id) else {
+            return;
+        };
+        let Some(timing) = span.extensions_mut().remove::<SpanTiming>() else {
+            return;
+        };
+        let duration_ms = timing.start.elapsed().as_secs_f64() * 1000.0;
+        let metadata = span.metadata();
+        tracing::info!(
+            parent: None,
+            span.name = metadata.name(),
+            span.target = metadata.target(),
+            span.fields = timing.fields.trim_start(),
+            duration_ms,
+            "span closed"
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::tests::CapturedWriter;
+    use tracing_subscriber::{fmt, layer::SubscriberExt, Registry};
+
+    #[test]
+    fn test_span_durations() {
+        let writer = CapturedWriter::default();
+        let output = writer.clone();
+        let subscriber = Registry::default().with(SpanDurationLayer).with(
+            fmt::layer()
+                .json()
+                .flatten_event(true)
+                .with_writer(move || writer.clone()),
+        );
+        tracing::subscriber::with_default(subscriber, || {
+            let span =
+                tracing::info_span!("charge", invoice_id = 42, status = tracing::field::Empty);
+            span.in_scope(|| std::thread::sleep(std::time::Duration::from_millis(5)));
+            span.record("status", "paid");
+        });
+
+        let lines = output.lines();
+        assert_eq!(lines.len(), 1, "{lines:?}");
+        let event: serde_json::Value = serde_json::from_str(&lines[0]).unwrap();
+        assert_eq!(event["message"], "span closed");
+        assert_eq!(event["span.name"], "charge");
+        assert_eq!(event["span.fields"], "invoice_id=42 status=paid");
+        assert!(event["duration_ms"].as_f64().unwrap() >= 5.0, "{event}");
+        assert!(event.get("span").is_none(), "{event}");
+    }
+}
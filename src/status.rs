@@ -0,0 +1,84 @@
+//! Helpers setting the error status of spans with a readable description.
+//!
+//! Backends show the status description in the error banner of a trace, so failed spans should
+//! carry one. Descriptions are truncated to [`TelemetryConfig::max_status_message_length`].
+//!
+//! [`TelemetryConfig::max_status_message_length`]: crate::TelemetryConfig::max_status_message_length
+
+use crate::{processors, sampling::with_otel_data};
+use opentelemetry::trace::Status;
+use std::{
+    fmt,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+use tracing::Span;
+
+/// Default for [`TelemetryConfig::max_status_message_length`](crate::TelemetryConfig::max_status_message_length).
+pub(crate) const DEFAULT_MAX_STATUS_MESSAGE_LENGTH: usize = 1024;
+
+/// Maximum length in bytes of status descriptions, `usize::MAX` when unlimited.
+static MAX_STATUS_MESSAGE_LENGTH: AtomicUsize = AtomicUsize::new(DEFAULT_MAX_STATUS_MESSAGE_LENGTH);
+
+/// Sets the maximum length of status descriptions, set by `init`.
+pub(crate) fn set_max_status_message_length(max_length: Option<usize>) {
+    MAX_STATUS_MESSAGE_LENGTH.store(max_length.unwrap_or(usize::MAX), Ordering::Relaxed);
+}
+
+/// Records `error` on `span` and marks the span as failed.
+///
+/// Emits an `ERROR` event with an `error` field in `span`, which the OpenTelemetry layer turns
+/// into an `exception` event, and uses the `Display` output of `error` as the status
+/// description. Recording `otel.status_code` on the span afterwards replaces the description.
+///
+/// # Arguments
+///
+/// * `span` - The span to mark as failed.
+/// * `error` - The error that made the operation fail.
+pub fn record_error(span: &Span, error: &dyn fmt::Display) {
+    tracing::error!(parent: span, error = %error);
+    set_error_status(span, &error.to_string());
+}
+
+/// Marks `span` as failed with the status description `message`.
+pub(crate) fn set_error_status(span: &Span, message: &str) {
+    let status = error_status(message, MAX_STATUS_MESSAGE_LENGTH.load(Ordering::Relaxed));
+    with_otel_data(span, |data| data.builder.status = status);
+}
+
+/// Builds an error status described by `message`, truncated to `max_length` bytes.
+fn error_status(message: &str, max_length: usize) -> Status {
+    match processors::truncate(message, max_length) {
+        Some(truncated) => Status::error(truncated),
+        None => Status::error(message.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processors::tests::collecting_subscriber;
+
+    #[test]
+    fn test_record_error_sets_description() {
+        let (collector, subscriber) = collecting_subscriber();
+
+        tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!("sync");
+            let error = std::io::Error::new(std::io::ErrorKind::TimedOut, "upstream timed out");
+            record_error(&span, &error);
+        });
+
+        let spans = collector.0.lock().unwrap();
+        assert_eq!(spans[0].status, Status::error("upstream timed out"));
+        assert_eq!(spans[0].events.iter().next().unwrap().name, "exception");
+    }
+
+    #[test]
+    fn test_error_status_truncated() {
+        assert_eq!(error_status("short", 8), Status::error("short"));
+        assert_eq!(
+            error_status("connection refused", 10),
+            Status::error("connection...[truncated]")
+        );
+    }
+}
@@ -0,0 +1,299 @@
+//! Log output to the local syslog daemon.
+
+use crate::TelemetryError;
+use std::{fmt, path::PathBuf};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::{
+    fmt::{format::Writer, FmtContext, FormatEvent, FormatFields},
+    registry::LookupSpan,
+    Layer,
+};
+
+/// Default for [`SyslogConfig::socket_path`], the socket of the local syslog daemon.
+pub const DEFAULT_SYSLOG_SOCKET: &str = "/dev/log";
+
+/// Configuration of the syslog output enabled by [`TelemetryConfig::syslog`].
+///
+/// [`TelemetryConfig::syslog`]: crate::TelemetryConfig::syslog
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
+pub struct SyslogConfig {
+    /// Facility messages are logged with. Defaults to [`SyslogFacility::Daemon`].
+    pub facility: SyslogFacility,
+    /// Datagram socket of the syslog daemon. Defaults to [`DEFAULT_SYSLOG_SOCKET`].
+    pub socket_path: PathBuf,
+}
+
+impl Default for SyslogConfig {
+    fn default() -> Self {
+        Self {
+            facility: SyslogFacility::default(),
+            socket_path: PathBuf::from(DEFAULT_SYSLOG_SOCKET),
+        }
+    }
+}
+
+/// Syslog facility, telling the daemon which kind of program logged a message.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum SyslogFacility {
+    /// Generic user-level messages.
+    User,
+    /// System daemons, the default.
+    #[default]
+    Daemon,
+    /// Reserved for local use.
+    Local0,
+    /// Reserved for local use.
+    Local1,
+    /// Reserved for local use.
+    Local2,
+    /// Reserved for local use.
+    Local3,
+    /// Reserved for local use.
+    Local4,
+    /// Reserved for local use.
+    Local5,
+    /// Reserved for local use.
+    Local6,
+    /// Reserved for local use.
+    Local7,
+}
+
+impl SyslogFacility {
+    /// Numeric code of the facility, as defined by RFC 5424.
+    fn code(self) -> u8 {
+        match self {
+            SyslogFacility::User => 1,
+            SyslogFacility::Daemon => 3,
+            SyslogFacility::Local0 => 16,
+            SyslogFacility::Local1 => 17,
+            SyslogFacility::Local2 => 18,
+            SyslogFacility::Local3 => 19,
+            SyslogFacility::Local4 => 20,
+            SyslogFacility::Local5 => 21,
+            SyslogFacility::Local6 => 22,
+            SyslogFacility::Local7 => 23,
+        }
+    }
+}
+
+/// Maps a tracing level to a syslog severity.
+///
+/// Matches `tracing-journald`, so `DEBUG` and `TRACE` both map to `debug` and stay out of the
+/// usual `*.info` selectors.
+fn severity(level: &Level) -> u8 {
+    match *level {
+        Level::ERROR => 3,
+        Level::WARN => 4,
+        Level::INFO => 6,
+        Level::DEBUG | Level::TRACE => 7,
+    }
+}
+
+/// Priority value prefixing a message, combining `facility` and the severity of `level`.
+fn priority(facility: SyslogFacility, level: &Level) -> u8 {
+    facility.code() * 8 + severity(level)
+}
+
+/// Event formatter writing the syslog header `<PRI>service_name[pid]: ` before the message.
+///
+/// The daemon adds the timestamp and host name, so the message is formatted without a
+/// timestamp. The level is carried by the priority instead of the message.
+struct SyslogFormat {
+    facility: SyslogFacility,
+    tag: String,
+    inner: tracing_subscriber::fmt::format::Format<tracing_subscriber::fmt::format::Full, ()>,
+}
+
+impl<S, N> FormatEvent<S, N> for SyslogFormat
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        let priority = priority(self.facility, event.metadata().level());
+        write!(writer, "<{priority}>{}[{}]: ", self.tag, std::process::id())?;
+        self.inner.format_event(ctx, writer, event)
+    }
+}
+
+/// Builds the layer sending each event to the syslog daemon as one datagram.
+///
+/// Fails when the socket cannot be connected, e.g. because no daemon is listening.
+pub(crate) fn layer<S>(
+    config: &SyslogConfig,
+    tag: &str,
+) -> Result<Box<dyn Layer<S> + Send + Sync>, TelemetryError>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    let socket = socket::SyslogSocket::connect(&config.socket_path).map_err(|source| {
+        TelemetryError::Syslog {
+            path: config.socket_path.clone(),
+            source,
+        }
+    })?;
+    let format = SyslogFormat {
+        facility: config.facility,
+        tag: tag.to_string(),
+        inner: tracing_subscriber::fmt::format()
+            .without_time()
+            .with_level(false)
+            .with_ansi(false),
+    };
+    Ok(tracing_subscriber::fmt::layer()
+        .with_ansi(false)
+        .event_format(format)
+        .with_writer(socket)
+        .boxed())
+}
+
+#[cfg(unix)]
+mod socket {
+    use std::{
+        io,
+        os::unix::net::UnixDatagram,
+        path::{Path, PathBuf},
+        sync::{Arc, Mutex},
+    };
+    use tracing_subscriber::fmt::MakeWriter;
+
+    /// Connected syslog socket, reconnected when the daemon restarts.
+    #[derive(Clone)]
+    pub(super) struct SyslogSocket {
+        path: PathBuf,
+        socket: Arc<Mutex<UnixDatagram>>,
+    }
+
+    impl SyslogSocket {
+        pub(super) fn connect(path: &Path) -> io::Result<Self> {
+            Ok(Self {
+                path: path.to_path_buf(),
+                socket: Arc::new(Mutex::new(connect(path)?)),
+            })
+        }
+
+        /// Sends `message` as one datagram, reconnecting once if the send fails.
+        fn send(&self, message: &[u8]) -> io::Result<()> {
+            let mut socket = self.socket.lock().unwrap_or_else(|err| err.into_inner());
+            if socket.send(message).is_ok() {
+                return Ok(());
+            }
+            *socket = connect(&self.path)?;
+            socket.send(message).map(drop)
+        }
+    }
+
+    fn connect(path: &Path) -> io::Result<UnixDatagram> {
+        let socket = UnixDatagram::unbound()?;
+        socket.connect(path)?;
+        Ok(socket)
+    }
+
+    impl<'a> MakeWriter<'a> for SyslogSocket {
+        type Writer = SyslogWriter<'a>;
+
+        fn make_writer(&'a self) -> Self::Writer {
+            SyslogWriter(self)
+        }
+    }
+
+    /// Writer sending each formatted event, without its trailing newline, as a datagram.
+    pub(super) struct SyslogWriter<'a>(&'a SyslogSocket);
+
+    impl io::Write for SyslogWriter<'_> {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.send(buf.strip_suffix(b"\n").unwrap_or(buf))?;
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+}
+
+/// Syslog sockets are not supported on this platform.
+#[cfg(not(unix))]
+mod socket {
+    use std::{io, path::Path};
+
+    pub(super) struct SyslogSocket;
+
+    impl SyslogSocket {
+        pub(super) fn connect(_path: &Path) -> io::Result<Self> {
+            Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "syslog sockets are not supported on this platform",
+            ))
+        }
+    }
+
+    impl tracing_subscriber::fmt::MakeWriter<'_> for SyslogSocket {
+        type Writer = io::Sink;
+
+        fn make_writer(&self) -> Self::Writer {
+            io::sink()
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::net::UnixDatagram;
+    use tracing_subscriber::{layer::SubscriberExt, Registry};
+
+    #[test]
+    fn test_priority() {
+        assert_eq!(priority(SyslogFacility::Daemon, &Level::ERROR), 27);
+        assert_eq!(priority(SyslogFacility::Daemon, &Level::INFO), 30);
+        assert_eq!(priority(SyslogFacility::User, &Level::WARN), 12);
+        assert_eq!(priority(SyslogFacility::Local7, &Level::DEBUG), 191);
+        assert_eq!(priority(SyslogFacility::Local7, &Level::TRACE), 191);
+    }
+
+    #[test]
+    fn test_events_sent_as_datagrams() {
+        let path = std::env::temp_dir().join(format!("tembo-syslog-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let daemon = UnixDatagram::bind(&path).unwrap();
+        let config = SyslogConfig {
+            facility: SyslogFacility::Local0,
+            socket_path: path.clone(),
+        };
+        let subscriber = Registry::default().with(layer(&config, "billing").unwrap());
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::warn!(invoice_id = 42, "payment retried");
+        });
+
+        let mut buf = [0; 1024];
+        let len = daemon.recv(&mut buf).unwrap();
+        let message = std::str::from_utf8(&buf[..len]).unwrap();
+        assert!(
+            message.starts_with(&format!("<132>billing[{}]: ", std::process::id())),
+            "{message}"
+        );
+        assert!(message.contains("payment retried"), "{message}");
+        assert!(message.contains("invoice_id=42"), "{message}");
+        assert!(!message.ends_with('\n'));
+        std::fs::remove_file(&path).unwrap();
+
+        let err = layer::<Registry>(&config, "billing").err().unwrap();
+        assert!(matches!(err, TelemetryError::Syslog { .. }));
+    }
+}
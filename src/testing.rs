@@ -0,0 +1,107 @@
+//! Deterministic tracing utilities for tests.
+//!
+//! Enabled by the `testing` feature. These helpers make sampling behavior reproducible so
+//! tests can assert that a given trace ID is or isn't sampled. They are not intended for
+//! production use: seeded ID generators produce predictable, colliding IDs across processes.
+
+use crate::{splitmix64, Sampler, SPLITMIX64_GAMMA};
+use opentelemetry::{
+    trace::{SamplingDecision, SpanId, SpanKind, TraceId},
+    Context,
+};
+use opentelemetry_sdk::trace::{IdGenerator, ShouldSample};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Returns whether `sampler` samples a root span with the given trace ID.
+///
+/// The decision is evaluated without a parent context, so `ParentBased` samplers delegate to
+/// their root sampler.
+///
+/// # Arguments
+///
+/// * `sampler` - The sampler to evaluate.
+/// * `trace_id` - The trace ID of the root span.
+pub fn is_sampled(sampler: &Sampler, trace_id: TraceId) -> bool {
+    let result = sampler.to_sdk().should_sample(
+        Some(&Context::new()),
+        trace_id,
+        "test",
+        &SpanKind::Internal,
+        &[],
+        &[],
+    );
+    result.decision == SamplingDecision::RecordAndSample
+}
+
+/// ID generator producing a reproducible sequence of IDs from a seed.
+///
+/// Install it with `opentelemetry_sdk::trace::config().with_id_generator(...)` in tests that
+/// build their own tracer provider, or with `TelemetryConfig::id_generator`, so every run
+/// produces the same trace IDs and therefore the same sampling decisions.
+#[derive(Debug)]
+pub struct SeededIdGenerator {
+    state: AtomicU64,
+}
+
+impl SeededIdGenerator {
+    /// Creates a generator starting from `seed`.
+    ///
+    /// # Arguments
+    ///
+    /// * `seed` - The seed for the ID sequence.
+    pub fn new(seed: u64) -> Self {
+        Self {
+            state: AtomicU64::new(seed),
+        }
+    }
+
+    /// Returns the next value of the SplitMix64 sequence.
+    fn next_u64(&self) -> u64 {
+        splitmix64(self.state.fetch_add(SPLITMIX64_GAMMA, Ordering::Relaxed))
+    }
+}
+
+impl IdGenerator for SeededIdGenerator {
+    fn new_trace_id(&self) -> TraceId {
+        let high = u128::from(self.next_u64()) << 64;
+        TraceId::from(high | u128::from(self.next_u64()))
+    }
+
+    fn new_span_id(&self) -> SpanId {
+        SpanId::from(self.next_u64())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ratio_sampling_is_deterministic() {
+        let sampler = Sampler::TraceIdRatio(0.5);
+        // The ratio sampler compares the lower 64 bits of the trace ID against the threshold.
+        let low = TraceId::from(1u128);
+        let high = TraceId::from(u128::from(u64::MAX));
+        assert!(is_sampled(&sampler, low));
+        assert!(!is_sampled(&sampler, high));
+        assert_eq!(is_sampled(&sampler, low), is_sampled(&sampler, low));
+        assert!(is_sampled(
+            &Sampler::ParentBased(Box::new(Sampler::AlwaysOn)),
+            high
+        ));
+    }
+
+    #[test]
+    fn test_seeded_id_generator_is_reproducible() {
+        let first = SeededIdGenerator::new(7);
+        let second = SeededIdGenerator::new(7);
+        for _ in 0..10 {
+            assert_eq!(first.new_trace_id(), second.new_trace_id());
+            assert_eq!(first.new_span_id(), second.new_span_id());
+        }
+        assert_ne!(
+            SeededIdGenerator::new(7).new_trace_id(),
+            SeededIdGenerator::new(8).new_trace_id()
+        );
+    }
+}
@@ -0,0 +1,102 @@
+//! Helpers recording spans and events at timestamps taken from external sources.
+//!
+//! The OpenTelemetry layer stamps spans and events with the wall-clock time at which they are
+//! entered, exited and recorded. When backfilling or replaying historical events, these helpers
+//! overwrite those times with the original ones, so exported traces show when things actually
+//! happened.
+//!
+//! Sampling is unaffected: the decision is made when the span is created, from its trace id and
+//! the configured sampler. `Sampler::RateLimited` counts spans as they are created, so replaying
+//! a backlog quickly can exhaust its budget even if the original events were spread out.
+
+use crate::sampling::with_otel_data;
+use opentelemetry::{trace::Event, KeyValue};
+use std::{borrow::Cow, time::SystemTime};
+use tracing::Span;
+
+/// Sets the start time of `span`, replacing the time at which it was created.
+///
+/// Does nothing without the OpenTelemetry layer installed by `init`.
+///
+/// # Arguments
+///
+/// * `span` - The span to update.
+/// * `timestamp` - When the replayed operation started.
+pub fn set_start_time(span: &Span, timestamp: SystemTime) {
+    with_otel_data(span, |data| data.builder.start_time = Some(timestamp));
+}
+
+/// Ends `span` at `timestamp` instead of the time it is closed.
+///
+/// The layer updates the end time every time a span is exited, so the span is consumed and
+/// must not be exited afterwards: drop any entered guard first, and do not enter clones of
+/// it once this is called. The span is closed once its last clone is dropped.
+///
+/// # Arguments
+///
+/// * `span` - The span to end.
+/// * `timestamp` - When the replayed operation ended.
+pub fn end_span_at(span: Span, timestamp: SystemTime) {
+    with_otel_data(&span, |data| data.builder.end_time = Some(timestamp));
+}
+
+/// Adds an event named `name` to `span` at `timestamp`.
+///
+/// Unlike events recorded with the `tracing` macros, the event is not written to the stdout
+/// logs, only exported with the span. Does nothing without the OpenTelemetry layer installed
+/// by `init`.
+///
+/// # Arguments
+///
+/// * `span` - The span to add the event to.
+/// * `name` - The event name.
+/// * `timestamp` - When the replayed event happened.
+/// * `attributes` - Attributes recorded on the event.
+pub fn add_event_at(
+    span: &Span,
+    name: impl Into<Cow<'static, str>>,
+    timestamp: SystemTime,
+    attributes: Vec<KeyValue>,
+) {
+    let event = Event::new(name, timestamp, attributes, 0);
+    with_otel_data(span, |data| {
+        data.builder.events.get_or_insert_with(Vec::new).push(event)
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processors::tests::collecting_subscriber;
+
+    use std::time::Duration;
+
+    #[test]
+    fn test_explicit_timestamps() {
+        let (collector, subscriber) = collecting_subscriber();
+        let started = SystemTime::UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+
+        tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!("replayed_order");
+            set_start_time(&span, started);
+            span.in_scope(|| tracing::info!("replaying"));
+            add_event_at(
+                &span,
+                "payment.captured",
+                started + Duration::from_secs(1),
+                vec![KeyValue::new("amount", 42)],
+            );
+            end_span_at(span, started + Duration::from_secs(2));
+        });
+
+        let spans = collector.0.lock().unwrap();
+        assert_eq!(spans.len(), 1);
+        assert_eq!(spans[0].start_time, started);
+        assert_eq!(spans[0].end_time, started + Duration::from_secs(2));
+        let events: Vec<_> = spans[0].events.iter().collect();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].name, "replaying");
+        assert_eq!(events[1].name, "payment.captured");
+        assert_eq!(events[1].timestamp, started + Duration::from_secs(1));
+    }
+}